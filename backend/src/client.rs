@@ -0,0 +1,57 @@
+use serde::Serialize;
+use serde_json::Value;
+use xi_core_lib::ViewId;
+
+use crate::edit::EditCommand;
+use crate::error::RpcError;
+use crate::rpc::{ Edit, Notification, Request };
+
+/// How messages get to xi-core and back, in-process or over a pipe.
+/// Works in raw json, `Client` is what turns the typed messages into it.
+pub trait Transport {
+    fn send_notification(&mut self, method: &str, params: Value);
+
+    /// Blocks until xi-core answers.
+    fn send_request(&mut self, method: &str, params: Value) -> Result<Value, RpcError>;
+
+    /// Runs a slice of idle work xi-core scheduled through the frontend.
+    fn handle_idle(&mut self, token: usize);
+}
+
+/// The typed way to talk to xi-core.
+pub struct Client {
+    transport: Box<dyn Transport>,
+}
+
+impl Client {
+    pub fn new(transport: Box<dyn Transport>) -> Self {
+        Client { transport }
+    }
+
+    pub fn notify<N: Notification>(&mut self, notification: &N) {
+        let params = to_params(notification);
+        self.transport.send_notification(N::METHOD, params);
+    }
+
+    pub fn request<R: Request>(&mut self, request: &R) -> Result<R::Response, RpcError> {
+        let params = to_params(request);
+        let response = self.transport.send_request(R::METHOD, params)?;
+        serde_json::from_value(response).map_err(|e| RpcError::bad_response(R::METHOD, e))
+    }
+
+    pub fn edit(&mut self, view_id: ViewId, command: EditCommand) {
+        self.notify(&Edit { view_id, command });
+    }
+
+    pub fn handle_idle(&mut self, token: usize) {
+        self.transport.handle_idle(token);
+    }
+}
+
+// the message types are all plain structs and enums, they can't fail to serialize
+fn to_params<T: Serialize>(message: &T) -> Value {
+    match serde_json::to_value(message) {
+        Ok(params) => params,
+        Err(e) => panic!("couldn't serialize message: {}", e),
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{ Deserialize, Serialize };
+
+/// A range of lines, first inclusive and last exclusive, sent as `[first, last]`.
+pub type LineRange = (i64, i64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Size {
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GestureType {
+    PointSelect,
+    ToggleSel,
+    RangeSelect,
+    LineSelect,
+    WordSelect,
+    MultiLineSelect,
+    MultiWordSelect,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FindQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+    pub chars: String,
+    pub case_sensitive: bool,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub whole_words: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where the mouse has been dragged to with the button held, extending the selection a
+/// gesture started. xi-core takes it as `[line, col, modifiers]`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(u64, u64, u64)", into = "(u64, u64, u64)")]
+pub struct MouseDrag {
+    pub line: u64,
+    pub col: u64,
+    /// The modifier keys held, as flags the way xi-core's clients send them.
+    pub modifiers: u64,
+}
+
+impl From<(u64, u64, u64)> for MouseDrag {
+    fn from((line, col, modifiers): (u64, u64, u64)) -> Self {
+        MouseDrag { line, col, modifiers }
+    }
+}
+
+impl From<MouseDrag> for (u64, u64, u64) {
+    fn from(drag: MouseDrag) -> Self {
+        (drag.line, drag.col, drag.modifiers)
+    }
+}
+
+/// Every edit method xi-core takes as a notification, sent inside an `edit` with the view it's for.
+///
+/// This is also what keymaps bind to: a method name on its own deserializes into the variants
+/// that don't need any params.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "method", content = "params")]
+pub enum EditCommand {
+    Insert { chars: String },
+    Paste { chars: String },
+    DeleteForward,
+    DeleteBackward,
+    DeleteWordForward,
+    DeleteWordBackward,
+    DeleteToEndOfParagraph,
+    DeleteToBeginningOfLine,
+    InsertNewline,
+    InsertTab,
+    MoveUp,
+    MoveUpAndModifySelection,
+    MoveDown,
+    MoveDownAndModifySelection,
+    MoveLeft,
+    MoveLeftAndModifySelection,
+    MoveRight,
+    MoveRightAndModifySelection,
+    MoveWordLeft,
+    MoveWordLeftAndModifySelection,
+    MoveWordRight,
+    MoveWordRightAndModifySelection,
+    MoveToBeginningOfParagraph,
+    MoveToBeginningOfParagraphAndModifySelection,
+    MoveToEndOfParagraph,
+    MoveToEndOfParagraphAndModifySelection,
+    MoveToLeftEndOfLine,
+    MoveToLeftEndOfLineAndModifySelection,
+    MoveToRightEndOfLine,
+    MoveToRightEndOfLineAndModifySelection,
+    MoveToBeginningOfDocument,
+    MoveToBeginningOfDocumentAndModifySelection,
+    MoveToEndOfDocument,
+    MoveToEndOfDocumentAndModifySelection,
+    ScrollPageUp,
+    PageUpAndModifySelection,
+    ScrollPageDown,
+    PageDownAndModifySelection,
+    SelectAll,
+    AddSelectionAbove,
+    AddSelectionBelow,
+    CollapseSelections,
+    SelectionIntoLines,
+    Scroll(LineRange),
+    Resize(Size),
+    GotoLine { line: u64 },
+    RequestLines(LineRange),
+    Yank,
+    Transpose,
+    Gesture { line: u64, col: u64, ty: GestureType },
+    Drag(MouseDrag),
+    Undo,
+    Redo,
+    Find { chars: String, case_sensitive: bool, #[serde(default)] regex: bool, #[serde(default)] whole_words: bool },
+    MultiFind { queries: Vec<FindQuery> },
+    FindNext { wrap_around: bool, allow_same: bool, modify_selection: String },
+    FindPrevious { wrap_around: bool, allow_same: bool, modify_selection: String },
+    FindAll,
+    HighlightFind { visible: bool },
+    SelectionForFind { case_sensitive: bool },
+    Replace { chars: String, preserve_case: bool },
+    ReplaceNext,
+    ReplaceAll,
+    SelectionForReplace,
+    RequestHover { request_id: usize, position: Option<Position> },
+    Uppercase,
+    Lowercase,
+    Capitalize,
+    Reindent,
+    Indent,
+    Outdent,
+    ToggleComment,
+    DuplicateLine,
+    IncreaseNumber,
+    DecreaseNumber,
+    ToggleRecording { recording_name: Option<String> },
+    PlayRecording { recording_name: String },
+    ClearRecording { recording_name: String },
+}
+
+impl EditCommand {
+    /// Looks up a command that takes no params by its method name, e.g. `"move_word_left"`.
+    pub fn from_method(method: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::json!({ "method": method })).ok()
+    }
+}
+
+/// The edit methods xi-core answers, both give back the text they took from the selection.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "method", content = "params")]
+pub enum EditRequestCommand {
+    Cut,
+    Copy,
+}
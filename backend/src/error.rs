@@ -0,0 +1,44 @@
+use std::fmt;
+
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+/// The error half of a json-rpc response.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> Self {
+        RpcError { code, message: message.to_string(), data: None }
+    }
+
+    /// Reads an error from whatever json the other side sent, keeping it as the message
+    /// if it isn't a well formed json-rpc error object.
+    pub fn from_json(value: Value) -> Self {
+        match serde_json::from_value(value.clone()) {
+            Ok(e) => e,
+            Err(_) => RpcError { code: -32603, message: value.to_string(), data: None },
+        }
+    }
+
+    /// For a response we got, but couldn't turn into the type the request expects.
+    pub fn bad_response(method: &str, error: serde_json::Error) -> Self {
+        RpcError::new(-32700, &format!("unexpected response to {}: {}", method, error))
+    }
+
+    /// For when the core went away before it answered.
+    pub fn disconnected() -> Self {
+        RpcError::new(-32000, "xi-core exited before responding")
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
@@ -1 +1,12 @@
-//todo just define a Backend type here that takes in whatever xi needs
\ No newline at end of file
+//! A typed client for xi-core. Every method xi-core understands has a request or notification
+//! type here, and `Client` sends them over whichever `Transport` the editor was started with.
+
+pub mod client;
+pub mod edit;
+pub mod error;
+pub mod pending;
+pub mod plugin;
+pub mod rpc;
+
+pub use client::{ Client, Transport };
+pub use error::RpcError;
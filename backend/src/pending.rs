@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{ channel, Receiver, Sender };
+
+use serde_json::Value;
+
+use crate::error::RpcError;
+
+pub type Response = Result<Value, RpcError>;
+
+/// Matches responses from xi-core up with the requests waiting on them, by request id.
+#[derive(Default)]
+pub struct PendingRequests {
+    next_id: u64,
+    waiting: HashMap<u64, Sender<Response>>,
+}
+
+impl PendingRequests {
+    pub fn new() -> Self {
+        PendingRequests::default()
+    }
+
+    /// Allocates the id for a new request, and the receiver its response will arrive on.
+    pub fn register(&mut self) -> (u64, Receiver<Response>) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (sender, receiver) = channel();
+        self.waiting.insert(id, sender);
+        (id, receiver)
+    }
+
+    /// Hands a response to whoever is waiting on it. Returns false for an id nobody is waiting on.
+    pub fn resolve(&mut self, id: u64, response: Response) -> bool {
+        match self.waiting.remove(&id) {
+            Some(sender) => {
+                // they may have given up waiting, which is fine
+                let _ = sender.send(response);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fails every request still waiting, for when the core has gone away.
+    pub fn disconnect(&mut self) {
+        for (_, sender) in self.waiting.drain() {
+            let _ = sender.send(Err(RpcError::disconnected()));
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use xi_core_lib::ViewId;
+
+/// A method call forwarded to a plugin untouched.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PluginRpcCall {
+    pub method: String,
+    pub params: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "command")]
+pub enum PluginCommand {
+    Start { view_id: ViewId, plugin_name: String },
+    Stop { view_id: ViewId, plugin_name: String },
+    PluginRpc { view_id: ViewId, receiver: String, rpc: PluginRpcCall },
+}
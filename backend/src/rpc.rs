@@ -0,0 +1,192 @@
+use std::path::PathBuf;
+
+use serde::{ Deserialize, Serialize };
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use xi_core_lib::{ ViewId, LanguageId };
+
+use crate::edit::{ EditCommand, EditRequestCommand };
+use crate::plugin::PluginCommand;
+
+/// A message to xi-core that doesn't get an answer. Serializes to its params.
+pub trait Notification: Serialize {
+    const METHOD: &'static str;
+}
+
+/// A message to xi-core that gets an answer. Serializes to its params.
+pub trait Request: Serialize {
+    const METHOD: &'static str;
+    type Response: DeserializeOwned;
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClientStarted {
+    pub config_dir: Option<PathBuf>,
+    pub client_extras_dir: Option<PathBuf>,
+}
+
+impl Notification for ClientStarted {
+    const METHOD: &'static str = "client_started";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SetTheme {
+    pub theme_name: String,
+}
+
+impl Notification for SetTheme {
+    const METHOD: &'static str = "set_theme";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Edit {
+    pub view_id: ViewId,
+    #[serde(flatten)]
+    pub command: EditCommand,
+}
+
+impl Notification for Edit {
+    const METHOD: &'static str = "edit";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Plugin {
+    #[serde(flatten)]
+    pub command: PluginCommand,
+}
+
+impl Notification for Plugin {
+    const METHOD: &'static str = "plugin";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CloseView {
+    pub view_id: ViewId,
+}
+
+impl Notification for CloseView {
+    const METHOD: &'static str = "close_view";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Save {
+    pub view_id: ViewId,
+    pub file_path: PathBuf,
+}
+
+impl Notification for Save {
+    const METHOD: &'static str = "save";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SetLanguage {
+    pub view_id: ViewId,
+    pub language_id: LanguageId,
+}
+
+impl Notification for SetLanguage {
+    const METHOD: &'static str = "set_language";
+}
+
+/// Which set of settings a `ModifyUserConfig` changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigDomain {
+    General,
+    Syntax(LanguageId),
+    UserOverride(ViewId),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModifyUserConfig {
+    pub domain: ConfigDomain,
+    pub changes: Value,
+}
+
+impl Notification for ModifyUserConfig {
+    const METHOD: &'static str = "modify_user_config";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NewView {
+    pub file_path: Option<PathBuf>,
+}
+
+impl Request for NewView {
+    const METHOD: &'static str = "new_view";
+    type Response = ViewId;
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GetConfig {
+    pub view_id: ViewId,
+}
+
+impl Request for GetConfig {
+    const METHOD: &'static str = "get_config";
+    type Response = Value;
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EditRequest {
+    pub view_id: ViewId,
+    #[serde(flatten)]
+    pub command: EditRequestCommand,
+}
+
+impl Request for EditRequest {
+    const METHOD: &'static str = "edit";
+    type Response = Option<String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::edit::{ MouseDrag, Size };
+    use crate::plugin::PluginCommand;
+
+    fn view_id() -> ViewId {
+        serde_json::from_value(json!("view-id-1")).unwrap()
+    }
+
+    #[test]
+    fn edits_carry_their_method_and_params_inside_the_view() {
+        let insert = Edit { view_id: view_id(), command: EditCommand::Insert { chars: "a".to_string() } };
+        assert_eq!(serde_json::to_value(&insert).unwrap(), json!({
+            "view_id": "view-id-1",
+            "method": "insert",
+            "params": { "chars": "a" },
+        }));
+
+        let resize = Edit { view_id: view_id(), command: EditCommand::Resize(Size { width: 640, height: 480 }) };
+        assert_eq!(serde_json::to_value(&resize).unwrap(), json!({
+            "view_id": "view-id-1",
+            "method": "resize",
+            "params": { "width": 640, "height": 480 },
+        }));
+
+        let scroll = Edit { view_id: view_id(), command: EditCommand::Scroll((0, 40)) };
+        assert_eq!(serde_json::to_value(&scroll).unwrap()["params"], json!([0, 40]));
+
+        let drag = Edit { view_id: view_id(), command: EditCommand::Drag(MouseDrag { line: 3, col: 7, modifiers: 0 }) };
+        assert_eq!(serde_json::to_value(&drag).unwrap()["params"], json!([3, 7, 0]));
+    }
+
+    #[test]
+    fn commands_without_params_can_be_looked_up_by_name() {
+        assert_eq!(EditCommand::from_method("move_word_left"), Some(EditCommand::MoveWordLeft));
+        assert_eq!(EditCommand::from_method("insert"), None);
+        assert_eq!(EditCommand::from_method("not_a_method"), None);
+    }
+
+    #[test]
+    fn plugin_commands_are_tagged_by_command() {
+        let start = Plugin { command: PluginCommand::Start { view_id: view_id(), plugin_name: "syntect".to_string() } };
+        assert_eq!(serde_json::to_value(&start).unwrap(), json!({
+            "command": "start",
+            "view_id": "view-id-1",
+            "plugin_name": "syntect",
+        }));
+    }
+}
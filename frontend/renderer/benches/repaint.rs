@@ -0,0 +1,80 @@
+//! Times repainting a full screen of styled text with the software renderer, with the shaped
+//! run cache and glyph atlas and without them. Run with `cargo bench`.
+
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+use xi_path_renderer::display_list::{ Color, FontFace, FontSpec, Frame, Point, RenderTarget };
+use xi_path_renderer::fonts::{ self, MeasureText };
+use xi_path_renderer::software_renderer::SoftwareRenderer;
+use xi_path_renderer::Renders;
+
+const WIDTH: f32 = 1200.0;
+const HEIGHT: f32 = 900.0;
+const FRAMES: u32 = 30;
+
+const SOURCE: &[&str] = &[
+    "fn layout_line(line: &Line, viewport: &Viewport, styles: &StyleMap) -> Vec<DisplayItem> {",
+    "    let font = config.text_font(settings);",
+    "    for (start, end, style) in style_segments(line, styles) {",
+    "        let left = x_of(start, fonts); // where the segment starts",
+    "        frame.push_text(TextRun { origin: Point::new(left, baseline), text, font });",
+    "    }",
+    "}",
+    "",
+];
+
+// a screen of code, each line split into a few differently coloured and weighted runs
+fn frame(renderer: &mut SoftwareRenderer) -> Frame {
+    let face = FontFace::Path(Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/fonts/DejaVuSansMono.ttf"));
+    let font = FontSpec::new(face, 14.0);
+    let mut bold = font.clone();
+    bold.bold = true;
+    let line_height = renderer.fonts().line_metrics(&font).height();
+
+    let mut frame = Frame::new(WIDTH, HEIGHT, Color::white());
+    let rows = (HEIGHT / line_height) as usize;
+    for row in 0..rows {
+        let text = SOURCE[row % SOURCE.len()];
+        let baseline = line_height * (row + 1) as f32;
+        let mut x = 40.0;
+        for (index, word) in text.split_inclusive(' ').enumerate() {
+            let (font, color) = match index % 3 {
+                0 => (&bold, Color::rgb(160, 40, 160)),
+                1 => (&font, Color::black()),
+                _ => (&font, Color::rgb(40, 120, 40)),
+            };
+            frame.fill_text(word, Point::new(x, baseline), font, color);
+            x += renderer.fonts().text_width(word, font);
+        }
+        frame.fill_text(&(row + 1).to_string(), Point::new(0.0, baseline), &font, Color::rgb(170, 170, 170));
+    }
+    frame
+}
+
+fn repaint(name: &str, mut renderer: SoftwareRenderer, scale_factor: f32) -> Duration {
+    let frame = frame(&mut renderer);
+    let target = RenderTarget::new((WIDTH * scale_factor) as u32, (HEIGHT * scale_factor) as u32, scale_factor);
+
+    // the first frame fills the caches, what we're after is every frame after it
+    renderer.render(&frame, &target);
+    let started = Instant::now();
+    for _ in 0..FRAMES {
+        renderer.render(&frame, &target);
+    }
+    let per_frame = started.elapsed() / FRAMES;
+    println!("{:<28} {:>8.2}ms a frame", name, per_frame.as_secs_f64() * 1000.0);
+    per_frame
+}
+
+fn main() {
+    for &scale_factor in &[1.0, 2.0] {
+        println!("{} frames of {}x{} at {}x", FRAMES, WIDTH, HEIGHT, scale_factor);
+        let uncached = repaint("  without caches", SoftwareRenderer::with_caches(0, 0), scale_factor);
+        let runs = repaint("  shaped run cache", SoftwareRenderer::with_caches(fonts::RUN_CACHE_BYTES, 0), scale_factor);
+        let cached = repaint("  run cache and glyph atlas", SoftwareRenderer::new(), scale_factor);
+        println!("  {:.1}x faster with the run cache, {:.1}x with both",
+                 uncached.as_secs_f64() / runs.as_secs_f64(),
+                 uncached.as_secs_f64() / cached.as_secs_f64());
+    }
+}
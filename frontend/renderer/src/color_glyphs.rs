@@ -0,0 +1,341 @@
+//! Colour glyphs, like emoji. font-kit only draws outlines in one colour, so the colour tables
+//! are read here: COLR layers (outlines drawn over each other, each in a colour from CPAL), and
+//! the PNG pictures in CBDT or sbix tables that Noto Color Emoji and Apple Color Emoji use.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use font_kit::canvas::{ Canvas, Format, RasterizationOptions };
+use font_kit::font::Font;
+use font_kit::hinting::HintingOptions;
+use pathfinder_geometry::rect::RectI;
+use pathfinder_geometry::transform2d::Transform2F;
+
+use crate::display_list::{ Color, Image };
+use crate::fonts::FontKey;
+use crate::lru::Lru;
+
+/// Roughly how many bytes of drawn colour glyphs a renderer keeps. They're four bytes a pixel,
+/// but there are far fewer of them than ordinary glyphs.
+pub const COLOR_GLYPH_CACHE_BYTES: usize = 4 * 1024 * 1024;
+
+/// A colour glyph drawn at a size, with its top left relative to the pen on the baseline, in pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorRaster {
+    pub image: Image,
+    pub left: i32,
+    pub top: i32,
+}
+
+/// Draws colour glyphs and keeps them, most emoji on screen are on it many times over.
+pub struct ColorGlyphCache {
+    // None for fonts without colour tables, which is most of them
+    tables: HashMap<FontKey, Option<Arc<ColorTables>>>,
+    rasters: Lru<(FontKey, u32, u32, Color), Option<Arc<ColorRaster>>>,
+}
+
+impl ColorGlyphCache {
+    /// Keeps about `bytes` of drawn glyphs.
+    pub fn new(bytes: usize) -> Self {
+        ColorGlyphCache { tables: HashMap::new(), rasters: Lru::new(bytes) }
+    }
+
+    /// Whether `font` has any colour glyphs at all, so callers can skip looking glyph by glyph.
+    pub fn has_colors(&mut self, font: &Font, key: &FontKey) -> bool {
+        self.tables(font, key).is_some()
+    }
+
+    /// `glyph` drawn in colour at `size` pixels to the em, or `None` when it's an ordinary glyph.
+    /// Layers the font leaves to the text's colour are drawn in `text_color`.
+    pub fn glyph(&mut self, font: &Font, key: &FontKey, glyph: u32, size: f32, text_color: Color) -> Option<Arc<ColorRaster>> {
+        let tables = self.tables(font, key)?;
+        let cache_key = (key.clone(), glyph, size.to_bits(), text_color);
+        if let Some(raster) = self.rasters.get(&cache_key) {
+            return raster.clone();
+        }
+
+        let raster = tables.glyph(glyph).and_then(|color_glyph| rasterize(font, &color_glyph, size, text_color)).map(Arc::new);
+        let cost = raster.as_ref().map(|r| r.image.pixels.len()).unwrap_or(0) + 64;
+        self.rasters.insert(cache_key, raster.clone(), cost);
+        raster
+    }
+
+    fn tables(&mut self, font: &Font, key: &FontKey) -> Option<Arc<ColorTables>> {
+        self.tables.entry(key.clone())
+            .or_insert_with(|| ColorTables::load(font).map(Arc::new))
+            .clone()
+    }
+}
+
+/// What a colour font has for one glyph.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorGlyph {
+    /// Outline glyphs drawn over each other, each in its colour, or the text's when it's `None`.
+    Layers(Vec<(u32, Option<Color>)>),
+    /// A picture drawn for `ppem` pixels to the em, and where its top left goes relative to the
+    /// pen, in the picture's pixels.
+    Bitmap { image: Image, ppem: f32, left: f32, top: f32 },
+}
+
+/// The colour tables of a font, as they came out of it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColorTables {
+    pub colr: Option<Vec<u8>>,
+    pub cpal: Option<Vec<u8>>,
+    pub cblc: Option<Vec<u8>>,
+    pub cbdt: Option<Vec<u8>>,
+    pub sbix: Option<Vec<u8>>,
+    pub glyph_count: u16,
+}
+
+fn tag(name: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*name)
+}
+
+impl ColorTables {
+    /// `None` when the font has no colour glyphs.
+    pub fn load(font: &Font) -> Option<ColorTables> {
+        let table = |name: &[u8; 4]| font.load_font_table(tag(name)).map(|t| t.to_vec());
+        let tables = ColorTables {
+            colr: table(b"COLR"),
+            cpal: table(b"CPAL"),
+            cblc: table(b"CBLC"),
+            cbdt: table(b"CBDT"),
+            sbix: table(b"sbix"),
+            glyph_count: table(b"maxp").and_then(|maxp| read_u16(&maxp, 4)).unwrap_or(0),
+        };
+
+        let has_layers = tables.colr.is_some() && tables.cpal.is_some();
+        let has_bitmaps = (tables.cblc.is_some() && tables.cbdt.is_some()) || tables.sbix.is_some();
+        if has_layers || has_bitmaps { Some(tables) } else { None }
+    }
+
+    /// What the font has for `glyph`. Layers are preferred, they stay sharp at any size.
+    /// Bitmaps come from the biggest strike with the glyph, and are shrunk to size when drawn.
+    pub fn glyph(&self, glyph: u32) -> Option<ColorGlyph> {
+        self.layers(glyph)
+            .or_else(|| self.cbdt_bitmap(glyph))
+            .or_else(|| self.sbix_bitmap(glyph))
+    }
+
+    // COLR version 0: base glyph records of (glyph, first layer, layer count), sorted by glyph,
+    // and layer records of (glyph, palette index)
+    fn layers(&self, glyph: u32) -> Option<ColorGlyph> {
+        let colr = self.colr.as_ref()?;
+        let cpal = self.cpal.as_ref()?;
+        let base_count = read_u16(colr, 2)? as usize;
+        let base_offset = read_u32(colr, 4)? as usize;
+        let layer_offset = read_u32(colr, 8)? as usize;
+
+        let base = (0..base_count)
+            .map(|i| base_offset + i * 6)
+            .find(|record| read_u16(colr, *record) == Some(glyph as u16))?;
+        let first_layer = read_u16(colr, base + 2)? as usize;
+        let layer_count = read_u16(colr, base + 4)? as usize;
+
+        let mut layers = Vec::with_capacity(layer_count);
+        for layer in first_layer..first_layer + layer_count {
+            let record = layer_offset + layer * 4;
+            let layer_glyph = read_u16(colr, record)? as u32;
+            let palette_index = read_u16(colr, record + 2)?;
+            // 0xffff means whatever colour the text is
+            let color = if palette_index == 0xffff { None } else { Some(palette_color(cpal, palette_index)?) };
+            layers.push((layer_glyph, color));
+        }
+        Some(ColorGlyph::Layers(layers))
+    }
+
+    // CBLC indexes the PNGs in CBDT by strike, then by ranges of glyphs
+    fn cbdt_bitmap(&self, glyph: u32) -> Option<ColorGlyph> {
+        let cblc = self.cblc.as_ref()?;
+        let cbdt = self.cbdt.as_ref()?;
+        let glyph = glyph as u16;
+
+        // the biggest strike with the glyph, it's shrunk to size when drawn
+        let strike_count = read_u32(cblc, 4)? as usize;
+        let strike = (0..strike_count)
+            .map(|i| 8 + i * 48)
+            .filter(|strike| {
+                let (start, end) = (read_u16(cblc, strike + 40), read_u16(cblc, strike + 42));
+                matches!((start, end), (Some(start), Some(end)) if start <= glyph && glyph <= end)
+            })
+            .max_by_key(|strike| cblc.get(strike + 45).cloned().unwrap_or(0))?;
+        let ppem = *cblc.get(strike + 45)? as f32;
+
+        let array_offset = read_u32(cblc, strike)? as usize;
+        let subtable_count = read_u32(cblc, strike + 8)? as usize;
+        for i in 0..subtable_count {
+            let entry = array_offset + i * 8;
+            let (first, last) = (read_u16(cblc, entry)?, read_u16(cblc, entry + 2)?);
+            if glyph < first || glyph > last {
+                continue;
+            }
+
+            let subtable = array_offset + read_u32(cblc, entry + 4)? as usize;
+            let index_format = read_u16(cblc, subtable)?;
+            let image_format = read_u16(cblc, subtable + 2)?;
+            let image_data = read_u32(cblc, subtable + 4)? as usize;
+            let index = (glyph - first) as usize;
+            let (start, end) = match index_format {
+                1 => (read_u32(cblc, subtable + 8 + index * 4)? as usize, read_u32(cblc, subtable + 12 + index * 4)? as usize),
+                2 => {
+                    let size = read_u32(cblc, subtable + 8)? as usize;
+                    (index * size, (index + 1) * size)
+                }
+                3 => (read_u16(cblc, subtable + 8 + index * 2)? as usize, read_u16(cblc, subtable + 10 + index * 2)? as usize),
+                _ => return None,
+            };
+            let data = cbdt.get(image_data + start..image_data + end)?;
+
+            // small or big metrics ahead of the png: height, width, then bearings, which are what we need
+            let (metrics_len, bearing_x, bearing_y) = match image_format {
+                17 => (5, *data.get(2)? as i8, *data.get(3)? as i8),
+                18 => (8, *data.get(2)? as i8, *data.get(3)? as i8),
+                _ => return None,
+            };
+            let png_len = read_u32(data, metrics_len)? as usize;
+            let png = data.get(metrics_len + 4..metrics_len + 4 + png_len)?;
+            let image = decode_png(png)?;
+            return Some(ColorGlyph::Bitmap { image, ppem, left: bearing_x as f32, top: -(bearing_y as f32) });
+        }
+        None
+    }
+
+    // sbix has a strike per size, each with an offset per glyph to its picture
+    fn sbix_bitmap(&self, glyph: u32) -> Option<ColorGlyph> {
+        let sbix = self.sbix.as_ref()?;
+        let strike_count = read_u32(sbix, 4)? as usize;
+        let glyph = glyph as usize;
+        if glyph >= self.glyph_count as usize {
+            return None;
+        }
+
+        let mut best: Option<(f32, &[u8], i16, i16)> = None;
+        for i in 0..strike_count {
+            let strike = read_u32(sbix, 8 + i * 4)? as usize;
+            let ppem = read_u16(sbix, strike)? as f32;
+            let start = read_u32(sbix, strike + 4 + glyph * 4)? as usize;
+            let end = read_u32(sbix, strike + 8 + glyph * 4)? as usize;
+            // an empty entry means this strike has nothing for the glyph
+            if end <= start + 8 {
+                continue;
+            }
+            let data = sbix.get(strike + start..strike + end)?;
+            if &data[4..8] != b"png " {
+                continue;
+            }
+            if best.map(|(best_ppem, ..)| ppem > best_ppem).unwrap_or(true) {
+                best = Some((ppem, &data[8..], read_u16(data, 0)? as i16, read_u16(data, 2)? as i16));
+            }
+        }
+
+        // the origin offsets are to the picture's bottom left, going up
+        let (ppem, png, origin_x, origin_y) = best?;
+        let image = decode_png(png)?;
+        let top = -(origin_y as f32 + image.height as f32);
+        Some(ColorGlyph::Bitmap { image, ppem, left: origin_x as f32, top })
+    }
+}
+
+// CPAL: palettes index into one list of BGRA colours, we only use the first palette
+fn palette_color(cpal: &[u8], index: u16) -> Option<Color> {
+    let records_offset = read_u32(cpal, 8)? as usize;
+    let first_record = read_u16(cpal, 12)? as usize;
+    let record = records_offset + (first_record + index as usize) * 4;
+    let bgra = cpal.get(record..record + 4)?;
+    Some(Color::rgba(bgra[2], bgra[1], bgra[0], bgra[3]))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn decode_png(data: &[u8]) -> Option<Image> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().ok()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer).ok()?;
+
+    // expanded and stripped, a png is 8 bit gray, gray and alpha, rgb or rgba
+    let pixels = match info.color_type {
+        png::ColorType::RGBA => buffer,
+        png::ColorType::RGB => buffer.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|g| vec![*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+    Some(Image { width: info.width, height: info.height, pixels })
+}
+
+/// Draws a colour glyph at `size` pixels to the em.
+fn rasterize(font: &Font, glyph: &ColorGlyph, size: f32, text_color: Color) -> Option<ColorRaster> {
+    match glyph {
+        ColorGlyph::Layers(layers) => {
+            let bounds_of = |layer: u32| {
+                font.raster_bounds(layer, size, Transform2F::default(), HintingOptions::None, RasterizationOptions::GrayscaleAa)
+                    .ok()
+                    .filter(|bounds| bounds.width() > 0 && bounds.height() > 0)
+            };
+            let bounds = layers.iter()
+                .filter_map(|(layer, _)| bounds_of(*layer))
+                .fold(None, |all: Option<RectI>, bounds| Some(all.map(|all| all.union_rect(bounds)).unwrap_or(bounds)))?;
+
+            let mut image = Image::new(bounds.width() as u32, bounds.height() as u32, Color::transparent());
+            for (layer, color) in layers {
+                let mut canvas = Canvas::new(bounds.size(), Format::A8);
+                let transform = Transform2F::from_translation(-bounds.origin().to_f32());
+                if font.rasterize_glyph(&mut canvas, *layer, size, transform, HintingOptions::None,
+                                        RasterizationOptions::GrayscaleAa).is_err() {
+                    continue;
+                }
+                for y in 0..bounds.height() {
+                    for x in 0..bounds.width() {
+                        let coverage = canvas.pixels[y as usize * canvas.stride + x as usize];
+                        if coverage > 0 {
+                            image.blend(x, y, color.unwrap_or(text_color), coverage);
+                        }
+                    }
+                }
+            }
+            Some(ColorRaster { image, left: bounds.origin_x(), top: bounds.origin_y() })
+        }
+        ColorGlyph::Bitmap { image, ppem, left, top } => {
+            let scale = size / ppem;
+            let width = ((image.width as f32 * scale).round() as u32).max(1);
+            let height = ((image.height as f32 * scale).round() as u32).max(1);
+            Some(ColorRaster {
+                image: image.scaled(width, height),
+                left: (left * scale).round() as i32,
+                top: (top * scale).round() as i32,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colr_layers_take_their_colours_from_the_palette() {
+        // COLR v0 with one base glyph, 5, made of layers 7 in palette colour 1 and 8 in the text's colour
+        let mut colr = vec![0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0, 20, 0, 2];
+        colr.extend_from_slice(&[0, 5, 0, 0, 0, 2]);
+        colr.extend_from_slice(&[0, 7, 0, 1, 0, 8, 0xff, 0xff]);
+
+        // CPAL with one palette of two colours, stored as BGRA
+        let mut cpal = vec![0, 0, 0, 2, 0, 1, 0, 2, 0, 0, 0, 14, 0, 0];
+        cpal.extend_from_slice(&[0, 0, 255, 255, 255, 128, 0, 255]);
+
+        let tables = ColorTables { colr: Some(colr), cpal: Some(cpal), ..ColorTables::default() };
+        let expected = vec![(7, Some(Color::rgb(0, 128, 255))), (8, None)];
+        assert_eq!(tables.glyph(5), Some(ColorGlyph::Layers(expected)));
+        assert_eq!(tables.glyph(6), None);
+    }
+}
@@ -0,0 +1,331 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// An 8-bit RGBA colour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b, a: 255 }
+    }
+
+    pub const fn black() -> Self {
+        Color::rgb(0, 0, 0)
+    }
+
+    pub const fn white() -> Self {
+        Color::rgb(255, 255, 255)
+    }
+
+    pub const fn transparent() -> Self {
+        Color::rgba(0, 0, 0, 0)
+    }
+
+    /// xi-core sends colours packed as ARGB.
+    pub fn from_argb(argb: u32) -> Self {
+        Color {
+            a: (argb >> 24) as u8,
+            r: (argb >> 16) as u8,
+            g: (argb >> 8) as u8,
+            b: argb as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32) -> Self {
+        Point { x, y }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn max_x(&self) -> f32 {
+        self.x + self.width
+    }
+
+    pub fn max_y(&self) -> f32 {
+        self.y + self.height
+    }
+
+    /// The area covered by both rects, empty if they don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let width = (self.max_x().min(other.max_x()) - x).max(0.0);
+        let height = (self.max_y().min(other.max_y()) - y).max(0.0);
+        Rect { x, y, width, height }
+    }
+
+    /// The smallest rect covering both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.max_x().max(other.max_x()) - x, self.max_y().max(other.max_y()) - y)
+    }
+
+    pub fn scale(&self, factor: f32) -> Rect {
+        Rect::new(self.x * factor, self.y * factor, self.width * factor, self.height * factor)
+    }
+}
+
+/// Where a font comes from: a file we ship, or a family installed on the system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FontFace {
+    Path(PathBuf),
+    Family(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontSpec {
+    pub face: FontFace,
+    pub size: f32,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl FontSpec {
+    pub fn new(face: FontFace, size: f32) -> Self {
+        FontSpec { face, size, bold: false, italic: false }
+    }
+}
+
+/// A run of text in a single font and colour, drawn with its baseline starting at `origin`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRun {
+    pub origin: Point,
+    pub text: String,
+    pub font: FontSpec,
+    pub color: Color,
+    pub underline: bool,
+    /// Right to left text, like a Hebrew or Arabic run. `text` stays in logical order, and
+    /// is drawn with its first character at the right, ending at `origin`'s side on the left.
+    pub rtl: bool,
+}
+
+/// An RGBA image, 4 bytes a pixel, rows top to bottom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, fill: Color) -> Self {
+        let pixels = [fill.r, fill.g, fill.b, fill.a].iter()
+            .cycle()
+            .take((width * height * 4) as usize)
+            .cloned()
+            .collect();
+        Image { width, height, pixels }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = ((y * self.width + x) * 4) as usize;
+        Color::rgba(self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3])
+    }
+
+    /// Draws `color` over the pixel, `coverage` (0-255) scaling its alpha.
+    pub fn blend(&mut self, x: i32, y: i32, color: Color, coverage: u8) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        // the pixel underneath can be see-through too, like in a colour glyph being built up
+        let i = ((y as u32 * self.width + x as u32) * 4) as usize;
+        let alpha = color.a as u32 * coverage as u32 / 255;
+        let under_alpha = self.pixels[i + 3] as u32 * (255 - alpha) / 255;
+        let out_alpha = alpha + under_alpha;
+        if out_alpha == 0 {
+            return;
+        }
+        let mix = |over: u8, under: u8| ((over as u32 * alpha + under as u32 * under_alpha) / out_alpha) as u8;
+        self.pixels[i] = mix(color.r, self.pixels[i]);
+        self.pixels[i + 1] = mix(color.g, self.pixels[i + 1]);
+        self.pixels[i + 2] = mix(color.b, self.pixels[i + 2]);
+        self.pixels[i + 3] = out_alpha as u8;
+    }
+
+    /// The image resized to `width` by `height`, each pixel the average of those it covers.
+    /// For shrinking bitmaps like emoji down to text size, where nearest neighbour looks ragged.
+    pub fn scaled(&self, width: u32, height: u32) -> Image {
+        let mut scaled = Image::new(width, height, Color::transparent());
+        if self.width == 0 || self.height == 0 {
+            return scaled;
+        }
+
+        let (x_ratio, y_ratio) = (self.width as f32 / width as f32, self.height as f32 / height as f32);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = ((x as f32 * x_ratio) as u32, (y as f32 * y_ratio) as u32);
+                let x1 = (((x + 1) as f32 * x_ratio).ceil() as u32).min(self.width).max(x0 + 1);
+                let y1 = (((y + 1) as f32 * y_ratio).ceil() as u32).min(self.height).max(y0 + 1);
+
+                // weight colours by alpha, so transparent pixels don't darken the edges
+                let mut sums = [0u32; 4];
+                for source_y in y0..y1 {
+                    for source_x in x0..x1 {
+                        let p = self.pixel(source_x, source_y);
+                        let a = p.a as u32;
+                        sums[0] += p.r as u32 * a;
+                        sums[1] += p.g as u32 * a;
+                        sums[2] += p.b as u32 * a;
+                        sums[3] += a;
+                    }
+                }
+                if sums[3] == 0 {
+                    continue;
+                }
+                let count = (x1 - x0) * (y1 - y0);
+                let i = ((y * width + x) * 4) as usize;
+                scaled.pixels[i] = (sums[0] / sums[3]) as u8;
+                scaled.pixels[i + 1] = (sums[1] / sums[3]) as u8;
+                scaled.pixels[i + 2] = (sums[2] / sums[3]) as u8;
+                scaled.pixels[i + 3] = (sums[3] / count) as u8;
+            }
+        }
+        scaled
+    }
+}
+
+/// How wide carets are drawn, in logical pixels.
+pub const CARET_WIDTH: f32 = 2.0;
+
+/// What a frame is made of. Coordinates are logical pixels, renderers scale them to the target.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisplayItem {
+    Rect { rect: Rect, color: Color },
+    Text(TextRun),
+    /// An insertion point, centred on `position.x` and running down from `position.y`.
+    /// Kept apart from rects so a backend can draw carets its own way, or blink them.
+    Caret { position: Point, height: f32, color: Color },
+    /// Until the matching `PopClip`, only draw inside `rect`, and inside any clip it's nested in.
+    PushClip(Rect),
+    PopClip,
+    /// Draws `image` stretched over `rect`.
+    Image { rect: Rect, image: Arc<Image> },
+}
+
+impl DisplayItem {
+    /// The same item moved over by `dx` and down by `dy`.
+    pub fn translate(&self, dx: f32, dy: f32) -> DisplayItem {
+        let move_rect = |rect: &Rect| Rect::new(rect.x + dx, rect.y + dy, rect.width, rect.height);
+        let move_point = |point: &Point| Point::new(point.x + dx, point.y + dy);
+        match self {
+            DisplayItem::Rect { rect, color } => DisplayItem::Rect { rect: move_rect(rect), color: *color },
+            DisplayItem::Text(run) => DisplayItem::Text(TextRun { origin: move_point(&run.origin), ..run.clone() }),
+            DisplayItem::Caret { position, height, color } => {
+                DisplayItem::Caret { position: move_point(position), height: *height, color: *color }
+            }
+            DisplayItem::PushClip(rect) => DisplayItem::PushClip(move_rect(rect)),
+            DisplayItem::PopClip => DisplayItem::PopClip,
+            DisplayItem::Image { rect, image } => DisplayItem::Image { rect: move_rect(rect), image: image.clone() },
+        }
+    }
+}
+
+/// Everything that makes up one frame, in drawing order, independent of what draws it.
+/// `width` and `height` are the logical size the frame was laid out for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub width: f32,
+    pub height: f32,
+    pub background: Color,
+    pub items: Vec<DisplayItem>,
+    /// What changed since the previous frame, `None` when it all did. A renderer that kept the
+    /// previous frame only has to redraw these areas, and doesn't have to draw at all when it's empty.
+    pub damage: Option<Vec<Rect>>,
+}
+
+/// What a frame is drawn onto: its size in device pixels, and how many of those make up a
+/// logical pixel. Mouse positions from SDL are already logical, so hit-testing against a
+/// frame never needs the scale factor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderTarget {
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+}
+
+impl RenderTarget {
+    pub fn new(width: u32, height: u32, scale_factor: f32) -> Self {
+        RenderTarget { width, height, scale_factor }
+    }
+
+    /// The size frames for this target should be laid out at.
+    pub fn logical_size(&self) -> (f32, f32) {
+        (self.width as f32 / self.scale_factor, self.height as f32 / self.scale_factor)
+    }
+}
+
+impl Frame {
+    pub fn new(width: f32, height: f32, background: Color) -> Self {
+        Frame { width, height, background, items: Vec::new(), damage: None }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        self.items.push(DisplayItem::Rect { rect, color });
+    }
+
+    pub fn fill_text(&mut self, text: &str, origin: Point, font: &FontSpec, color: Color) {
+        self.push_text(TextRun {
+            origin,
+            text: text.to_string(),
+            font: font.clone(),
+            color,
+            underline: false,
+            rtl: false,
+        });
+    }
+
+    pub fn push_text(&mut self, run: TextRun) {
+        if run.text.is_empty() {
+            return;
+        }
+        self.items.push(DisplayItem::Text(run));
+    }
+
+    pub fn caret(&mut self, position: Point, height: f32, color: Color) {
+        self.items.push(DisplayItem::Caret { position, height, color });
+    }
+
+    pub fn push_clip(&mut self, rect: Rect) {
+        self.items.push(DisplayItem::PushClip(rect));
+    }
+
+    pub fn pop_clip(&mut self) {
+        self.items.push(DisplayItem::PopClip);
+    }
+
+    pub fn draw_image(&mut self, rect: Rect, image: Arc<Image>) {
+        self.items.push(DisplayItem::Image { rect, image });
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+use font_kit::family_name::FamilyName;
+use font_kit::font::Font;
+use font_kit::properties::{ Properties, Style, Weight };
+use font_kit::source::SystemSource;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+use crate::display_list::{ FontFace, FontSpec };
+use crate::lru::Lru;
+
+/// Roughly how many bytes of shaped runs a `FontCache` keeps by default, a few thousand lines' worth.
+pub const RUN_CACHE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Vertical metrics of a font at a size, in pixels. `descent` is the distance below the baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+}
+
+impl LineMetrics {
+    pub fn height(&self) -> f32 {
+        self.ascent + self.descent
+    }
+}
+
+/// What layout needs to know about text to place it, without having to draw it.
+pub trait MeasureText {
+    fn text_width(&mut self, text: &str, font: &FontSpec) -> f32;
+    fn line_metrics(&mut self, font: &FontSpec) -> LineMetrics;
+
+    /// Where each cluster of `text` starts from its left, as its byte offset and x, in the order
+    /// of the text and ending with the text's length and width. By default every char is a
+    /// cluster of its own, as wide as it is on its own.
+    fn cluster_xs(&mut self, text: &str, font: &FontSpec) -> Vec<(usize, f32)> {
+        let mut xs = Vec::with_capacity(text.len() + 1);
+        let mut x = 0.0;
+        let mut buffer = [0; 4];
+        for (offset, c) in text.char_indices() {
+            xs.push((offset, x));
+            x += self.text_width(c.encode_utf8(&mut buffer), font);
+        }
+        xs.push((text.len(), x));
+        xs
+    }
+}
+
+/// A glyph in a shaped run, `x` pixels right of the start of the run and `y` below its baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionedGlyph {
+    pub glyph: u32,
+    pub x: f32,
+    pub y: f32,
+    /// The byte offset into the run's text of what this glyph draws. Shaped glyphs don't say
+    /// which text they came from, and are all 0.
+    pub cluster: usize,
+    /// From the fallback font rather than the run's own, like an emoji in a code font.
+    pub fallback: bool,
+}
+
+/// A run of text turned into the glyphs that draw it, at one font and size.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapedRun {
+    pub glyphs: Vec<PositionedGlyph>,
+    pub width: f32,
+    /// Where each cluster starts, as its byte offset into the text and the x the pen was at.
+    /// They aren't mirrored for a right to left run. Empty when shaped, for want of clusters.
+    pub clusters: Vec<(usize, f32)>,
+}
+
+/// Which loaded font a spec draws with. Size isn't part of it, one font serves every size.
+pub type FontKey = (FontFace, bool, bool);
+
+pub fn font_key(spec: &FontSpec) -> FontKey {
+    (spec.face.clone(), spec.bold, spec.italic)
+}
+
+// where to look for what a font doesn't have, emoji mostly, in order
+const FALLBACK_FAMILIES: &[&str] = &["Apple Color Emoji", "Noto Color Emoji", "Segoe UI Emoji", "Twemoji Mozilla"];
+
+/// How many cells a grapheme takes in a monospace font: two for wide ones, like emoji and CJK,
+/// and one for everything else.
+pub fn grapheme_columns(grapheme: &str) -> usize {
+    // a variation selector 16 asks for the emoji presentation, which is always wide
+    if grapheme.contains('\u{FE0F}') {
+        return 2;
+    }
+    match grapheme.chars().next().and_then(UnicodeWidthChar::width) {
+        Some(2) => 2,
+        _ => 1,
+    }
+}
+
+/// Loads each font once, from its file or from the system, and keeps it around.
+/// Also keeps recently shaped runs, most lines are measured and drawn many times over.
+pub struct FontCache {
+    fonts: HashMap<FontKey, Font>,
+    // loaded the first time something needs it, and None inside when there's no fallback font at all
+    fallback: Option<Option<(FontKey, Font)>>,
+    runs: Lru<(FontKey, u32, bool, String), Arc<ShapedRun>>,
+}
+
+impl Default for FontCache {
+    fn default() -> Self {
+        FontCache::new()
+    }
+}
+
+impl FontCache {
+    pub fn new() -> Self {
+        FontCache::with_run_cache(RUN_CACHE_BYTES)
+    }
+
+    /// Keeps about `bytes` of shaped runs. With 0, every run is shaped every time it's used.
+    pub fn with_run_cache(bytes: usize) -> Self {
+        FontCache {
+            fonts: HashMap::new(),
+            fallback: None,
+            runs: Lru::new(bytes),
+        }
+    }
+
+    pub fn font(&mut self, spec: &FontSpec) -> &Font {
+        self.fonts.entry(font_key(spec)).or_insert_with(|| load_font(spec))
+    }
+
+    /// The font glyphs with `fallback` set come from, if the system has one.
+    pub fn fallback_font(&mut self) -> Option<(&FontKey, &Font)> {
+        self.fallback
+            .get_or_insert_with(load_fallback)
+            .as_ref()
+            .map(|(key, font)| (key, font))
+    }
+
+    /// The glyphs `text` is drawn with, and where they go. Glyphs are in logical order either
+    /// way, a right to left run just places them from its right edge.
+    pub fn shape(&mut self, text: &str, spec: &FontSpec, rtl: bool) -> Arc<ShapedRun> {
+        let key = (font_key(spec), spec.size.to_bits(), rtl, text.to_string());
+        if let Some(run) = self.runs.get(&key) {
+            return run.clone();
+        }
+
+        let run = Arc::new(self.shape_uncached(text, spec, rtl));
+        let cost = text.len()
+            + run.glyphs.len() * mem::size_of::<PositionedGlyph>()
+            + run.clusters.len() * mem::size_of::<(usize, f32)>()
+            + mem::size_of::<ShapedRun>();
+        self.runs.insert(key, run.clone(), cost);
+        run
+    }
+
+    // shapes without looking in the cache or adding to it
+    fn shape_uncached(&mut self, text: &str, spec: &FontSpec, rtl: bool) -> ShapedRun {
+        let key = font_key(spec);
+        self.font(spec);
+        self.fallback_font();
+        let font = &self.fonts[&key];
+        let fallback = self.fallback.as_ref().and_then(|f| f.as_ref()).map(|(_, font)| font);
+
+        let mut run = ShapedRun::default();
+        for (range, uses_fallback) in segments(font, fallback, text) {
+            let segment_font = match fallback {
+                Some(fallback) if uses_fallback => fallback,
+                _ => font,
+            };
+            let segment = &text[range.clone()];
+            let shaped = shape_run(segment_font, segment, spec.size, uses_fallback);
+
+            // in a monospace font, what comes from the fallback font takes whole cells, centred
+            // in them, so the columns after it still line up
+            let cell = glyph_advance(font, glyph_for_char(font, '0'), spec.size);
+            let advance = if uses_fallback && font.is_monospace() {
+                cell * segment.graphemes(true).map(grapheme_columns).sum::<usize>() as f32
+            } else {
+                shaped.width
+            };
+
+            // clusters start where the pen is, so a centred fallback glyph's starts at its cell's
+            // left. shaped segments have no clusters to go on
+            let start = run.width;
+            if !shaped.clusters.is_empty() && advance != shaped.width {
+                let mut pen = start;
+                for (cluster, grapheme) in segment.grapheme_indices(true) {
+                    run.clusters.push((range.start + cluster, pen));
+                    pen += cell * grapheme_columns(grapheme) as f32;
+                }
+            } else {
+                run.clusters.extend(shaped.clusters.iter().map(|(cluster, x)| (range.start + cluster, start + x)));
+            }
+
+            let offset = run.width + (advance - shaped.width) / 2.0;
+            run.glyphs.extend(shaped.glyphs.iter().map(|glyph| PositionedGlyph {
+                x: glyph.x + offset,
+                cluster: range.start + glyph.cluster,
+                fallback: uses_fallback,
+                ..*glyph
+            }));
+            run.width += advance;
+        }
+
+        if rtl {
+            mirror(&mut run, font, fallback, spec.size);
+        }
+        run
+    }
+}
+
+fn has_glyph(font: &Font, c: char) -> bool {
+    font.glyph_for_char(c).map(|glyph| glyph != 0).unwrap_or(false)
+}
+
+// splits text where it changes between the font and the fallback: graphemes the font has
+// nothing for, and emoji asked to be drawn as emoji, go to the fallback when it has them
+fn segments(font: &Font, fallback: Option<&Font>, text: &str) -> Vec<(Range<usize>, bool)> {
+    let mut segments: Vec<(Range<usize>, bool)> = Vec::new();
+    for (start, grapheme) in text.grapheme_indices(true) {
+        let first = grapheme.chars().next().unwrap_or(' ');
+        let uses_fallback = match fallback {
+            Some(fallback) => (!has_glyph(font, first) || grapheme.contains('\u{FE0F}')) && has_glyph(fallback, first),
+            None => false,
+        };
+
+        let end = start + grapheme.len();
+        match segments.last_mut() {
+            Some((range, fallback)) if *fallback == uses_fallback => range.end = end,
+            _ => segments.push((start..end, uses_fallback)),
+        }
+    }
+    segments
+}
+
+// a glyph per char, each placed at the advance of the ones before it. emoji sequences need
+// shaping to be joined up, without it a grapheme from the fallback is drawn as its first char
+#[cfg(not(feature = "shaping"))]
+fn shape_run(font: &Font, text: &str, size: f32, by_grapheme: bool) -> ShapedRun {
+    let mut run = ShapedRun::default();
+    let mut place = |cluster: usize, c: char| {
+        let glyph = glyph_for_char(font, c);
+        run.glyphs.push(PositionedGlyph { glyph, x: run.width, y: 0.0, cluster, fallback: false });
+        run.clusters.push((cluster, run.width));
+        run.width += glyph_advance(font, glyph, size);
+    };
+    if by_grapheme {
+        for (cluster, grapheme) in text.grapheme_indices(true) {
+            place(cluster, grapheme.chars().next().unwrap_or(' '));
+        }
+    } else {
+        for (cluster, c) in text.char_indices() {
+            place(cluster, c);
+        }
+    }
+    run
+}
+
+// harfbuzz picks the glyphs, so ligatures form, marks sit on their base, scripts join up and
+// emoji sequences become one emoji. skribo doesn't say which text a glyph came from, so shaped
+// glyphs have no cluster, and columns are worked out from the widths of whole prefixes instead
+#[cfg(feature = "shaping")]
+fn shape_run(font: &Font, text: &str, size: f32, _by_grapheme: bool) -> ShapedRun {
+    let style = skribo::TextStyle { size };
+    let layout = skribo::layout_run(&style, &skribo::FontRef::new(font.clone()), text);
+    ShapedRun {
+        glyphs: layout.glyphs.iter()
+            // skribo's offsets are y up, like the font's
+            .map(|g| PositionedGlyph { glyph: g.glyph_id, x: g.offset.x(), y: -g.offset.y(), cluster: 0, fallback: false })
+            .collect(),
+        width: layout.advance.x(),
+        clusters: Vec::new(),
+    }
+}
+
+// each glyph goes as far from the right edge as it was from the left
+fn mirror(run: &mut ShapedRun, font: &Font, fallback: Option<&Font>, size: f32) {
+    for glyph in &mut run.glyphs {
+        let glyph_font = match fallback {
+            Some(fallback) if glyph.fallback => fallback,
+            _ => font,
+        };
+        glyph.x = run.width - glyph.x - glyph_advance(glyph_font, glyph.glyph, size);
+    }
+}
+
+fn load_fallback() -> Option<(FontKey, Font)> {
+    let source = SystemSource::new();
+    FALLBACK_FAMILIES.iter().find_map(|family| {
+        let font = source.select_family_by_name(family).ok()?.fonts().first()?.load().ok()?;
+        Some(((FontFace::Family(family.to_string()), false, false), font))
+    })
+}
+
+// a face we can't find falls back to the system monospace font, rather than drawing nothing
+fn load_font(spec: &FontSpec) -> Font {
+    let mut properties = Properties::new();
+    if spec.bold {
+        properties.weight(Weight::BOLD);
+    }
+    if spec.italic {
+        properties.style(Style::Italic);
+    }
+
+    let loaded = match &spec.face {
+        FontFace::Path(path) => Font::from_path(path, 0).ok(),
+        FontFace::Family(family) => SystemSource::new()
+            .select_best_match(&[FamilyName::Title(family.clone())], &properties)
+            .ok()
+            .and_then(|handle| handle.load().ok()),
+    };
+
+    if let Some(font) = loaded {
+        return font;
+    }
+
+    let fallback = SystemSource::new()
+        .select_best_match(&[FamilyName::Monospace], &properties)
+        .ok()
+        .and_then(|handle| handle.load().ok());
+    match fallback {
+        Some(font) => font,
+        None => panic!("couldn't load {:?} or any monospace font", spec.face),
+    }
+}
+
+/// The glyph for a character, or the font's `?` when it has nothing for it.
+pub fn glyph_for_char(font: &Font, c: char) -> u32 {
+    font.glyph_for_char(c)
+        .or_else(|| font.glyph_for_char('?'))
+        .unwrap_or(0)
+}
+
+/// How far the pen moves after drawing `glyph` at `size`, in pixels.
+pub fn glyph_advance(font: &Font, glyph: u32, size: f32) -> f32 {
+    let units_per_em = font.metrics().units_per_em as f32;
+    match font.advance(glyph) {
+        Ok(advance) => advance.x() * size / units_per_em,
+        Err(_) => 0.0,
+    }
+}
+
+impl MeasureText for FontCache {
+    fn text_width(&mut self, text: &str, spec: &FontSpec) -> f32 {
+        if text.is_empty() {
+            return 0.0;
+        }
+        self.shape(text, spec, false).width
+    }
+
+    // from the run that draws the text, rather than shaping each prefix and crowding the runs
+    // of real lines out of the cache
+    fn cluster_xs(&mut self, text: &str, spec: &FontSpec) -> Vec<(usize, f32)> {
+        let run = self.shape(text, spec, false);
+        let mut xs = if run.clusters.is_empty() && !text.is_empty() {
+            // shaped runs don't say, so each grapheme's prefix is measured, but not kept
+            text.grapheme_indices(true)
+                .map(|(offset, _)| (offset, self.shape_uncached(&text[..offset], spec, false).width))
+                .collect()
+        } else {
+            run.clusters.clone()
+        };
+        xs.push((text.len(), run.width));
+        xs
+    }
+
+    fn line_metrics(&mut self, spec: &FontSpec) -> LineMetrics {
+        let metrics = self.font(spec).metrics();
+        let scale = spec.size / metrics.units_per_em as f32;
+        LineMetrics {
+            ascent: metrics.ascent * scale,
+            descent: -metrics.descent * scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_graphemes_take_two_columns() {
+        assert_eq!(grapheme_columns("a"), 1);
+        assert_eq!(grapheme_columns("e\u{301}"), 1);
+        assert_eq!(grapheme_columns("\u{1F600}"), 2);
+        assert_eq!(grapheme_columns("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}"), 2);
+        assert_eq!(grapheme_columns("\u{6F22}"), 2);
+        // a heart is narrow as text, but wide when asked to be an emoji
+        assert_eq!(grapheme_columns("\u{2764}"), 1);
+        assert_eq!(grapheme_columns("\u{2764}\u{FE0F}"), 2);
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use font_kit::canvas::{ Canvas, Format, RasterizationOptions };
+use font_kit::font::Font;
+use font_kit::hinting::HintingOptions;
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::vec2f;
+
+use crate::fonts::FontKey;
+use crate::lru::Lru;
+
+// glyphs are rasterized at quarter pixel offsets, so each is drawn at most 16 ways at a size
+const SUBPIXEL_STEPS: f32 = 4.0;
+
+/// A glyph's coverage, one byte a pixel, with its top left relative to the pen position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RasterGlyph {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+    pub coverage: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: FontKey,
+    glyph: u32,
+    size: u32,
+    subpixel: (u8, u8),
+}
+
+/// Rasterized glyphs for the software renderer, so a glyph is rasterized once for each size
+/// and subpixel offset it's drawn at rather than every time it's on screen.
+pub struct GlyphAtlas {
+    // None for glyphs with nothing to draw, like spaces
+    glyphs: Lru<GlyphKey, Option<Arc<RasterGlyph>>>,
+}
+
+impl GlyphAtlas {
+    /// Keeps about `bytes` of glyphs. With 0, every glyph is rasterized every time it's drawn.
+    pub fn new(bytes: usize) -> Self {
+        GlyphAtlas { glyphs: Lru::new(bytes) }
+    }
+
+    /// `glyph` rasterized at `size` with the pen at (`x`, `y`), and the whole pixel to draw it from.
+    pub fn glyph(&mut self, font: &Font, font_key: &FontKey, glyph: u32, size: f32, x: f32, y: f32)
+                 -> (Option<Arc<RasterGlyph>>, i32, i32) {
+        // snap the pen to the nearest step, then split it into whole pixels and steps
+        let snap = |offset: f32| {
+            let snapped = (offset * SUBPIXEL_STEPS).round() / SUBPIXEL_STEPS;
+            (snapped.floor() as i32, ((snapped - snapped.floor()) * SUBPIXEL_STEPS) as u8)
+        };
+        let (pen_x, step_x) = snap(x);
+        let (pen_y, step_y) = snap(y);
+        let subpixel = (step_x, step_y);
+        let key = GlyphKey { font: font_key.clone(), glyph, size: size.to_bits(), subpixel };
+
+        if let Some(raster) = self.glyphs.get(&key) {
+            return (raster.clone(), pen_x, pen_y);
+        }
+
+        let raster = rasterize(font, glyph, size, subpixel).map(Arc::new);
+        let cost = raster.as_ref().map(|r| r.coverage.len()).unwrap_or(0) + std::mem::size_of::<GlyphKey>() + 32;
+        self.glyphs.insert(key, raster.clone(), cost);
+        (raster, pen_x, pen_y)
+    }
+}
+
+fn rasterize(font: &Font, glyph: u32, size: f32, subpixel: (u8, u8)) -> Option<RasterGlyph> {
+    let offset = vec2f(subpixel.0 as f32 / SUBPIXEL_STEPS, subpixel.1 as f32 / SUBPIXEL_STEPS);
+    let transform = Transform2F::from_translation(offset);
+    let bounds = font.raster_bounds(glyph, size, transform, HintingOptions::None, RasterizationOptions::GrayscaleAa).ok()?;
+    if bounds.width() <= 0 || bounds.height() <= 0 {
+        return None;
+    }
+
+    let mut canvas = Canvas::new(bounds.size(), Format::A8);
+    let transform = Transform2F::from_translation(-bounds.origin().to_f32()) * transform;
+    font.rasterize_glyph(&mut canvas, glyph, size, transform, HintingOptions::None, RasterizationOptions::GrayscaleAa).ok()?;
+
+    // the canvas's rows can be padded, keep just the glyph
+    let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+    let mut coverage = Vec::with_capacity((width * height) as usize);
+    for row in 0..height as usize {
+        let start = row * canvas.stride;
+        coverage.extend_from_slice(&canvas.pixels[start..start + width as usize]);
+    }
+    Some(RasterGlyph { left: bounds.origin_x(), top: bounds.origin_y(), width, height, coverage })
+}
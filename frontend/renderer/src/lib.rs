@@ -1,6 +1,16 @@
+pub mod color_glyphs;
+pub mod display_list;
+pub mod fonts;
+pub mod glyph_atlas;
+mod lru;
 pub mod pathfinder_renderer;
+pub mod software_renderer;
 
+use display_list::{ Frame, RenderTarget };
+
+/// Something that can draw a laid out frame. Frames are built once, with no idea what will
+/// draw them, and handed to whichever backend is in use.
 pub trait Renders : 
 {
-    fn render(&mut self);
-}
\ No newline at end of file
+    fn render(&mut self, frame: &Frame, target: &RenderTarget);
+}
@@ -0,0 +1,108 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::hash::Hash;
+
+/// A map holding at most `capacity` worth of values, dropping the least recently used to make
+/// room. What each value is worth is up to the caller, usually roughly how many bytes it holds.
+pub struct Lru<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    // when each key was last used, oldest first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    cost: usize,
+    capacity: usize,
+}
+
+struct Entry<V> {
+    value: V,
+    cost: usize,
+    last_used: u64,
+}
+
+impl<K: Clone + Eq + Hash, V> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Lru {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            cost: 0,
+            capacity,
+        }
+    }
+
+    /// The value for `key`, which is now the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        if let Some(key) = self.order.remove(&entry.last_used) {
+            self.order.insert(self.tick, key);
+        }
+        entry.last_used = self.tick;
+        Some(&entry.value)
+    }
+
+    /// Adds `value`, evicting the least recently used until it fits.
+    /// A value worth more than the whole capacity isn't kept at all.
+    pub fn insert(&mut self, key: K, value: V, cost: usize) {
+        self.remove(&key);
+        if cost > self.capacity {
+            return;
+        }
+        while self.cost + cost > self.capacity && self.evict_oldest() {}
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, Entry { value, cost, last_used: self.tick });
+        self.cost += cost;
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.last_used);
+        self.cost -= entry.cost;
+        Some(entry.value)
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = match self.order.keys().next() {
+            Some(tick) => *tick,
+            None => return false,
+        };
+        if let Some(key) = self.order.remove(&oldest) {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.cost -= entry.cost;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_least_recently_used_to_stay_in_capacity() {
+        let mut lru = Lru::new(3);
+        lru.insert("a", 1, 1);
+        lru.insert("b", 2, 1);
+        lru.insert("c", 3, 1);
+
+        // using a makes b the oldest
+        assert_eq!(lru.get(&"a"), Some(&1));
+        lru.insert("d", 4, 1);
+        assert_eq!(lru.get(&"b"), None);
+        assert_eq!(lru.get(&"a"), Some(&1));
+
+        // something worth two pushes out the two oldest, c and d
+        lru.insert("e", 5, 2);
+        assert_eq!(lru.get(&"c"), None);
+        assert_eq!(lru.get(&"d"), None);
+        assert_eq!(lru.get(&"a"), Some(&1));
+        assert_eq!(lru.get(&"e"), Some(&5));
+
+        // and something too big for the whole cache isn't kept, or allowed to evict anything
+        lru.insert("f", 6, 4);
+        assert_eq!(lru.get(&"f"), None);
+        assert_eq!(lru.get(&"a"), Some(&1));
+    }
+}
@@ -1,17 +1,331 @@
+use std::sync::Arc;
+
+use foreign_types::ForeignTypeRef;
+use metal::{ CAMetalLayer, CoreAnimationLayer, CoreAnimationLayerRef };
+use pathfinder_canvas::{ Canvas, CanvasFontContext, CanvasRenderingContext2D, FillRule, FillStyle, Path2D };
+use pathfinder_color::{ ColorF, ColorU };
+use pathfinder_content::pattern::Image as PatternImage;
+use pathfinder_geometry::rect::RectF;
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::{ vec2f, vec2i, Vector2I };
+use pathfinder_gpu::Device;
+use pathfinder_metal::MetalDevice;
+use pathfinder_renderer::concurrent::rayon::RayonExecutor;
+use pathfinder_renderer::concurrent::scene_proxy::SceneProxy;
+use pathfinder_renderer::gpu::options::{ DestFramebuffer, RendererMode, RendererOptions };
+use pathfinder_renderer::gpu::renderer::Renderer;
+use pathfinder_renderer::options::BuildOptions;
+use pathfinder_renderer::scene::Scene;
+use pathfinder_resources::embedded::EmbeddedResourceLoader;
+use sdl2::hint;
+use sdl2::render::WindowCanvas;
+use sdl2::video::{ VideoSubsystem, Window };
+use sdl2_sys::SDL_RenderGetMetalLayer;
+
+use font_kit::font::Font;
+
+use crate::color_glyphs::{ ColorGlyphCache, COLOR_GLYPH_CACHE_BYTES };
+use crate::display_list::{ Color, DisplayItem, Frame, Image, Rect, RenderTarget, TextRun, CARET_WIDTH };
+use crate::fonts::{ self, FontCache, FontKey, MeasureText, ShapedRun };
 use crate::Renders;
 
-pub struct PathfinderRenderer{
+/// Draws frames to a window with pathfinder on Metal. Owns the window and everything
+/// between it and pathfinder, so all a caller does is hand it frames and present them.
+pub struct PathfinderRenderer {
+    // sdl's renderer owns the metal layer, so it has to live as long as we draw to the layer
+    window_canvas: WindowCanvas,
+    metal_layer: CoreAnimationLayer,
+    renderer: Renderer<MetalDevice>,
+    font_context: CanvasFontContext,
+    fonts: FontCache,
+    colors: ColorGlyphCache,
+    // the window's size in points, which is what layout and mouse positions are in
+    size: Vector2I,
+    // the window's size in pixels, bigger than `size` on a HiDPI display
+    drawable_size: Vector2I,
+    scene: Option<Scene>,
+}
+
+impl PathfinderRenderer {
+    /// Opens a window of `size` and sets up Metal and pathfinder to draw to it.
+    pub fn new(video: &VideoSubsystem, title: &str, size: Vector2I) -> Self {
+        // has to be set before sdl creates the window's renderer
+        assert!(hint::set("SDL_RENDER_DRIVER", "metal"));
+
+        let window = video.window(title, size.x() as u32, size.y() as u32)
+                          .opengl()
+                          .allow_highdpi()
+                          .resizable()
+                          .build()
+                          .unwrap();
+
+        // Create a Metal context.
+        let window_canvas = window.into_canvas().present_vsync().build().unwrap();
+        let metal_layer = unsafe {
+            CoreAnimationLayerRef::from_ptr(SDL_RenderGetMetalLayer(window_canvas.raw()) as *mut CAMetalLayer)
+        }.to_owned();
+        let metal_device = metal_layer.device();
+        let drawable = metal_layer.next_drawable().unwrap();
+
+        // Create a Pathfinder renderer.
+        let device = unsafe {
+            MetalDevice::new(metal_device, drawable.clone())
+        };
+        let (size, drawable_size) = window_sizes(&window_canvas);
+        let mode = RendererMode::default_for_device(&device);
+        let options = RendererOptions {
+            dest: DestFramebuffer::full_window(drawable_size),
+            background_color: Some(ColorF::white()),
+            ..RendererOptions::default()
+        };
+        let renderer = Renderer::new(device, &EmbeddedResourceLoader, mode, options);
+
+        PathfinderRenderer {
+            window_canvas,
+            metal_layer,
+            renderer,
+            font_context: CanvasFontContext::from_system_source(),
+            fonts: FontCache::new(),
+            colors: ColorGlyphCache::new(COLOR_GLYPH_CACHE_BYTES),
+            size,
+            drawable_size,
+            scene: None,
+        }
+    }
+
+    pub fn window(&self) -> &Window {
+        self.window_canvas.window()
+    }
+
+    pub fn window_mut(&mut self) -> &mut Window {
+        self.window_canvas.window_mut()
+    }
+
+    pub fn fonts(&mut self) -> &mut FontCache {
+        &mut self.fonts
+    }
+
+    /// What frames should be rendered at to fill the window.
+    pub fn target(&self) -> RenderTarget {
+        RenderTarget::new(self.drawable_size.x() as u32, self.drawable_size.y() as u32, self.scale_factor())
+    }
+
+    /// How many pixels there are to a point. Can be fractional, and changes when the
+    /// window moves to a monitor with a different scale.
+    pub fn scale_factor(&self) -> f32 {
+        if self.size.x() == 0 {
+            return 1.0;
+        }
+        self.drawable_size.x() as f32 / self.size.x() as f32
+    }
+
+    /// Picks up a new window size or scale factor, after a resize or a move to another monitor.
+    /// Returns whether anything changed, and so whether the window needs redrawing.
+    pub fn resize(&mut self) -> bool {
+        let (size, drawable_size) = window_sizes(&self.window_canvas);
+        if size == self.size && drawable_size == self.drawable_size {
+            return false;
+        }
+
+        self.size = size;
+        self.drawable_size = drawable_size;
+        self.renderer.replace_dest_framebuffer(DestFramebuffer::full_window(drawable_size));
+        true
+    }
+
+    /// Puts the most recently rendered frame on screen. Does nothing if there's no new frame.
+    pub fn present(&mut self) {
+        let scene = match self.scene.take() {
+            Some(s) => s,
+            None => return,
+        };
+
+        let drawable = self.metal_layer.next_drawable().unwrap();
+        self.renderer.device_mut().swap_drawable(drawable.clone());
 
+        let mut scene = SceneProxy::from_scene(scene, self.renderer.mode().level, RayonExecutor);
+        scene.build_and_render(&mut self.renderer, BuildOptions::default());
+        self.renderer.device().present_drawable(drawable);
+    }
 }
 
 impl PathfinderRenderer {
-    pub fn new() -> Self {
-        // create the metal context and everything here
-        todo!();
-        PathfinderRenderer { }
+    // pathfinder lays text out left to right in one font, so right to left runs, and runs with
+    // glyphs from the fallback font, are drawn where our own shaping put each glyph. colour
+    // glyphs are drawn as pictures, rasterized at the device size
+    fn fill_shaped_text(&mut self, canvas: &mut CanvasRenderingContext2D, run: &TextRun, scale: f32) {
+        let shaped = self.fonts.shape(&run.text, &run.font, run.rtl);
+        let primary = (fonts::font_key(&run.font), self.fonts.font(&run.font).clone());
+        let fallback = self.fonts.fallback_font().map(|(key, font)| (key.clone(), font.clone()));
+
+        let mut plain = Vec::new();
+        for (index, glyph) in shaped.glyphs.iter().enumerate() {
+            let (key, font) = match &fallback {
+                Some(fallback) if glyph.fallback => fallback,
+                _ => &primary,
+            };
+            match self.colors.glyph(font, key, glyph.glyph, run.font.size * scale, run.color) {
+                Some(raster) => {
+                    let rect = Rect::new(run.origin.x + glyph.x + raster.left as f32 / scale,
+                                         run.origin.y + glyph.y + raster.top as f32 / scale,
+                                         raster.image.width as f32 / scale,
+                                         raster.image.height as f32 / scale);
+                    draw_image(canvas, &rect, &raster.image);
+                }
+                None => plain.push(index),
+            }
+        }
+        fill_glyphs(canvas, run, &shaped, &plain, &primary.1, fallback.as_ref().map(|(_, font)| font));
     }
 }
 
 impl Renders for PathfinderRenderer {
+    fn render(&mut self, frame: &Frame, target: &RenderTarget) {
+        // pathfinder draws the whole scene every time, all damage tells us is whether to bother
+        if frame.damage.as_ref().map(|damage| damage.is_empty()).unwrap_or(false) {
+            return;
+        }
+
+        let size = vec2i(target.width as i32, target.height as i32).to_f32();
+        let mut canvas = Canvas::new(size).get_context_2d(self.font_context.clone());
+
+        canvas.set_fill_style(fill_style(frame.background));
+        canvas.fill_rect(RectF::new(vec2f(0.0, 0.0), size));
+
+        // everything in the frame is logical pixels, the canvas is device pixels. text is
+        // outlines, so this scales font sizes too, and glyphs stay sharp at any scale
+        canvas.set_transform(&Transform2F::from_scale(target.scale_factor));
+
+        // setting a font builds pathfinder a new font collection, so only do it when it changes
+        let mut current_font: Option<FontKey> = None;
+        for item in &frame.items {
+            match item {
+                DisplayItem::Rect { rect, color } => {
+                    canvas.set_fill_style(fill_style(*color));
+                    canvas.fill_rect(rect_f(rect));
+                }
+                DisplayItem::Text(run) => {
+                    let key = fonts::font_key(&run.font);
+                    if current_font.as_ref() != Some(&key) {
+                        canvas.set_font(self.fonts.font(&run.font).clone());
+                        current_font = Some(key);
+                    }
+                    canvas.set_font_size(run.font.size);
+                    canvas.set_fill_style(fill_style(run.color));
+                    let placed = run.rtl
+                        || self.fonts.shape(&run.text, &run.font, false).glyphs.iter().any(|glyph| glyph.fallback)
+                        || self.colors.has_colors(self.fonts.font(&run.font), &key);
+                    if placed {
+                        self.fill_shaped_text(&mut canvas, run, target.scale_factor);
+                        // it may have switched to the fallback font
+                        current_font = None;
+                    } else {
+                        canvas.fill_text(&run.text, vec2f(run.origin.x, run.origin.y));
+                    }
+
+                    if run.underline {
+                        let width = self.fonts.text_width(&run.text, &run.font);
+                        canvas.fill_rect(RectF::new(vec2f(run.origin.x, run.origin.y + 1.0), vec2f(width, 1.0)));
+                    }
+                }
+                DisplayItem::Caret { position, height, color } => {
+                    canvas.set_fill_style(fill_style(*color));
+                    let caret = Rect::new(position.x - CARET_WIDTH / 2.0, position.y, CARET_WIDTH, *height);
+                    canvas.fill_rect(rect_f(&caret));
+                }
+                DisplayItem::PushClip(rect) => {
+                    // restore pops the clip, and the transform and styles set since, with it
+                    canvas.save();
+                    let mut path = Path2D::new();
+                    path.rect(rect_f(rect));
+                    canvas.clip_path(path, FillRule::Winding);
+                }
+                DisplayItem::PopClip => {
+                    // the font set inside the clip goes with it
+                    canvas.restore();
+                    current_font = None;
+                }
+                DisplayItem::Image { rect, image } => draw_image(&mut canvas, rect, image),
+            }
+        }
+
+        self.scene = Some(canvas.into_canvas().into_scene());
+    }
+}
 
-}
\ No newline at end of file
+// the glyphs at `indices` in one go, each from its own font
+#[cfg(feature = "shaping")]
+fn fill_glyphs(canvas: &mut CanvasRenderingContext2D,
+               run: &TextRun,
+               shaped: &ShapedRun,
+               indices: &[usize],
+               font: &Font,
+               fallback: Option<&Font>) {
+    let font = skribo::FontRef::new(font.clone());
+    let fallback = fallback.map(|fallback| skribo::FontRef::new(fallback.clone()));
+    let layout = skribo::Layout {
+        size: run.font.size,
+        glyphs: indices.iter()
+            .map(|&index| {
+                let g = &shaped.glyphs[index];
+                let font = match &fallback {
+                    Some(fallback) if g.fallback => fallback.clone(),
+                    _ => font.clone(),
+                };
+                skribo::Glyph { font, glyph_id: g.glyph, offset: vec2f(g.x, -g.y) }
+            })
+            .collect(),
+        advance: vec2f(shaped.width, 0.0),
+    };
+    canvas.fill_text(&layout, vec2f(run.origin.x, run.origin.y));
+}
+
+// without shaping there's a glyph per char, so each char goes where its glyph was placed
+#[cfg(not(feature = "shaping"))]
+fn fill_glyphs(canvas: &mut CanvasRenderingContext2D,
+               run: &TextRun,
+               shaped: &ShapedRun,
+               indices: &[usize],
+               font: &Font,
+               fallback: Option<&Font>) {
+    let mut buffer = [0; 4];
+    let mut using_fallback = false;
+    for &index in indices {
+        let glyph = &shaped.glyphs[index];
+        let c = match run.text[glyph.cluster..].chars().next() {
+            Some(c) => c,
+            None => continue,
+        };
+        if let Some(fallback) = fallback {
+            if glyph.fallback != using_fallback {
+                canvas.set_font(if glyph.fallback { fallback.clone() } else { font.clone() });
+                using_fallback = glyph.fallback;
+            }
+        }
+        canvas.fill_text(c.encode_utf8(&mut buffer), vec2f(run.origin.x + glyph.x, run.origin.y));
+    }
+}
+
+// the window's size in points, and the size of what we draw to in pixels
+fn window_sizes(window_canvas: &WindowCanvas) -> (Vector2I, Vector2I) {
+    let (width, height) = window_canvas.window().size();
+    let (drawable_width, drawable_height) = window_canvas.output_size().unwrap();
+    (vec2i(width as i32, height as i32), vec2i(drawable_width as i32, drawable_height as i32))
+}
+
+fn draw_image(canvas: &mut CanvasRenderingContext2D, rect: &Rect, image: &Image) {
+    let pixels = image.pixels
+        .chunks(4)
+        .map(|p| ColorU::new(p[0], p[1], p[2], p[3]))
+        .collect();
+    let size = vec2i(image.width as i32, image.height as i32);
+    canvas.draw_image(PatternImage::new(size, Arc::new(pixels)), rect_f(rect));
+}
+
+fn rect_f(rect: &Rect) -> RectF {
+    RectF::new(vec2f(rect.x, rect.y), vec2f(rect.width, rect.height))
+}
+
+fn fill_style(color: Color) -> FillStyle {
+    FillStyle::Color(ColorU::new(color.r, color.g, color.b, color.a))
+}
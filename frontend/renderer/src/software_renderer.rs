@@ -0,0 +1,233 @@
+pub use crate::display_list::Image;
+use crate::display_list::{ Color, DisplayItem, Frame, Point, Rect, RenderTarget, TextRun, CARET_WIDTH };
+use crate::color_glyphs::{ ColorGlyphCache, COLOR_GLYPH_CACHE_BYTES };
+use crate::fonts::{ self, FontCache };
+use crate::glyph_atlas::GlyphAtlas;
+use crate::Renders;
+
+/// Roughly how many bytes of rasterized glyphs a renderer keeps by default.
+pub const GLYPH_CACHE_BYTES: usize = 8 * 1024 * 1024;
+
+/// Draws frames on the CPU into an `Image`. Doesn't need a window or a GPU, so it's what
+/// we use for snapshot tests and anywhere else we want the pixels of a frame.
+pub struct SoftwareRenderer {
+    fonts: FontCache,
+    glyphs: GlyphAtlas,
+    colors: ColorGlyphCache,
+    image: Image,
+    scale: f32,
+    // in device pixels, innermost last
+    clips: Vec<Rect>,
+}
+
+impl SoftwareRenderer {
+    pub fn new() -> Self {
+        SoftwareRenderer::with_caches(fonts::RUN_CACHE_BYTES, GLYPH_CACHE_BYTES)
+    }
+
+    /// A renderer keeping about `run_bytes` of shaped runs and `glyph_bytes` of rasterized glyphs.
+    /// With both 0 nothing is kept between frames, which is what the caches are measured against.
+    pub fn with_caches(run_bytes: usize, glyph_bytes: usize) -> Self {
+        SoftwareRenderer {
+            fonts: FontCache::with_run_cache(run_bytes),
+            glyphs: GlyphAtlas::new(glyph_bytes),
+            colors: ColorGlyphCache::new(COLOR_GLYPH_CACHE_BYTES),
+            image: Image::new(0, 0, Color::white()),
+            scale: 1.0,
+            clips: Vec::new(),
+        }
+    }
+
+    /// The most recently rendered frame.
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn fonts(&mut self) -> &mut FontCache {
+        &mut self.fonts
+    }
+
+    fn clip(&self) -> Rect {
+        match self.clips.last() {
+            Some(clip) => *clip,
+            None => Rect::new(0.0, 0.0, self.image.width as f32, self.image.height as f32),
+        }
+    }
+
+    fn push_clip(&mut self, rect: &Rect) {
+        let clip = self.clip().intersection(&rect.scale(self.scale));
+        self.clips.push(clip);
+    }
+
+    // whole pixels whose centres are inside the rect, and inside the clip
+    fn pixel_bounds(&self, rect: &Rect) -> (i32, i32, i32, i32) {
+        let rect = rect.intersection(&self.clip());
+        let x0 = (rect.x - 0.5).ceil().max(0.0) as i32;
+        let y0 = (rect.y - 0.5).ceil().max(0.0) as i32;
+        let x1 = (rect.max_x() - 0.5).ceil() as i32;
+        let y1 = (rect.max_y() - 0.5).ceil() as i32;
+        (x0, y0, x1, y1)
+    }
+
+    fn fill_rect(&mut self, rect: &Rect, color: Color) {
+        let (x0, y0, x1, y1) = self.pixel_bounds(&rect.scale(self.scale));
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.image.blend(x, y, color, 255);
+            }
+        }
+    }
+
+    fn draw_caret(&mut self, position: &Point, height: f32, color: Color) {
+        let rect = Rect::new(position.x - CARET_WIDTH / 2.0, position.y, CARET_WIDTH, height);
+        self.fill_rect(&rect, color);
+    }
+
+    // nearest neighbour, images are icons and the like, not worth filtering
+    fn draw_image(&mut self, rect: &Rect, image: &Image) {
+        let dest = rect.scale(self.scale);
+        if image.width == 0 || image.height == 0 || dest.width <= 0.0 || dest.height <= 0.0 {
+            return;
+        }
+
+        let (x0, y0, x1, y1) = self.pixel_bounds(&dest);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let u = ((x as f32 + 0.5 - dest.x) / dest.width * image.width as f32) as u32;
+                let v = ((y as f32 + 0.5 - dest.y) / dest.height * image.height as f32) as u32;
+                let color = image.pixel(u.min(image.width - 1), v.min(image.height - 1));
+                self.image.blend(x, y, color, 255);
+            }
+        }
+    }
+
+    fn fill_text(&mut self, run: &TextRun) {
+        let shaped = self.fonts.shape(&run.text, &run.font, run.rtl);
+        let primary = (fonts::font_key(&run.font), self.fonts.font(&run.font).clone());
+        let fallback = match shaped.glyphs.iter().any(|glyph| glyph.fallback) {
+            true => self.fonts.fallback_font().map(|(key, font)| (key.clone(), font.clone())),
+            false => None,
+        };
+        // shaped runs are in logical pixels, glyphs are rasterized at the device size
+        let size = run.font.size * self.scale;
+        let origin = Point::new(run.origin.x * self.scale, run.origin.y * self.scale);
+        let clip = self.clip();
+        let inside = |x: i32, y: i32| x as f32 + 0.5 >= clip.x && (x as f32 + 0.5) < clip.max_x()
+            && y as f32 + 0.5 >= clip.y && (y as f32 + 0.5) < clip.max_y();
+
+        for positioned in &shaped.glyphs {
+            let (font_key, font) = match &fallback {
+                Some(fallback) if positioned.fallback => fallback,
+                _ => &primary,
+            };
+            let pen_x = origin.x + positioned.x * self.scale;
+            let pen_y = origin.y + positioned.y * self.scale;
+
+            // colour glyphs bring their own pixels, and are drawn on whole pixels
+            if let Some(raster) = self.colors.glyph(font, font_key, positioned.glyph, size, run.color) {
+                let left = pen_x.round() as i32 + raster.left;
+                let top = pen_y.round() as i32 + raster.top;
+                for row in 0..raster.image.height {
+                    for column in 0..raster.image.width {
+                        let (x, y) = (left + column as i32, top + row as i32);
+                        let pixel = raster.image.pixel(column, row);
+                        if inside(x, y) && pixel.a > 0 {
+                            self.image.blend(x, y, pixel, 255);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let (raster, pen_x, pen_y) = self.glyphs.glyph(font, font_key, positioned.glyph, size, pen_x, pen_y);
+            let raster = match raster {
+                Some(r) => r,
+                None => continue,
+            };
+
+            let left = pen_x + raster.left;
+            let top = pen_y + raster.top;
+            for row in 0..raster.height as i32 {
+                for column in 0..raster.width as i32 {
+                    let (x, y) = (left + column, top + row);
+                    let coverage = raster.coverage[(row * raster.width as i32 + column) as usize];
+                    if inside(x, y) && coverage > 0 {
+                        self.image.blend(x, y, run.color, coverage);
+                    }
+                }
+            }
+        }
+
+        if run.underline {
+            self.fill_rect(&Rect::new(run.origin.x, run.origin.y + 1.0, shaped.width, 1.0), run.color);
+        }
+    }
+}
+
+impl Renders for SoftwareRenderer {
+    fn render(&mut self, frame: &Frame, target: &RenderTarget) {
+        let same_target = self.image.width == target.width && self.image.height == target.height
+            && self.scale == target.scale_factor;
+        self.scale = target.scale_factor;
+        self.clips.clear();
+
+        // with the last frame still in the image, only what changed has to be drawn again
+        match &frame.damage {
+            Some(damage) if same_target => {
+                let area = match damage.split_first() {
+                    Some((first, rest)) => rest.iter().fold(*first, |area, rect| area.union(rect)),
+                    None => return,
+                };
+                self.push_clip(&area);
+                self.fill_rect(&area, frame.background);
+            }
+            _ => self.image = Image::new(target.width, target.height, frame.background),
+        }
+
+        for item in &frame.items {
+            match item {
+                DisplayItem::Rect { rect, color } => self.fill_rect(rect, *color),
+                DisplayItem::Text(run) => self.fill_text(run),
+                DisplayItem::Caret { position, height, color } => self.draw_caret(position, *height, *color),
+                DisplayItem::PushClip(rect) => self.push_clip(rect),
+                DisplayItem::PopClip => {
+                    self.clips.pop();
+                }
+                DisplayItem::Image { rect, image } => self.draw_image(rect, image),
+            }
+        }
+        self.clips.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clips_nest_and_scale_with_the_target() {
+        let red = Color::rgb(255, 0, 0);
+        let mut frame = Frame::new(10.0, 10.0, Color::white());
+        frame.push_clip(Rect::new(0.0, 0.0, 6.0, 6.0));
+        frame.push_clip(Rect::new(2.0, 2.0, 8.0, 8.0));
+        frame.fill_rect(Rect::new(0.0, 0.0, 10.0, 10.0), red);
+        frame.pop_clip();
+        frame.caret(Point::new(1.0, 0.0), 10.0, Color::black());
+        frame.pop_clip();
+
+        let mut renderer = SoftwareRenderer::new();
+        renderer.render(&frame, &RenderTarget::new(20, 20, 2.0));
+        let image = renderer.image();
+
+        // only the overlap of both clips is filled, in device pixels. the caret covers x 0 to 4
+        assert_eq!(image.pixel(5, 3), Color::white());
+        assert_eq!(image.pixel(3, 5), Color::black());
+        assert_eq!(image.pixel(4, 4), red);
+        assert_eq!(image.pixel(11, 11), red);
+        assert_eq!(image.pixel(12, 12), Color::white());
+
+        // the caret is still clipped by the outer clip after the inner one is popped
+        assert_eq!(image.pixel(1, 5), Color::black());
+        assert_eq!(image.pixel(1, 15), Color::white());
+    }
+}
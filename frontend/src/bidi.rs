@@ -0,0 +1,249 @@
+//! Lays out lines with text running in both directions. Runs of right to left text, like a
+//! Hebrew or Arabic string literal, are drawn in visual order, while offsets into the line stay
+//! in the logical order the buffer has them in.
+
+use std::ops::Range;
+
+use unicode_bidi::BidiInfo;
+use xi_path_renderer::display_list::FontSpec;
+use xi_path_renderer::fonts::MeasureText;
+
+use crate::line::Line;
+use crate::settings::ViewSettings;
+
+/// A run of a line all going one direction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VisualRun {
+    /// Logical byte offsets into the line's text.
+    pub range: Range<usize>,
+    pub rtl: bool,
+    /// Where the run starts from the left of the line, and how wide it's drawn.
+    pub x: f32,
+    pub width: f32,
+    /// Each grapheme boundary in the run, and how far it's drawn from the run's start,
+    /// its left edge or its right edge for right to left text.
+    carets: Vec<(usize, f32)>,
+}
+
+/// A piece of a logical range that's drawn in one go, from `left` to `right`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VisualPiece {
+    pub range: Range<usize>,
+    pub rtl: bool,
+    pub left: f32,
+    pub right: f32,
+}
+
+/// A line split into runs of one direction, in the order they're drawn left to right.
+/// Lines are still drawn from the left, whatever direction they read in.
+///
+/// Each run is measured once, here, so placing cursors and hit testing don't need the fonts again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VisualLine {
+    pub runs: Vec<VisualRun>,
+    /// Whether the line reads right to left as a whole, going by its first strong character.
+    pub rtl: bool,
+}
+
+impl VisualLine {
+    pub fn new(line: &Line, settings: &ViewSettings, font: &FontSpec, fonts: &mut dyn MeasureText) -> Self {
+        let text = &line.text;
+        let mut directions: Vec<(Range<usize>, bool)> = Vec::new();
+        let mut rtl = false;
+
+        let info = BidiInfo::new(text, None);
+        if !info.has_rtl() {
+            if !text.is_empty() {
+                directions.push((0..text.len(), false));
+            }
+        } else {
+            for (index, paragraph) in info.paragraphs.iter().enumerate() {
+                if index == 0 {
+                    rtl = paragraph.level.is_rtl();
+                }
+                let (levels, runs) = info.visual_runs(paragraph, paragraph.range.clone());
+                for run in runs {
+                    let run_rtl = levels[run.start].is_rtl();
+                    directions.push((run, run_rtl));
+                }
+            }
+        }
+
+        let boundaries = settings.expanded_offsets(text);
+        let mut x = 0.0;
+        let runs = directions.into_iter()
+            .map(|(range, rtl)| {
+                let drawn_start = settings.expand_tabs(&text[..range.start]).len();
+                let clusters = fonts.cluster_xs(&drawn_text(line, settings, range.clone()), font);
+                let width = clusters.last().map(|(_, x)| *x).unwrap_or(0.0);
+
+                // a boundary inside a cluster, like between the letters of a ligature, is drawn
+                // where the cluster starts. both are in order, so one pass pairs them up
+                let mut cluster = 0;
+                let carets = boundaries.iter()
+                    .filter(|(offset, _)| range.start <= *offset && *offset <= range.end)
+                    .map(|(offset, drawn)| {
+                        let drawn = drawn - drawn_start;
+                        while cluster + 1 < clusters.len() && clusters[cluster + 1].0 <= drawn {
+                            cluster += 1;
+                        }
+                        (*offset, clusters[cluster].1)
+                    })
+                    .collect();
+
+                let run = VisualRun { range, rtl, x, width, carets };
+                x += width;
+                run
+            })
+            .collect();
+        VisualLine { runs, rtl }
+    }
+
+    /// Where a cursor at `offset` is drawn. Usually that's one place, but where runs going
+    /// opposite ways meet, the text before the offset ends in one place and the text after it
+    /// starts in another, and the cursor is drawn split across both. The first is where text
+    /// typed in the line's own direction would go.
+    pub fn carets(&self, line: &Line, offset: usize) -> Vec<f32> {
+        let offset = line.clamp_offset(offset);
+        let before = self.runs.iter().find(|run| run.range.start < offset && offset <= run.range.end);
+        let after = self.runs.iter().find(|run| run.range.start <= offset && offset < run.range.end);
+
+        match (before, after) {
+            (Some(before), Some(after)) if before.range != after.range => {
+                let before_x = x_in_run(before, offset);
+                let after_x = x_in_run(after, offset);
+                if before_x == after_x {
+                    vec![before_x]
+                } else if before.rtl == self.rtl {
+                    vec![before_x, after_x]
+                } else {
+                    vec![after_x, before_x]
+                }
+            }
+            (Some(run), _) | (None, Some(run)) => vec![x_in_run(run, offset)],
+            (None, None) => vec![0.0],
+        }
+    }
+
+    /// The main place a cursor at `offset` is drawn, see `carets`.
+    pub fn x(&self, line: &Line, offset: usize) -> f32 {
+        self.carets(line, offset)[0]
+    }
+
+    /// The grapheme boundary drawn nearest `x`, for placing a cursor where a click landed.
+    pub fn offset_at(&self, x: f32) -> usize {
+        // the run under x, or the one at whichever end x is off
+        let run = match self.runs.iter().find(|run| x <= run.x + run.width).or_else(|| self.runs.last()) {
+            Some(run) => run,
+            None => return 0,
+        };
+
+        let mut nearest = (run.range.start, f32::MAX);
+        for (boundary, _) in &run.carets {
+            let distance = (x_in_run(run, *boundary) - x).abs();
+            if distance < nearest.1 {
+                nearest = (*boundary, distance);
+            }
+        }
+        nearest.0
+    }
+
+    /// Splits a logical range at the runs it crosses, left to right as they're drawn.
+    /// A selection over a direction change is drawn as more than one rectangle this way.
+    pub fn pieces(&self, range: Range<usize>) -> Vec<VisualPiece> {
+        let mut pieces = Vec::new();
+        for run in &self.runs {
+            let start = range.start.max(run.range.start);
+            let end = range.end.min(run.range.end);
+            if start >= end {
+                continue;
+            }
+
+            let start_x = x_in_run(run, start);
+            let end_x = x_in_run(run, end);
+            pieces.push(VisualPiece {
+                range: start..end,
+                rtl: run.rtl,
+                left: start_x.min(end_x),
+                right: start_x.max(end_x),
+            });
+        }
+        pieces
+    }
+}
+
+// x of an offset inside a run, counting from the run's right edge when it reads right to left.
+// an offset inside a grapheme is drawn at its start
+fn x_in_run(run: &VisualRun, offset: usize) -> f32 {
+    let within = match run.carets.binary_search_by_key(&offset, |(boundary, _)| *boundary) {
+        Ok(index) => run.carets[index].1,
+        Err(0) => 0.0,
+        Err(index) => run.carets[index - 1].1,
+    };
+    if run.rtl {
+        run.x + run.width - within
+    } else {
+        run.x + within
+    }
+}
+
+/// The text drawn for a range of a line, with tabs expanded to the stops they'd land on in the whole line.
+pub fn drawn_text(line: &Line, settings: &ViewSettings, range: Range<usize>) -> String {
+    let drawn_start = settings.expand_tabs(&line.text[..range.start]).len();
+    settings.expand_tabs(&line.text[..range.end])[drawn_start..].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use xi_path_renderer::display_list::FontFace;
+
+    use super::*;
+    use crate::test_measure::FixedWidth;
+
+    fn font() -> FontSpec {
+        FontSpec::new(FontFace::Family("monospace".to_string()), 12.0)
+    }
+
+    #[test]
+    fn rtl_runs_are_drawn_in_visual_order() {
+        // "s = " then shalom in hebrew, logically first letter to last
+        let line = Line::new("s = \u{5E9}\u{5DC}\u{5D5}\u{5DD};");
+        let (settings, font) = (ViewSettings::default(), font());
+        let visual = VisualLine::new(&line, &settings, &font, &mut FixedWidth);
+
+        assert!(!visual.rtl);
+        let runs: Vec<(Range<usize>, bool)> = visual.runs.iter().map(|run| (run.range.clone(), run.rtl)).collect();
+        assert_eq!(runs, vec![(0..4, false), (4..12, true), (12..13, false)]);
+
+        // the first hebrew letter is drawn at the right of its run, the last at the left
+        let x = |offset| visual.x(&line, offset);
+        assert_eq!(x(6), 70.0);
+        assert_eq!(x(10), 50.0);
+
+        // clicks map back to logical offsets
+        assert_eq!(visual.offset_at(72.0), 6);
+        assert_eq!(visual.offset_at(49.0), 10);
+        assert_eq!(visual.offset_at(500.0), 13);
+    }
+
+    #[test]
+    fn carets_split_where_directions_meet() {
+        let line = Line::new("s = \u{5E9}\u{5DC}\u{5D5}\u{5DD};");
+        let (settings, font) = (ViewSettings::default(), font());
+        let visual = VisualLine::new(&line, &settings, &font, &mut FixedWidth);
+
+        // after the space the ltr text ends at 40, and the hebrew starts at its right edge, 80
+        assert_eq!(visual.carets(&line, 4), vec![40.0, 80.0]);
+        // after the last letter the hebrew ends at 40, and the semicolon starts at 80
+        assert_eq!(visual.carets(&line, 12), vec![80.0, 40.0]);
+        // inside a run there's just the one
+        assert_eq!(visual.carets(&line, 2), vec![20.0]);
+
+        // a selection from the s into the hebrew is drawn in two pieces
+        let pieces: Vec<(f32, f32)> = visual.pieces(0..8)
+            .iter()
+            .map(|piece| (piece.left, piece.right))
+            .collect();
+        assert_eq!(pieces, vec![(0.0, 40.0), (60.0, 80.0)]);
+    }
+}
@@ -0,0 +1,139 @@
+//! Mapping between offsets into a line and x positions on screen. Cursors sit between grapheme
+//! clusters, never inside one, so an accented letter or an emoji sequence moves as one.
+
+use unicode_segmentation::UnicodeSegmentation;
+use xi_path_renderer::display_list::FontSpec;
+use xi_path_renderer::fonts::MeasureText;
+
+use crate::bidi::VisualLine;
+use crate::line::Line;
+use crate::settings::ViewSettings;
+
+/// Every offset a cursor can be at in `text`, from 0 to its length.
+pub fn grapheme_boundaries(text: &str) -> Vec<usize> {
+    let mut boundaries: Vec<usize> = text.grapheme_indices(true).map(|(offset, _)| offset).collect();
+    boundaries.push(text.len());
+    boundaries
+}
+
+/// How far right of the start of the line a cursor at `offset` is drawn, with tabs expanded
+/// and right to left runs in visual order. An offset inside a grapheme is drawn at its start.
+pub fn offset_to_x(line: &Line,
+                   offset: usize,
+                   settings: &ViewSettings,
+                   font: &FontSpec,
+                   fonts: &mut dyn MeasureText) -> f32 {
+    VisualLine::new(line, settings, font, fonts).x(line, offset)
+}
+
+/// The offset of the grapheme boundary drawn nearest `x`, for placing a cursor where a click landed.
+pub fn x_to_offset(line: &Line,
+                   x: f32,
+                   settings: &ViewSettings,
+                   font: &FontSpec,
+                   fonts: &mut dyn MeasureText) -> usize {
+    VisualLine::new(line, settings, font, fonts).offset_at(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use xi_path_renderer::display_list::FontFace;
+    use xi_path_renderer::fonts::FontCache;
+
+    use super::*;
+
+    fn font() -> FontSpec {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fonts/DejaVuSansMono.ttf");
+        FontSpec::new(FontFace::Path(path), 14.0)
+    }
+
+    #[test]
+    fn offsets_inside_a_grapheme_are_drawn_at_its_start() {
+        // an e with a combining accent, then a family emoji joined with zero width joiners
+        let line = Line::new("e\u{301}x\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}y");
+        assert_eq!(grapheme_boundaries(&line.text), vec![0, 3, 4, 22, 23]);
+
+        let (settings, font, mut fonts) = (ViewSettings::default(), font(), FontCache::new());
+        let mut x = |offset| offset_to_x(&line, offset, &settings, &font, &mut fonts);
+        assert_eq!(x(1), x(0));
+        assert_eq!(x(2), x(0));
+        assert_eq!(x(10), x(4));
+        assert!(x(3) > x(0));
+    }
+
+    #[test]
+    fn x_maps_back_to_the_nearest_boundary() {
+        let line = Line::new("\tcafe\u{301} ok");
+        let (settings, font, mut fonts) = (ViewSettings::default(), font(), FontCache::new());
+
+        for boundary in grapheme_boundaries(&line.text) {
+            let x = offset_to_x(&line, boundary, &settings, &font, &mut fonts);
+            assert_eq!(x_to_offset(&line, x, &settings, &font, &mut fonts), boundary);
+            assert_eq!(x_to_offset(&line, x + 1.0, &settings, &font, &mut fonts), boundary);
+        }
+        assert_eq!(x_to_offset(&line, -10.0, &settings, &font, &mut fonts), 0);
+        assert_eq!(x_to_offset(&line, 10_000.0, &settings, &font, &mut fonts), line.text.len());
+    }
+
+    #[cfg(feature = "shaping")]
+    #[test]
+    fn combining_marks_take_no_room_of_their_own() {
+        let line = Line::new("e\u{301}x");
+        let (settings, font, mut fonts) = (ViewSettings::default(), font(), FontCache::new());
+
+        // shaped, the accent sits over the e, so the x after it is where it'd be without it
+        let after_accent = offset_to_x(&line, 3, &settings, &font, &mut fonts);
+        assert_eq!(after_accent, fonts.text_width("e", &font));
+    }
+
+    // every boundary maps to an x and back, and the boundaries go the way the text reads
+    fn assert_boundaries_round_trip(line: &Line, rtl: bool) -> Vec<f32> {
+        let (settings, font, mut fonts) = (ViewSettings::default(), font(), FontCache::new());
+        let xs: Vec<f32> = grapheme_boundaries(&line.text).into_iter()
+            .map(|boundary| {
+                let x = offset_to_x(line, boundary, &settings, &font, &mut fonts);
+                assert_eq!(x_to_offset(line, x, &settings, &font, &mut fonts), boundary, "in {:?}", line.text);
+                x
+            })
+            .collect();
+        for pair in xs.windows(2) {
+            assert!(if rtl { pair[0] > pair[1] } else { pair[0] < pair[1] }, "{:?} out of order in {:?}", xs, line.text);
+        }
+        xs
+    }
+
+    #[test]
+    fn ligatures_have_a_column_per_char() {
+        // whether or not the font joins these up, a cursor can go between each of them
+        let line = Line::new("fi ffl -> != ===");
+        let xs = assert_boundaries_round_trip(&line, false);
+        let (font, mut fonts) = (font(), FontCache::new());
+        assert_eq!(xs[0], 0.0);
+        assert_eq!(*xs.last().unwrap(), fonts.text_width(&line.text, &font));
+    }
+
+    #[test]
+    fn joined_arabic_reads_from_the_right() {
+        // salaam, its letters joined up
+        let line = Line::new("\u{633}\u{644}\u{627}\u{645}");
+        let xs = assert_boundaries_round_trip(&line, true);
+        let (font, mut fonts) = (font(), FontCache::new());
+        assert_eq!(xs[0], fonts.text_width(&line.text, &font));
+        assert_eq!(*xs.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn indic_clusters_move_as_one() {
+        // namaste, where the s and its virama make one grapheme and the t and its vowel sign another
+        let line = Line::new("\u{928}\u{92E}\u{938}\u{94D}\u{924}\u{947}");
+        assert_eq!(grapheme_boundaries(&line.text), vec![0, 3, 6, 12, 18]);
+        assert_boundaries_round_trip(&line, false);
+
+        let (settings, font, mut fonts) = (ViewSettings::default(), font(), FontCache::new());
+        let mut x = |offset| offset_to_x(&line, offset, &settings, &font, &mut fonts);
+        assert_eq!(x(9), x(6));
+        assert_eq!(x(15), x(12));
+    }
+}
@@ -0,0 +1,135 @@
+//! Diagnostics, the errors and warnings plugins like a language server mark the buffer with.
+//! They come as annotations of their own type, with a payload for each range saying how bad
+//! it is and what's wrong.
+
+use serde_json::Value;
+use xi_path_renderer::display_list::Color;
+
+use crate::line_cache::Annotation;
+use crate::styles::{ ERROR_STYLE, INFO_STYLE, WARNING_STYLE };
+
+/// The annotation type diagnostics come as.
+pub const DIAGNOSTIC_ANNOTATION: &str = "diagnostic";
+
+/// How bad a diagnostic is. Ordered, so the worst of several is the biggest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    // the language server protocol numbers them, 1 for errors down to 4 for hints,
+    // but a plugin could just as well name them
+    fn from_json(value: &Value) -> Option<Severity> {
+        match value {
+            Value::Number(n) => match n.as_u64()? {
+                1 => Some(Severity::Error),
+                2 => Some(Severity::Warning),
+                3 | 4 => Some(Severity::Info),
+                _ => None,
+            },
+            Value::String(name) => match name.to_lowercase().as_str() {
+                "error" => Some(Severity::Error),
+                "warning" => Some(Severity::Warning),
+                "info" | "information" | "hint" => Some(Severity::Info),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Severity::Error => Color::rgb(220, 40, 40),
+            Severity::Warning => Color::rgb(230, 160, 0),
+            Severity::Info => Color::rgb(40, 120, 220),
+        }
+    }
+
+    /// The style the ranges it covers are drawn in, which underlines them with a squiggle.
+    pub fn style_id(self) -> usize {
+        match self {
+            Severity::Error => ERROR_STYLE,
+            Severity::Warning => WARNING_STYLE,
+            Severity::Info => INFO_STYLE,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+/// One diagnostic, over a range of the buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// (start line, start column, end line, end column), lines from 0 and columns in utf-8 bytes.
+    pub range: [usize; 4],
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// What the diagnostics list shows for it, with the line and column counted from 1.
+    pub fn describe(&self) -> String {
+        format!("{}:{} {}: {}", self.range[0] + 1, self.range[1] + 1, self.severity.label(), self.message)
+    }
+}
+
+/// Every diagnostic in `annotations`, in the order they are in the buffer.
+pub fn diagnostics(annotations: &[Annotation]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = annotations.iter()
+        .filter(|annotation| annotation.kind == DIAGNOSTIC_ANNOTATION)
+        .flat_map(|annotation| {
+            annotation.ranges.iter().enumerate().map(move |(index, range)| {
+                let payload = annotation.payloads.as_ref().and_then(|payloads| payloads.get(index));
+                diagnostic(*range, payload)
+            })
+        })
+        .collect();
+    diagnostics.sort_by_key(|diagnostic| diagnostic.range);
+    diagnostics
+}
+
+// without a severity it's taken as an error, the protocol leaves it to us
+fn diagnostic(range: [usize; 4], payload: Option<&Value>) -> Diagnostic {
+    let severity = payload.and_then(|p| p.get("severity")).and_then(Severity::from_json).unwrap_or(Severity::Error);
+    let message = payload.and_then(|p| p.get("message")).and_then(Value::as_str).unwrap_or("").to_string();
+    Diagnostic { severity, range, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn payloads_give_each_range_its_severity_and_message() {
+        let annotation: Annotation = serde_json::from_value(json!({
+            "type": "diagnostic",
+            "ranges": [[4, 0, 4, 3], [1, 2, 1, 8], [7, 0, 7, 1]],
+            "payloads": [
+                { "severity": 2, "message": "unused variable" },
+                { "severity": "error", "message": "mismatched types" },
+                {},
+            ],
+        })).unwrap();
+
+        let found: Vec<(usize, Severity, String)> = diagnostics(&[annotation])
+            .into_iter()
+            .map(|d| (d.range[0], d.severity, d.message))
+            .collect();
+        assert_eq!(found, vec![
+            (1, Severity::Error, "mismatched types".to_string()),
+            (4, Severity::Warning, "unused variable".to_string()),
+            (7, Severity::Error, String::new()),
+        ]);
+    }
+}
@@ -0,0 +1,146 @@
+//! The file finder: every file under the working directory that git wouldn't ignore, narrowed
+//! down by a fuzzy match on what's typed, best match first.
+
+use std::path::{ Path, PathBuf };
+
+use ignore::WalkBuilder;
+
+use crate::picker::Picker;
+
+// a whole home directory could be the working directory, so indexing stops somewhere
+const MAX_FILES: usize = 100_000;
+// only the best are shown, the list is one row each
+const MAX_RESULTS: usize = 20;
+
+/// Every file under `root`, relative to it and sorted, skipping what `.gitignore`s and hidden
+/// files leave out.
+pub fn index(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkBuilder::new(root)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .take(MAX_FILES)
+        .collect();
+    files.sort();
+    files
+}
+
+/// How well `query` matches `candidate`, higher being better, or `None` if its characters
+/// don't all appear in `candidate` in order. Case only matters when the query has capitals.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let smart_case = query.chars().any(char::is_uppercase);
+    let same = |a: char, b: char| if smart_case { a == b } else { a.to_lowercase().eq(b.to_lowercase()) };
+
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    let mut chars = candidate.char_indices();
+    let mut last_char = None;
+    for q in query.chars() {
+        // takes the first place each character fits, which is good enough for file names
+        let (index, before) = loop {
+            let (index, c) = chars.next()?;
+            let before = last_char.replace(c);
+            if same(q, c) {
+                break (index, before);
+            }
+        };
+        score += 1;
+        // runs of matches, and matches at the start of a name or word, count for more
+        if previous.map(|p| p + 1 == index).unwrap_or(false) {
+            score += 5;
+        }
+        match before {
+            None | Some('/') => score += 8,
+            Some('_') | Some('-') | Some('.') | Some(' ') => score += 4,
+            _ => {}
+        }
+        previous = Some(index);
+    }
+
+    // matching in the file name beats matching in the directories, and shorter paths win ties
+    let name_start = candidate.rfind('/').map(|slash| slash + 1).unwrap_or(0);
+    if previous.map(|p| p >= name_start).unwrap_or(false) {
+        score += 10;
+    }
+    Some(score * 100 - candidate.len() as i64)
+}
+
+/// The file finder overlay's state: the files indexed, what's been typed, and the matches.
+pub struct FileFinder {
+    root: PathBuf,
+    files: Vec<PathBuf>,
+    query: String,
+    pub matches: Picker<PathBuf>,
+}
+
+impl FileFinder {
+    /// Indexes `root`, which is where the files it opens are relative to.
+    pub fn new(root: &Path) -> Self {
+        FileFinder::with_files(root, index(root))
+    }
+
+    pub fn with_files(root: &Path, files: Vec<PathBuf>) -> Self {
+        let mut finder = FileFinder {
+            root: root.to_path_buf(),
+            files,
+            query: String::new(),
+            matches: Picker::new("", Vec::new()),
+        };
+        finder.update_matches();
+        finder
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.query.push_str(text);
+        self.update_matches();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.update_matches();
+    }
+
+    /// The file picked, with the root in front.
+    pub fn selected(&self) -> Option<PathBuf> {
+        self.matches.selected().map(|path| self.root.join(path))
+    }
+
+    fn update_matches(&mut self) {
+        let mut scored: Vec<(i64, &PathBuf)> = self.files.iter()
+            .filter_map(|path| score(&self.query, &path.to_string_lossy()).map(|score| (score, path)))
+            .collect();
+        // stable, so equal scores stay in path order
+        scored.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let matches = scored.into_iter().take(MAX_RESULTS).map(|(_, path)| path.clone()).collect();
+        self.matches = Picker::new(&format!("Open: {}", self.query), matches);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_in_file_names_and_at_word_starts_rank_first() {
+        let files = ["src/main.rs", "frontend/src/minimap.rs", "frontend/src/line_cache.rs", "docs/manual.md"];
+        let mut finder = FileFinder::with_files(Path::new("/repo"), files.iter().map(PathBuf::from).collect());
+
+        finder.push_str("lc");
+        assert_eq!(finder.matches.items(), &[PathBuf::from("frontend/src/line_cache.rs")]);
+
+        finder.pop();
+        finder.pop();
+        finder.push_str("main");
+        assert_eq!(finder.matches.items()[0], PathBuf::from("src/main.rs"));
+        assert_eq!(finder.selected(), Some(PathBuf::from("/repo/src/main.rs")));
+
+        // capitals only match capitals
+        assert_eq!(score("Main", "src/main.rs"), None);
+    }
+}
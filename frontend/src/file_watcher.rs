@@ -0,0 +1,126 @@
+//! Watching the files of open views for changes made outside the editor, by a git checkout or
+//! a formatter say. Changes arrive as `FrontendMessage::FileChanged`, through the same channel
+//! and waker as xi-core's callbacks.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::mpsc::{ channel, Sender };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::{ Duration, SystemTime };
+
+use notify::{ watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher };
+
+use crate::message::FrontendMessage;
+use crate::waker::Waker;
+
+// tools that write a file several times in a row get reported once
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// What a file on disk looked like when we last read or wrote it, to tell its changes from ours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiskState {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl DiskState {
+    /// `None` when the file isn't there, or can't be looked at.
+    pub fn of(path: &Path) -> Option<DiskState> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(DiskState { modified: metadata.modified().ok()?, len: metadata.len() })
+    }
+}
+
+// the paths we were given, by where notify reports them
+type Watched = Arc<Mutex<HashMap<PathBuf, PathBuf>>>;
+
+/// Watches files for the main loop. Directories are watched rather than the files themselves,
+/// since saving by writing a new file and renaming it over the old one would end a watch on the file.
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    watched: Watched,
+    // how many watched files are in each directory
+    directories: HashMap<PathBuf, usize>,
+}
+
+impl FileWatcher {
+    /// Starts watching, with changes sent to `sender` and the main loop woken for each.
+    pub fn new(sender: Sender<FrontendMessage>, waker: Option<Arc<Waker>>) -> Result<Self, notify::Error> {
+        let (events, received) = channel();
+        let watcher = watcher(events, DEBOUNCE)?;
+        let watched: Watched = Arc::new(Mutex::new(HashMap::new()));
+
+        let paths = watched.clone();
+        thread::spawn(move || {
+            for event in received {
+                let changed = match event {
+                    DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) => path,
+                    DebouncedEvent::Rename(_, to) => to,
+                    _ => continue,
+                };
+                let path = match paths.lock().unwrap().get(&changed) {
+                    Some(path) => path.clone(),
+                    None => continue,
+                };
+                // the receiver only goes away when the main loop has exited
+                if sender.send(FrontendMessage::FileChanged(path)).is_err() {
+                    return;
+                }
+                if let Some(waker) = &waker {
+                    waker.wake();
+                }
+            }
+        });
+
+        Ok(FileWatcher { watcher, watched, directories: HashMap::new() })
+    }
+
+    pub fn watch(&mut self, path: &Path) {
+        let (directory, reported) = match reported_path(path) {
+            Some(paths) => paths,
+            None => return,
+        };
+        if self.watched.lock().unwrap().insert(reported, path.to_path_buf()).is_some() {
+            return;
+        }
+
+        let count = self.directories.entry(directory.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            if let Err(e) = self.watcher.watch(&directory, RecursiveMode::NonRecursive) {
+                eprintln!("couldn't watch {:?} for changes: {}", directory, e);
+            }
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        let (directory, reported) = match reported_path(path) {
+            Some(paths) => paths,
+            None => return,
+        };
+        if self.watched.lock().unwrap().remove(&reported).is_none() {
+            return;
+        }
+
+        if let Some(count) = self.directories.get_mut(&directory) {
+            *count -= 1;
+            if *count == 0 {
+                self.directories.remove(&directory);
+                let _ = self.watcher.unwatch(&directory);
+            }
+        }
+    }
+}
+
+// notify reports paths with the directory canonicalized, so `path` is looked up that way too
+fn reported_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let directory = directory.canonicalize().ok()?;
+    let reported = directory.join(path.file_name()?);
+    Some((directory, reported))
+}
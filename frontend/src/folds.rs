@@ -0,0 +1,217 @@
+//! Code folding. Regions come from a plugin when it marks them, and from indentation when
+//! not, and folding one hides every line of it but the first. xi-core knows nothing about
+//! folds, so hidden lines are still in the line cache and just skipped when drawing.
+
+use std::collections::BTreeSet;
+
+use crate::line_cache::Annotation;
+
+/// The annotation type plugins mark foldable ranges with.
+pub const FOLD_ANNOTATION: &str = "fold";
+
+/// Lines `start` to `end` of the buffer, counting from 0. Folded, `start` stays on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FoldRegion {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Whether a line's fold region is open or folded, for the gutter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Fold {
+    Open,
+    Folded,
+}
+
+/// A view's fold regions, and which of them are folded.
+#[derive(Clone, Debug, Default)]
+pub struct Folds {
+    regions: Vec<FoldRegion>,
+    // by start line, so a fold survives its region being worked out again
+    folded: BTreeSet<usize>,
+}
+
+impl Folds {
+    /// Works the regions out again, from the plugin's ranges when there are any, otherwise
+    /// from the indentation of `lines`, which are (buffer line, text) in buffer order.
+    pub fn update_regions<'a>(&mut self,
+                              annotations: &[Annotation],
+                              lines: impl Iterator<Item = (usize, &'a str)>,
+                              tab_size: usize) {
+        let from_plugin: Vec<FoldRegion> = annotations.iter()
+            .filter(|annotation| annotation.kind == FOLD_ANNOTATION)
+            .flat_map(|annotation| annotation.ranges.iter())
+            .filter(|range| range[2] > range[0])
+            .map(|range| FoldRegion { start: range[0], end: range[2] })
+            .collect();
+
+        self.regions = if from_plugin.is_empty() { indent_regions(lines, tab_size) } else { from_plugin };
+        self.regions.sort_by_key(|region| region.start);
+    }
+
+    pub fn regions(&self) -> &[FoldRegion] {
+        &self.regions
+    }
+
+    /// The lines folded, by the line their region starts at.
+    pub fn folded(&self) -> Vec<usize> {
+        self.folded.iter().cloned().collect()
+    }
+
+    pub fn set_folded(&mut self, starts: &[usize]) {
+        self.folded = starts.iter().cloned().collect();
+    }
+
+    /// The fold marker for `line`, if a region starts there.
+    pub fn marker(&self, line: usize) -> Option<Fold> {
+        self.region_at(line)?;
+        Some(if self.folded.contains(&line) { Fold::Folded } else { Fold::Open })
+    }
+
+    /// Folds or unfolds the region starting at `line`. Returns false when none does.
+    pub fn toggle(&mut self, line: usize) -> bool {
+        if self.region_at(line).is_none() {
+            return false;
+        }
+        if !self.folded.remove(&line) {
+            self.folded.insert(line);
+        }
+        true
+    }
+
+    /// The innermost region `line` is in, including the line it starts on.
+    pub fn innermost(&self, line: usize) -> Option<FoldRegion> {
+        self.regions.iter()
+            .filter(|region| region.start <= line && line <= region.end)
+            .min_by_key(|region| region.end - region.start)
+            .cloned()
+    }
+
+    /// Whether `line` is inside a folded region, and so not drawn.
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.folded_regions().any(|region| region.start < line && line <= region.end)
+    }
+
+    /// Unfolds whatever hides `line`, like when a cursor ends up in a folded region.
+    pub fn reveal(&mut self, line: usize) {
+        let hiding: Vec<usize> = self.folded_regions()
+            .filter(|region| region.start < line && line <= region.end)
+            .map(|region| region.start)
+            .collect();
+        for start in hiding {
+            self.folded.remove(&start);
+        }
+    }
+
+    /// Where moving the cursor from `line` goes when it would land in a folded region: past its
+    /// end going down, or onto its first line going up. `None` when a plain move will do.
+    pub fn jump_over(&self, line: usize, down: bool) -> Option<usize> {
+        if down {
+            self.folded_regions().filter(|region| region.start == line).map(|region| region.end + 1).max()
+        } else {
+            self.folded_regions().filter(|region| region.end + 1 == line).map(|region| region.start).min()
+        }
+    }
+
+    fn region_at(&self, line: usize) -> Option<&FoldRegion> {
+        self.regions.iter().find(|region| region.start == line)
+    }
+
+    fn folded_regions<'a>(&'a self) -> impl Iterator<Item = &'a FoldRegion> + 'a {
+        self.regions.iter().filter(move |region| self.folded.contains(&region.start))
+    }
+}
+
+// how far in the text of a line starts, in columns, or None for a blank line
+fn indentation(text: &str, tab_size: usize) -> Option<usize> {
+    let mut column = 0;
+    for c in text.chars() {
+        match c {
+            ' ' => column += 1,
+            '\t' => column += tab_size - column % tab_size,
+            '\n' | '\r' => return None,
+            _ => return Some(column),
+        }
+    }
+    None
+}
+
+// a region starts at every line followed by more indented ones, and runs to the last of them.
+// blank lines don't count, so a region doesn't swallow the gap after it
+fn indent_regions<'a>(lines: impl Iterator<Item = (usize, &'a str)>, tab_size: usize) -> Vec<FoldRegion> {
+    let mut regions = Vec::new();
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut previous: Option<(usize, usize)> = None;
+
+    for (line, text) in lines {
+        let indent = match indentation(text, tab_size.max(1)) {
+            Some(indent) => indent,
+            None => continue,
+        };
+        while let Some(&(start, open_indent)) = open.last() {
+            if open_indent < indent {
+                break;
+            }
+            open.pop();
+            regions.push(FoldRegion { start, end: previous.map(|(line, _)| line).unwrap_or(start) });
+        }
+        if let Some((previous_line, previous_indent)) = previous {
+            if indent > previous_indent {
+                open.push((previous_line, previous_indent));
+            }
+        }
+        previous = Some((line, indent));
+    }
+
+    // what's still open runs to the last line we have
+    if let Some((last, _)) = previous {
+        regions.extend(open.into_iter().map(|(start, _)| FoldRegion { start, end: last }));
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[&str] = &[
+        "fn main() {",
+        "    if ready {",
+        "        go();",
+        "",
+        "    }",
+        "}",
+        "",
+        "fn other() {}",
+    ];
+
+    fn folds() -> Folds {
+        let mut folds = Folds::default();
+        folds.update_regions(&[], SOURCE.iter().cloned().enumerate(), 4);
+        folds
+    }
+
+    #[test]
+    fn regions_follow_indentation() {
+        assert_eq!(folds().regions(), &[FoldRegion { start: 0, end: 4 }, FoldRegion { start: 1, end: 2 }]);
+    }
+
+    #[test]
+    fn folding_hides_all_but_the_first_line_and_moves_jump_over_it() {
+        let mut folds = folds();
+        assert!(folds.toggle(1));
+        assert!(!folds.toggle(3));
+
+        let hidden: Vec<usize> = (0..SOURCE.len()).filter(|line| folds.is_hidden(*line)).collect();
+        assert_eq!(hidden, vec![2]);
+        assert_eq!(folds.marker(1), Some(Fold::Folded));
+        assert_eq!(folds.marker(0), Some(Fold::Open));
+        assert_eq!(folds.jump_over(1, true), Some(3));
+        assert_eq!(folds.jump_over(3, false), Some(1));
+        assert_eq!(folds.jump_over(0, true), None);
+
+        // a cursor landing inside opens it up again
+        folds.reveal(2);
+        assert_eq!(folds.folded(), Vec::<usize>::new());
+    }
+}
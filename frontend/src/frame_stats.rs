@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How long one frame took.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTiming {
+    pub layout: Duration,
+    /// Drawing and presenting, including any wait for vsync.
+    pub render: Duration,
+    /// From the input that led to the frame to it being on screen, when there was any.
+    pub latency: Option<Duration>,
+    pub relaid_lines: usize,
+}
+
+/// Timings of the most recent frames, for keeping an eye on how long typing takes to show up.
+pub struct FrameStats {
+    frames: VecDeque<FrameTiming>,
+    capacity: usize,
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        FrameStats { frames: VecDeque::with_capacity(capacity), capacity: capacity.max(1) }
+    }
+
+    pub fn record(&mut self, timing: FrameTiming) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(timing);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// A one line summary: averages, 95th percentiles and worst cases, in milliseconds.
+    pub fn summary(&self) -> String {
+        let layout: Vec<Duration> = self.frames.iter().map(|f| f.layout).collect();
+        let render: Vec<Duration> = self.frames.iter().map(|f| f.render).collect();
+        let latency: Vec<Duration> = self.frames.iter().filter_map(|f| f.latency).collect();
+        let relaid: usize = self.frames.iter().map(|f| f.relaid_lines).sum();
+
+        format!("{} frames, layout {}, render {}, input latency {}, {:.1} lines laid out a frame",
+                self.frames.len(),
+                describe(&layout),
+                describe(&render),
+                describe(&latency),
+                relaid as f32 / self.frames.len().max(1) as f32)
+    }
+}
+
+fn describe(durations: &[Duration]) -> String {
+    if durations.is_empty() {
+        return "n/a".to_string();
+    }
+
+    let mut sorted = durations.to_vec();
+    sorted.sort();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let average = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+    let p95 = sorted[(sorted.len() * 95 / 100).min(sorted.len() - 1)];
+    format!("avg {:.2}ms p95 {:.2}ms max {:.2}ms", ms(average), ms(p95), ms(sorted[sorted.len() - 1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_most_recent_frames() {
+        let mut stats = FrameStats::new(2);
+        for ms in &[1, 2, 3] {
+            stats.record(FrameTiming { layout: Duration::from_millis(*ms), ..FrameTiming::default() });
+        }
+        assert_eq!(stats.len(), 2);
+        assert!(stats.summary().starts_with("2 frames, layout avg 2.50ms p95 3.00ms max 3.00ms, render avg 0.00ms"));
+        assert!(stats.summary().contains("input latency n/a"));
+    }
+}
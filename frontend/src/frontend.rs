@@ -1,7 +1,8 @@
-use xi_path_renderer:: {
-    pathfinder_renderer::PathfinderRenderer, 
-    Renders
-};
+use std::sync::{ Arc, Mutex };
+use std::sync::mpsc::{ channel, Receiver, Sender };
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use xi_core_lib:: {
     ViewId, ConfigTable,LanguageId,
@@ -14,72 +15,164 @@ use xi_core_lib:: {
     width_cache:: { WidthReq, WidthResponse }
 };
 
+use crate::measure::WidthMeasurer;
+use crate::message::FrontendMessage;
+use crate::styles::StyleDef;
+use crate::waker::Waker;
+
+/// The frontend xi-core talks to. It never touches editor state or the renderer itself,
+/// every callback is queued as a `FrontendMessage` for the main loop to apply.
 pub struct XiPathFrontend{
-    renderer : Box<dyn Renders>
+    sender: Mutex<Sender<FrontendMessage>>,
+    waker: Option<Arc<Waker>>,
+    measurer: Option<Arc<Mutex<WidthMeasurer>>>,
 }
 
 impl XiPathFrontend {
-    pub fn new_with_pathfinder_renderer() -> Self {
-        XiPathFrontend {
-            renderer: Box::new(PathfinderRenderer::new()),
+    /// Creates the frontend, and the receiving end the main loop drains its messages from.
+    pub fn new() -> (Self, Receiver<FrontendMessage>) {
+        let (sender, receiver) = channel();
+        let frontend = XiPathFrontend { 
+            sender: Mutex::new(sender),
+            waker: None,
+            measurer: None,
+        };
+        (frontend, receiver)
+    }
+
+    /// Somewhere else to send messages to the main loop from, like the file watcher.
+    pub fn sender(&self) -> Sender<FrontendMessage> {
+        self.sender.lock().unwrap().clone()
+    }
+
+    /// Lets callbacks wake the main loop when it's blocked waiting for input.
+    pub fn set_waker(&mut self, waker: Arc<Waker>) {
+        self.waker = Some(waker);
+    }
+
+    /// Measures text for xi-core with the same fonts the window is drawn with.
+    pub fn set_measurer(&mut self, measurer: Arc<Mutex<WidthMeasurer>>) {
+        self.measurer = Some(measurer);
+    }
+
+    fn send(&self, message: FrontendMessage) {
+        // the receiver only goes away when the main loop has exited, nothing left to tell
+        if self.sender.lock().unwrap().send(message).is_err() {
+            return;
+        }
+
+        if let Some(waker) = &self.waker {
+            waker.wake();
         }
     }
+}
+
+/// Something that takes xi-core's notifications as json, the way they arrive over the wire
+/// from an out-of-process core. The json-rpc equivalent of the `Frontend` callbacks.
+pub trait HandleNotification {
+    fn handle_notification(&self, method: &str, params: &Value);
+}
 
-    pub fn new(new_renderer: Box<dyn Renders>) -> Self {
-        XiPathFrontend { 
-            renderer: new_renderer,
+impl HandleNotification for XiPathFrontend {
+    // methods we don't act on yet, or can't make sense of, are dropped
+    fn handle_notification(&self, method: &str, params: &Value) {
+        let message = match method {
+            "update" => param(params, "view_id").zip(param(params, "update"))
+                .map(|(view_id, update)| FrontendMessage::Update { view_id, update }),
+            "scroll_to" => param(params, "view_id").zip(param(params, "line"))
+                .map(|(view_id, line)| FrontendMessage::ScrollTo { view_id, line, col: param(params, "col").unwrap_or(0) }),
+            "def_style" => StyleDef::from_json(params)
+                .map(|(style_id, style)| FrontendMessage::DefStyle { style_id, style }),
+            "config_changed" => param(params, "view_id").zip(param(params, "changes"))
+                .map(|(view_id, changes)| FrontendMessage::ConfigChanged { view_id, changes }),
+            "available_languages" => param(params, "languages").map(FrontendMessage::AvailableLanguages),
+            "language_changed" => param(params, "view_id").zip(param(params, "language_id"))
+                .map(|(view_id, language)| FrontendMessage::LanguageChanged { view_id, language }),
+            "add_status_item" => param(params, "view_id").map(|view_id| FrontendMessage::AddStatusItem {
+                view_id,
+                key: param(params, "key").unwrap_or_default(),
+                value: param(params, "value").unwrap_or_default(),
+                alignment: param(params, "alignment").unwrap_or_default(),
+            }),
+            "update_status_item" => param(params, "view_id").map(|view_id| FrontendMessage::UpdateStatusItem {
+                view_id,
+                key: param(params, "key").unwrap_or_default(),
+                value: param(params, "value").unwrap_or_default(),
+            }),
+            "remove_status_item" => param(params, "view_id").map(|view_id| FrontendMessage::RemoveStatusItem {
+                view_id,
+                key: param(params, "key").unwrap_or_default(),
+            }),
+            _ => None,
+        };
+
+        match message {
+            Some(message) => self.send(message),
+            None => eprintln!("ignoring {} notification from xi-core", method),
         }
     }
 }
 
+fn param<T: DeserializeOwned>(params: &Value, name: &str) -> Option<T> {
+    params.get(name).and_then(|value| serde_json::from_value(value.clone()).ok())
+}
+
 impl Frontend for XiPathFrontend {
 
+    // the core's update and style types are only for serializing, so they're read back from
+    // json the same way they would be coming from an out-of-process core
     fn update_view(&self, view_id: ViewId, update: &Update) {
-        //can call renderer.do_stuff() here
-        todo!()
+        match serde_json::to_value(update).and_then(serde_json::from_value) {
+            Ok(update) => self.send(FrontendMessage::Update { view_id, update }),
+            Err(e) => eprintln!("couldn't read update for {:?}: {}", view_id, e),
+        }
     }
     fn scroll_to(&self, view_id: ViewId, line: usize, col: usize) {
-        todo!()
+        self.send(FrontendMessage::ScrollTo { view_id, line, col });
     }
     fn config_changed(&self, view_id: ViewId, changes: &ConfigTable) {
-        todo!()
-    }
-    fn available_themes(&self, theme_names: Vec<String>) {
-        todo!()
+        self.send(FrontendMessage::ConfigChanged { view_id, changes: changes.clone() });
     }
+    // there's no picker to choose a theme from yet, xi-core's default one is used
+    fn available_themes(&self, _theme_names: Vec<String>) {}
     fn available_languages(&self, languages: Vec<LanguageId>) {
-        todo!()
-    }
-    fn theme_changed(&self, name: &str, theme: &ThemeSettings) {
-        todo!()
+        self.send(FrontendMessage::AvailableLanguages(languages));
     }
+    // syntax colors arrive as styles through def_style, the theme's colors for the rest of
+    // the window, like its background, aren't used yet
+    fn theme_changed(&self, _name: &str, _theme: &ThemeSettings) {}
     fn language_changed(&self, view_id: ViewId, new_lang: &LanguageId) {
-        todo!()
-    }
-    fn plugin_started(&self, view_id: ViewId, plugin: &str) {
-        todo!()
+        self.send(FrontendMessage::LanguageChanged { view_id, language: new_lang.clone() });
     }
+    // xi-core starts and stops plugins itself, and what they find arrives as annotations and
+    // status items, so there's nothing to show for these besides a plugin that died
+    fn plugin_started(&self, _view_id: ViewId, _plugin: &str) {}
     fn plugin_stopped(&self, view_id: ViewId, plugin: &str, code: i32) {
-        todo!()
-    }
-    fn available_plugins(&self, view_id: ViewId, plugins: &[ClientPluginInfo]) {
-        todo!()
-    }
-    fn update_cmds(&self, view_id: ViewId, plugin: &str, cmds: &[Command]) {
-        todo!()
+        if code != 0 {
+            eprintln!("plugin {} stopped for {:?} with code {}", plugin, view_id, code);
+        }
     }
+    fn available_plugins(&self, _view_id: ViewId, _plugins: &[ClientPluginInfo]) {}
+    // plugin commands have nowhere to go until there's a command palette
+    fn update_cmds(&self, _view_id: ViewId, _plugin: &str, _cmds: &[Command]) {}
     fn def_style(&self, style: &Style)  {
-        todo!()
-    }
-    fn find_status(&self, view_id: ViewId, queries: &Vec<FindStatus>) {
-        todo!()
-    }
-    fn replace_status(&self, view_id: ViewId, replace: &Replace) {
-        todo!()
+        let style = serde_json::to_value(style).ok().as_ref().and_then(StyleDef::from_json);
+        match style {
+            Some((style_id, style)) => self.send(FrontendMessage::DefStyle { style_id, style }),
+            None => eprintln!("couldn't read a style from xi-core"),
+        }
     }
+    // matches are drawn, and ticked on the minimap, from the "find" annotations in updates,
+    // the counts here aren't shown anywhere yet
+    fn find_status(&self, _view_id: ViewId, _queries: &Vec<FindStatus>) {}
+    // nothing sends a replace yet, so there's no replace ui to show this in
+    fn replace_status(&self, _view_id: ViewId, _replace: &Replace) {}
 
     fn measure_width(&self, reqs: &[WidthReq]) -> WidthResponse {
-        todo!()
+        match &self.measurer {
+            Some(measurer) => measurer.lock().unwrap().measure(reqs),
+            None => WidthMeasurer::new().measure(reqs),
+        }
     }
 
     fn add_status_item(
@@ -90,21 +183,26 @@ impl Frontend for XiPathFrontend {
         value: &str,
         alignment: &str,
     ) {
-        todo!()
+        // source is the plugin that added the item, we don't show it anywhere yet
+        self.send(FrontendMessage::AddStatusItem {
+            view_id,
+            key: key.to_string(),
+            value: value.to_string(),
+            alignment: alignment.to_string(),
+        });
     }
     fn update_status_item(&self, view_id: ViewId, key: &str, value: &str) {
-        todo!()
+        self.send(FrontendMessage::UpdateStatusItem { view_id, key: key.to_string(), value: value.to_string() });
     }
     fn remove_status_item(&self, view_id: ViewId, key: &str) {
-        todo!()
-    }
-    fn show_hover(&self, view_id: ViewId, request_id: usize, result: String) {
-        todo!()
+        self.send(FrontendMessage::RemoveStatusItem { view_id, key: key.to_string() });
     }
+    // hovers only come back for a request_hover, which nothing sends yet
+    fn show_hover(&self, _view_id: ViewId, _request_id: usize, _result: String) {}
     fn schedule_idle(&self, token: usize) {
-        todo!()
+        self.send(FrontendMessage::ScheduleIdle(token));
     }
     fn schedule_timer(&self, timeout: std::time::Instant, token: usize) {
-        todo!()
+        self.send(FrontendMessage::ScheduleTimer { deadline: timeout, token });
     }
 }
\ No newline at end of file
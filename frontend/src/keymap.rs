@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use sdl2::keyboard::{Keycode, Mod};
+use serde_json::Value;
+use xi_core_lib::LanguageId;
+
+/// Key bindings for a view, mapping chords like `ctrl+shift+k` to xi-core edit methods.
+///
+/// The keys every editor has, like return and the arrows, are bound to start with. Then bindings
+/// are read from `<config_dir>/keymaps/default.json`, and then from
+/// `<config_dir>/keymaps/<language>.json`, which overrides the defaults for that language.
+/// Both files are a flat json object of `"chord": "edit_method"`.
+#[derive(Clone, Debug, Default)]
+pub struct Keymap {
+    bindings: HashMap<String, String>,
+}
+
+// typing text arrives separately from key presses, so these are only the keys that don't type anything
+const BUILT_IN: &[(&str, &str)] = &[
+    ("return", "insert_newline"),
+    ("tab", "insert_tab"),
+    ("backspace", "delete_backward"),
+    ("delete", "delete_forward"),
+    ("left", "move_left"),
+    ("right", "move_right"),
+    ("up", "move_up"),
+    ("down", "move_down"),
+    ("shift+left", "move_left_and_modify_selection"),
+    ("shift+right", "move_right_and_modify_selection"),
+    ("shift+up", "move_up_and_modify_selection"),
+    ("shift+down", "move_down_and_modify_selection"),
+    ("home", "move_to_left_end_of_line"),
+    ("end", "move_to_right_end_of_line"),
+    ("pageup", "scroll_page_up"),
+    ("pagedown", "scroll_page_down"),
+    ("ctrl+a", "select_all"),
+    ("ctrl+z", "undo"),
+    ("ctrl+shift+z", "redo"),
+];
+
+impl Keymap {
+    /// Just the built in bindings, for a view before there's a config dir to read any from.
+    pub fn built_in() -> Self {
+        let mut keymap = Keymap::default();
+        for (chord, method) in BUILT_IN {
+            keymap.bind(chord, method);
+        }
+        keymap
+    }
+
+    pub fn load(config_dir: &Path, language: Option<&LanguageId>) -> Self {
+        let keymap_dir = config_dir.join("keymaps");
+        let mut keymap = Keymap::built_in();
+        keymap.merge_file(&keymap_dir.join("default.json"));
+
+        if let Some(language) = language {
+            keymap.merge_file(&keymap_dir.join(format!("{}.json", language.as_ref())));
+        }
+        keymap
+    }
+
+    // missing files are normal (most languages won't have one), a broken one we just report and skip
+    fn merge_file(&mut self, path: &Path) {
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return,
+        };
+
+        match serde_json::from_str::<Value>(&contents) {
+            Ok(Value::Object(map)) => {
+                for (chord, method) in map {
+                    if let Value::String(method) = method {
+                        self.bindings.insert(chord.to_lowercase(), method);
+                    }
+                }
+            }
+            _ => eprintln!("ignoring malformed keymap {:?}", path),
+        }
+    }
+
+    pub fn bind(&mut self, chord: &str, method: &str) {
+        self.bindings.insert(chord.to_lowercase(), method.to_string());
+    }
+
+    pub fn command_for(&self, chord: &str) -> Option<&str> {
+        self.bindings.get(chord).map(|method| method.as_str())
+    }
+}
+
+/// Builds the chord name used in keymap files for a key press, e.g. `ctrl+alt+left`.
+pub fn chord(keycode: Keycode, keymod: Mod) -> String {
+    let mut chord = String::new();
+    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+        chord.push_str("ctrl+");
+    }
+    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) {
+        chord.push_str("alt+");
+    }
+    if keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD) {
+        chord.push_str("cmd+");
+    }
+    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        chord.push_str("shift+");
+    }
+    chord.push_str(&keycode.name().to_lowercase());
+    chord
+}
@@ -0,0 +1,381 @@
+use xi_path_renderer::display_list::{ Color, DisplayItem, FontFace, FontSpec, Frame, Point, Rect, TextRun };
+use xi_path_renderer::fonts::MeasureText;
+
+use crate::bidi::{ self, VisualLine };
+use crate::folds::Fold;
+use crate::line::Line;
+use crate::minimap::MINIMAP_WIDTH;
+use crate::retained::RetainedLayout;
+use crate::settings::ViewSettings;
+use crate::status_bar::{ StatusAlignment, StatusBar };
+use crate::styles::{ StyleDef, StyleMap };
+
+pub const BACKGROUND: Color = Color::white();
+const TEXT_COLOR: Color = Color::black();
+const GUTTER_TEXT_COLOR: Color = Color::rgb(170, 170, 170);
+const CURRENT_LINE_NUMBER_COLOR: Color = Color::rgb(80, 80, 80);
+const CURRENT_LINE_BACKGROUND: Color = Color::rgb(246, 246, 250);
+const FOLD_MARKER_COLOR: Color = Color::rgb(150, 150, 150);
+const PLACEHOLDER_BACKGROUND: Color = Color::rgb(230, 230, 235);
+const PLACEHOLDER_TEXT_COLOR: Color = Color::rgb(110, 110, 110);
+const CURSOR_COLOR: Color = Color::black();
+const STATUS_BACKGROUND: Color = Color::rgb(240, 240, 240);
+const STATUS_TEXT_COLOR: Color = Color::rgb(90, 90, 90);
+const PICKER_BACKGROUND: Color = Color::rgb(250, 250, 250);
+const PICKER_SELECTED: Color = Color::rgb(200, 220, 255);
+
+pub const TEXT_PADDING: f32 = 4.0;
+// the gutter is at least this many digits wide, so it doesn't jump about in short files
+const MIN_NUMBER_DIGITS: usize = 2;
+const STATUS_PADDING: f32 = 4.0;
+const STATUS_FONT_SIZE: f32 = 12.0;
+
+/// The fonts the editor is drawn with, unless a view's settings pick another face.
+#[derive(Clone, Debug)]
+pub struct LayoutConfig {
+    pub text_face: FontFace,
+    pub gutter_face: FontFace,
+    pub status_face: FontFace,
+}
+
+// a family the system maps to its monospace font, and any family it can't find falls back to that too
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        let face = FontFace::Family("monospace".to_string());
+        LayoutConfig { text_face: face.clone(), gutter_face: face.clone(), status_face: face }
+    }
+}
+
+impl LayoutConfig {
+    pub fn text_font(&self, settings: &ViewSettings) -> FontSpec {
+        let face = match &settings.font_face {
+            Some(family) => FontFace::Family(family.clone()),
+            None => self.text_face.clone(),
+        };
+        FontSpec::new(face, settings.font_size)
+    }
+
+    fn gutter_font(&self, settings: &ViewSettings) -> FontSpec {
+        let face = match &settings.font_face {
+            Some(family) => FontFace::Family(family.clone()),
+            None => self.gutter_face.clone(),
+        };
+        FontSpec::new(face, settings.font_size)
+    }
+
+    fn status_font(&self) -> FontSpec {
+        FontSpec::new(self.status_face.clone(), STATUS_FONT_SIZE)
+    }
+}
+
+/// What's needed to lay out a view: the lines on screen, top first, and how to draw them.
+pub struct ViewLayout<'a> {
+    pub lines: &'a [Line],
+    pub styles: &'a StyleMap,
+    pub settings: &'a ViewSettings,
+    pub status_bar: Option<&'a StatusBar>,
+    /// The screen line `lines` starts at.
+    pub first_line: usize,
+    /// The highest line number in the buffer, which is what the gutter is made wide enough for.
+    pub last_line_number: usize,
+    /// The buffer line the cursor is on, counting from 1 like line numbers. It can be off
+    /// screen, relative numbers still count from it.
+    pub cursor_line: Option<usize>,
+}
+
+/// How much of a view fits in the window, in lines and logical pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub first_line: usize,
+    /// How many lines fit, counting one partly hidden at the bottom.
+    pub visible_lines: usize,
+    pub line_height: f32,
+    pub descent: f32,
+    /// The whole gutter: a column for diagnostic markers, the line numbers, then a column for fold markers.
+    pub gutter_width: f32,
+    pub marker_width: f32,
+    pub number_width: f32,
+    /// The width of the text right of the gutter, which is what lines wrap to when wrapping by width.
+    pub text_width: f32,
+    /// The height above the status bar.
+    pub text_height: f32,
+}
+
+impl Viewport {
+    /// The lines on screen, end exclusive, the way xi-core's `scroll` wants them.
+    pub fn line_range(&self) -> (usize, usize) {
+        (self.first_line, self.first_line + self.visible_lines)
+    }
+}
+
+/// Works out the viewport for a window of `width` by `height`, scrolled to `first_line`, with
+/// room in the gutter for numbers up to `last_line_number`.
+pub fn viewport(width: f32, height: f32,
+                first_line: usize,
+                last_line_number: usize,
+                settings: &ViewSettings,
+                show_status_bar: bool,
+                config: &LayoutConfig,
+                fonts: &mut dyn MeasureText) -> Viewport {
+    let status_height = if show_status_bar { status_bar_height(config, fonts) } else { 0.0 };
+    let text_height = (height - status_height).max(0.0);
+
+    // rows are spaced by the text font, so gutter numbers line up with the lines they number
+    let metrics = fonts.line_metrics(&config.text_font(settings));
+    let line_height = metrics.height();
+
+    //get total lines the view can fit in entirety, drop the remainder by forcing to usize, add 1 line to be partially rendered at bottom
+    let visible_lines = (text_height / line_height) as usize + 1;
+
+    // the gutter grows a digit at a time as the buffer does, rather than with every line number
+    let digits = last_line_number.to_string().len().max(MIN_NUMBER_DIGITS);
+    let number_width = fonts.text_width(&"0".repeat(digits), &config.gutter_font(settings));
+    let marker_width = (line_height * 0.75).round();
+    let gutter_width = marker_width + number_width + marker_width;
+    // text wraps short of the minimap, which is drawn over the right of the window
+    let minimap_width = if settings.show_minimap { MINIMAP_WIDTH } else { 0.0 };
+
+    Viewport {
+        first_line,
+        visible_lines,
+        line_height,
+        descent: metrics.descent,
+        gutter_width,
+        marker_width,
+        number_width,
+        text_width: (width - gutter_width - TEXT_PADDING - minimap_width).max(0.0),
+        text_height,
+    }
+}
+
+/// Lays out a whole frame for a view: gutter, text, and the status bar along the bottom.
+/// Lays out every line from scratch, the main loop keeps a `RetainedLayout` between frames instead.
+pub fn build_frame(width: f32, height: f32,
+                   view: &ViewLayout,
+                   config: &LayoutConfig,
+                   fonts: &mut dyn MeasureText) -> Frame {
+    RetainedLayout::new().build_frame(width, height, view, config, fonts)
+}
+
+/// The gutter for each of `lines`, in frame coordinates: the line's number, diagnostic and fold markers,
+/// blank on the rows a wrapped line continues on, and the highlight behind `current_line`, the
+/// buffer line the cursor is on, which spans the whole `width` of the row.
+pub fn gutter_rows(lines: &[Line],
+                   current_line: Option<usize>,
+                   viewport: &Viewport,
+                   width: f32,
+                   settings: &ViewSettings,
+                   config: &LayoutConfig,
+                   fonts: &mut dyn MeasureText) -> Vec<Vec<DisplayItem>> {
+    let font = config.gutter_font(settings);
+
+    lines.iter().enumerate().map(|(row, line)| {
+        let mut frame = Frame::new(0.0, 0.0, BACKGROUND);
+        let top = viewport.line_height * row as f32;
+        let is_current = current_line.is_some() && line.buffer_line.or(line.line_number) == current_line;
+
+        if is_current && settings.highlight_current_line {
+            frame.fill_rect(Rect::new(0.0, top, width, viewport.line_height), CURRENT_LINE_BACKGROUND);
+        }
+
+        if let Some(number) = line.line_number {
+            // relative numbers count from the cursor's line, which keeps its own number
+            let label = match current_line {
+                Some(current) if settings.relative_line_numbers && !is_current => {
+                    (number as isize - current as isize).abs().to_string()
+                }
+                _ => number.to_string(),
+            };
+            let color = if is_current { CURRENT_LINE_NUMBER_COLOR } else { GUTTER_TEXT_COLOR };
+            // right aligned, so the digits of neighbouring numbers line up
+            let x = viewport.marker_width + viewport.number_width - fonts.text_width(&label, &font);
+            frame.fill_text(&label, Point::new(x, top + viewport.line_height - viewport.descent), &font, color);
+
+            // a square in the marker column left of the number, for the worst diagnostic on the line
+            if let Some(severity) = line.diagnostic {
+                let size = (viewport.marker_width / 2.0).round();
+                let marker = Rect::new((viewport.marker_width - size) / 2.0, top + (viewport.line_height - size) / 2.0, size, size);
+                frame.fill_rect(marker, severity.color());
+            }
+        }
+
+        // a minus for an open region and a plus for a folded one, in the column right of the number
+        if let Some(fold) = line.fold {
+            let size = (viewport.marker_width / 2.0).round();
+            let left = viewport.marker_width + viewport.number_width + (viewport.marker_width - size) / 2.0;
+            let middle = (top + viewport.line_height / 2.0).round();
+            frame.fill_rect(Rect::new(left, middle, size, 1.0), FOLD_MARKER_COLOR);
+            if fold == Fold::Folded {
+                frame.fill_rect(Rect::new((left + size / 2.0).round(), middle - size / 2.0 + 0.5, 1.0, size), FOLD_MARKER_COLOR);
+            }
+        }
+        frame.items
+    }).collect()
+}
+
+/// Lays out one line with the top left of its text at the origin. `visual` is the line's
+/// runs, measured in the text font.
+pub fn layout_line(line: &Line,
+                   visual: &VisualLine,
+                   viewport: &Viewport,
+                   styles: &StyleMap,
+                   settings: &ViewSettings,
+                   config: &LayoutConfig,
+                   fonts: &mut dyn MeasureText) -> Vec<DisplayItem> {
+    let font = config.text_font(settings);
+    let mut frame = Frame::new(0.0, 0.0, BACKGROUND);
+    let baseline = viewport.line_height - viewport.descent;
+
+    // right to left runs are laid out in visual order, so a span can be drawn in several pieces
+    for (start, end, style) in style_segments(line, styles) {
+        for piece in visual.pieces(start..end) {
+            if let Some(bg) = style.bg {
+                frame.fill_rect(Rect::new(piece.left, 0.0, piece.right - piece.left, viewport.line_height), bg);
+            }
+
+            let mut styled_font = font.clone();
+            styled_font.bold = style.bold;
+            styled_font.italic = style.italic;
+            frame.push_text(TextRun {
+                origin: Point::new(piece.left, baseline),
+                text: bidi::drawn_text(line, settings, piece.range),
+                font: styled_font,
+                color: style.fg.unwrap_or(TEXT_COLOR),
+                underline: style.underline,
+                rtl: piece.rtl,
+            });
+
+            if let Some(color) = style.squiggle {
+                draw_squiggle(&mut frame, piece.left, piece.right, baseline + (viewport.descent / 2.0).max(1.0), color);
+            }
+        }
+    }
+
+    // a folded line ends in a placeholder for the lines it hides
+    if line.folded {
+        let placeholder = " \u{2026} ";
+        let left = visual.runs.last().map(|run| run.x + run.width).unwrap_or(0.0) + fonts.text_width(" ", &font);
+        let width = fonts.text_width(placeholder, &font);
+        frame.fill_rect(Rect::new(left, 2.0, width, viewport.line_height - 4.0), PLACEHOLDER_BACKGROUND);
+        frame.fill_text(placeholder, Point::new(left, baseline), &font, PLACEHOLDER_TEXT_COLOR);
+    }
+
+    // where directions meet, a cursor is split: the top half where text in the line's own
+    // direction goes, the bottom half where the other direction's does
+    for cursor in &line.cursors {
+        match visual.carets(line, *cursor).as_slice() {
+            [x] => frame.caret(Point::new(*x, 0.0), viewport.line_height, CURSOR_COLOR),
+            [primary, secondary] => {
+                let half = viewport.line_height / 2.0;
+                frame.caret(Point::new(*primary, 0.0), half, CURSOR_COLOR);
+                frame.caret(Point::new(*secondary, half), half, CURSOR_COLOR);
+            }
+            _ => {}
+        }
+    }
+    frame.items
+}
+
+// a zigzag two pixels a step, from `left` to `right` with its top at `y`
+fn draw_squiggle(frame: &mut Frame, left: f32, right: f32, y: f32, color: Color) {
+    let mut x = left;
+    let mut up = true;
+    while x < right {
+        let width = (right - x).min(2.0);
+        frame.fill_rect(Rect::new(x, if up { y } else { y + 1.0 }, width, 1.0), color);
+        x += 2.0;
+        up = !up;
+    }
+}
+
+/// Whether `x` is over the gutter's fold markers, where a click folds or unfolds.
+pub fn over_fold_markers(x: f32, viewport: &Viewport) -> bool {
+    x >= viewport.marker_width + viewport.number_width && x < viewport.gutter_width
+}
+
+/// Splits a line at every span boundary, giving each piece the combination of every span over it.
+/// Later spans are drawn over earlier ones, so a selection's background shows over syntax colours.
+fn style_segments(line: &Line, styles: &StyleMap) -> Vec<(usize, usize, StyleDef)> {
+    let mut bounds = vec![0, line.text.len()];
+    for span in &line.styles {
+        bounds.push(line.clamp_offset(span.start));
+        bounds.push(line.clamp_offset(span.end));
+    }
+    bounds.sort();
+    bounds.dedup();
+
+    bounds.windows(2)
+        .map(|pair| {
+            let (start, end) = (pair[0], pair[1]);
+            let style = line.styles.iter()
+                .filter(|span| span.start <= start && span.end >= end)
+                .filter_map(|span| styles.get(span.style_id))
+                .fold(StyleDef::default(), |under, over| over.over(&under));
+            (start, end, style)
+        })
+        .collect()
+}
+
+pub fn status_bar_height(config: &LayoutConfig, fonts: &mut dyn MeasureText) -> f32 {
+    fonts.line_metrics(&config.status_font()).height() + STATUS_PADDING * 2.0
+}
+
+/// Draws the status bar along the bottom of the frame and returns how tall it is.
+pub fn draw_status_bar(frame: &mut Frame,
+                       status_bar: &StatusBar,
+                       config: &LayoutConfig,
+                       fonts: &mut dyn MeasureText) -> f32 {
+    let font = config.status_font();
+    let metrics = fonts.line_metrics(&font);
+    let padding = STATUS_PADDING;
+    let bar_height = status_bar_height(config, fonts);
+    let bar_top = frame.height - bar_height;
+
+    frame.fill_rect(Rect::new(0.0, bar_top, frame.width, bar_height), STATUS_BACKGROUND);
+    let baseline = bar_top + padding + metrics.ascent;
+
+    // left items flow right from the left edge, right items flow left from the right edge
+    let mut left = padding;
+    for item in status_bar.items(StatusAlignment::Left) {
+        frame.fill_text(&item.value, Point::new(left, baseline), &font, STATUS_TEXT_COLOR);
+        left += fonts.text_width(&item.value, &font) + padding * 4.0;
+    }
+
+    let mut right = frame.width - padding;
+    for item in status_bar.items(StatusAlignment::Right) {
+        right -= fonts.text_width(&item.value, &font);
+        frame.fill_text(&item.value, Point::new(right, baseline), &font, STATUS_TEXT_COLOR);
+        right -= padding * 4.0;
+    }
+    bar_height
+}
+
+/// Draws a list of choices centred near the top of the frame, with the selected one highlighted.
+pub fn draw_picker(frame: &mut Frame,
+                   title: &str,
+                   labels: &[String],
+                   selected: usize,
+                   settings: &ViewSettings,
+                   config: &LayoutConfig,
+                   fonts: &mut dyn MeasureText) {
+    let font = config.text_font(settings);
+    let metrics = fonts.line_metrics(&font);
+    let line_height = metrics.height() + 6.0;
+    let padding = 8_f32;
+    let width = frame.width / 2.0;
+    let left = (frame.width - width) / 2.0;
+    let top = padding * 4.0;
+
+    // title row plus one row per item
+    let height = line_height * (labels.len() + 1) as f32 + padding * 2.0;
+    frame.fill_rect(Rect::new(left, top, width, height), PICKER_BACKGROUND);
+    frame.fill_text(title, Point::new(left + padding, top + padding + metrics.ascent), &font, GUTTER_TEXT_COLOR);
+
+    for (index, label) in labels.iter().enumerate() {
+        let row_top = top + padding + line_height * (index + 1) as f32;
+        if index == selected {
+            frame.fill_rect(Rect::new(left, row_top, width, line_height), PICKER_SELECTED);
+        }
+        frame.fill_text(label, Point::new(left + padding, row_top + 3.0 + metrics.ascent), &font, TEXT_COLOR);
+    }
+}
@@ -1,4 +1,31 @@
+pub mod bidi;
+pub mod columns;
+pub mod diagnostics;
+pub mod file_finder;
+pub mod file_watcher;
+pub mod folds;
+pub mod frame_stats;
 pub mod frontend;
+pub mod keymap;
+pub mod layout;
+pub mod line;
+pub mod line_cache;
+pub mod line_diff;
+pub mod measure;
+pub mod message;
+pub mod minimap;
+pub mod picker;
+pub mod retained;
+pub mod scheduler;
+pub mod session_state;
+pub mod settings;
+pub mod state;
+pub mod status_bar;
+pub mod styles;
+#[cfg(test)]
+mod test_measure;
+pub mod trace;
+pub mod waker;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,57 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::diagnostics::Severity;
+use crate::folds::Fold;
+
+/// A span of a line drawn in one of the styles xi-core defined with `def_style`.
+/// Offsets are utf-8 byte offsets into the line's text, end exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StyleSpan {
+    pub start: usize,
+    pub end: usize,
+    pub style_id: usize,
+}
+
+/// A line of a view, as xi-core sends it in an update.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    pub text: String,
+    /// Byte offsets of the cursors on this line.
+    pub cursors: Vec<usize>,
+    pub styles: Vec<StyleSpan>,
+    /// The buffer line number, only set on the first screen line of a buffer line.
+    pub line_number: Option<usize>,
+    /// The buffer line this screen line is part of, counting from 1 like `line_number`, and set
+    /// on wrapped rows too. `None` when it isn't known, like for lines xi-core hasn't sent.
+    pub buffer_line: Option<usize>,
+    /// The worst diagnostic on the buffer line, which the gutter shows a marker for.
+    pub diagnostic: Option<Severity>,
+    /// Set on the first row of a line a fold region starts at, for the gutter's fold marker.
+    pub fold: Option<Fold>,
+    /// The last row on screen of a line whose region is folded, which ends with a placeholder.
+    pub folded: bool,
+    /// Changes whenever xi-core sends the line again, so anything worked out from it can be kept until then.
+    pub revision: u64,
+}
+
+impl Line {
+    pub fn new(text: &str) -> Self {
+        Line {
+            text: text.to_string(),
+            ..Line::default()
+        }
+    }
+
+    /// Snaps an offset from xi-core to the text, in case it points past the end or into a
+    /// grapheme cluster, like between a letter and an accent combined with it.
+    pub fn clamp_offset(&self, offset: usize) -> usize {
+        if offset >= self.text.len() {
+            return self.text.len();
+        }
+        self.text.grapheme_indices(true)
+            .map(|(start, _)| start)
+            .take_while(|start| *start <= offset)
+            .last()
+            .unwrap_or(0)
+    }
+}
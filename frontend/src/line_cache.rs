@@ -0,0 +1,361 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::diagnostics::{ self, Diagnostic };
+use crate::line::{ Line, StyleSpan };
+use crate::styles::{ FIND_HIGHLIGHT_STYLE, SELECTION_STYLE };
+
+/// A line as it comes in an update. `update` ops leave out the text.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LineUpdate {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub cursor: Vec<usize>,
+    /// Triples of (start, relative to the end of the previous span; length; style id).
+    #[serde(default)]
+    pub styles: Vec<i64>,
+    #[serde(default)]
+    pub ln: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpKind {
+    #[serde(rename = "ins")]
+    Insert,
+    Skip,
+    Invalidate,
+    Copy,
+    Update,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpdateOp {
+    pub op: OpKind,
+    pub n: usize,
+    #[serde(default)]
+    pub lines: Vec<LineUpdate>,
+    /// For `copy`, the new line number of the first line copied.
+    #[serde(default)]
+    pub ln: Option<usize>,
+}
+
+/// Ranges of the buffer xi-core or a plugin has marked, like selections, find results or diagnostics.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Annotation {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// (start line, start column, end line, end column), lines from 0 and columns in utf-8 bytes.
+    #[serde(default)]
+    pub ranges: Vec<[usize; 4]>,
+    #[serde(default)]
+    pub payloads: Option<Vec<Value>>,
+}
+
+/// The params of xi-core's `update` notification, the changes to a view's lines since the last one.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ViewUpdate {
+    pub ops: Vec<UpdateOp>,
+    #[serde(default)]
+    pub pristine: bool,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+/// Our copy of a view's lines, kept in step with xi-core by applying its updates.
+/// Lines xi-core hasn't sent yet, or has told us are out of date, are `None`.
+#[derive(Default)]
+pub struct LineCache {
+    lines: Vec<Option<Line>>,
+    annotations: Vec<Annotation>,
+    next_revision: u64,
+    /// Changes whenever the annotations do, since they're drawn over lines that didn't change.
+    pub annotations_revision: u64,
+    /// Changes with every update, for whatever is worked out from all of the lines at once.
+    pub generation: u64,
+}
+
+impl LineCache {
+    pub fn new() -> Self {
+        LineCache::default()
+    }
+
+    pub fn height(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn get(&self, row: usize) -> Option<&Line> {
+        self.lines.get(row).and_then(|line| line.as_ref())
+    }
+
+    /// The number of the last buffer line we have, which is as many lines as the buffer has once
+    /// xi-core has sent its end.
+    pub fn last_line_number(&self) -> usize {
+        self.lines.iter().rev().flatten().filter_map(|line| line.line_number).next().unwrap_or(0)
+    }
+
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    pub fn apply_update(&mut self, update: ViewUpdate) {
+        self.generation += 1;
+        let mut old = std::mem::replace(&mut self.lines, Vec::new()).into_iter();
+
+        for op in update.ops {
+            match op.op {
+                OpKind::Copy => {
+                    let mut copied: Vec<Option<Line>> = old.by_ref().take(op.n).collect();
+                    // copied lines keep their revision, only their numbers move
+                    if let Some(new_first) = op.ln {
+                        let old_first = copied.iter().flatten().filter_map(|line| line.line_number).next();
+                        if let Some(old_first) = old_first {
+                            for line in copied.iter_mut().flatten() {
+                                line.line_number = line.line_number.map(|n| n + new_first - old_first);
+                            }
+                        }
+                    }
+                    self.lines.extend(copied);
+                }
+                OpKind::Skip => {
+                    old.by_ref().take(op.n).for_each(drop);
+                }
+                OpKind::Invalidate => self.lines.extend((0..op.n).map(|_| None)),
+                OpKind::Insert => {
+                    for update in op.lines {
+                        let line = self.new_line(update.text.as_deref().unwrap_or(""), &update);
+                        self.lines.push(Some(line));
+                    }
+                }
+                OpKind::Update => {
+                    for update in op.lines {
+                        let line = match old.next() {
+                            Some(Some(old_line)) => {
+                                let mut line = self.new_line(&old_line.text, &update);
+                                line.line_number = update.ln.or(old_line.line_number);
+                                Some(line)
+                            }
+                            _ => None,
+                        };
+                        self.lines.push(line);
+                    }
+                }
+            }
+        }
+
+        if update.annotations != self.annotations {
+            self.annotations = update.annotations;
+            self.annotations_revision += 1;
+        }
+    }
+
+    fn new_line(&mut self, text: &str, update: &LineUpdate) -> Line {
+        self.next_revision += 1;
+        let mut line = Line::new(text);
+        line.cursors = update.cursor.clone();
+        line.line_number = update.ln;
+        line.revision = self.next_revision;
+
+        let mut end = 0_i64;
+        for triple in update.styles.chunks(3) {
+            if let [start, length, style_id] = *triple {
+                let start = (end + start).max(0);
+                end = start + length.max(0);
+                line.styles.push(StyleSpan { start: start as usize, end: end as usize, style_id: style_id as usize });
+            }
+        }
+        line
+    }
+
+    /// The screen lines from `first` up to `last`, ready to draw: missing lines come back empty,
+    /// and selections, find results and diagnostics from the annotations are added to their styles.
+    pub fn lines(&self, first: usize, last: usize) -> Vec<Line> {
+        let mut lines: Vec<Line> = (first..last)
+            .map(|row| self.get(row).cloned().unwrap_or_default())
+            .collect();
+        let diagnostics = self.diagnostics();
+        let ranges = self.styled_ranges(&diagnostics);
+
+        // wrapped lines only number their first row, so work out where every row is in its buffer line
+        let mut position = self.buffer_position(first);
+        for line in lines.iter_mut() {
+            if let Some(number) = line.line_number {
+                position = Some((number.saturating_sub(1), 0));
+            }
+            if let Some((buffer_line, column)) = position {
+                line.buffer_line = Some(buffer_line + 1);
+                line.diagnostic = diagnostics.iter()
+                    .filter(|diagnostic| diagnostic.range[0] == buffer_line)
+                    .map(|diagnostic| diagnostic.severity)
+                    .max();
+                annotate(line, buffer_line, column, &ranges);
+                position = Some((buffer_line, column + line.text.len()));
+            }
+        }
+        lines
+    }
+
+    /// The text of every buffer line we have, by its line number counting from 0. Wrapped lines
+    /// only bring their first row, which is enough to see how far they're indented.
+    pub fn buffer_lines<'a>(&'a self) -> impl Iterator<Item = (usize, &'a str)> + 'a {
+        self.lines.iter()
+            .flatten()
+            .filter_map(|line| line.line_number.map(|number| (number.saturating_sub(1), line.text.as_str())))
+    }
+
+    /// The buffer lines, counting from 0, that have a cursor on them.
+    pub fn cursor_lines(&self) -> Vec<usize> {
+        let mut buffer_line = None;
+        let mut lines = Vec::new();
+        for line in &self.lines {
+            let line = match line {
+                Some(line) => line,
+                None => {
+                    buffer_line = None;
+                    continue;
+                }
+            };
+            if let Some(number) = line.line_number {
+                buffer_line = Some(number.saturating_sub(1));
+            }
+            if let (Some(buffer_line), false) = (buffer_line, line.cursors.is_empty()) {
+                lines.push(buffer_line);
+            }
+        }
+        lines.dedup();
+        lines
+    }
+
+    /// The whole buffer, or `None` while xi-core hasn't sent all of it.
+    pub fn text(&self) -> Option<String> {
+        self.lines.iter().map(|line| line.as_ref().map(|line| line.text.as_str())).collect()
+    }
+
+    /// The selections xi-core has marked, one range for each, a cursor being an empty one.
+    pub fn selections(&self) -> Vec<[usize; 4]> {
+        self.annotations.iter()
+            .filter(|annotation| annotation.kind == "selection")
+            .flat_map(|annotation| annotation.ranges.iter().cloned())
+            .collect()
+    }
+
+    /// The diagnostics plugins have marked the buffer with, in buffer order.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        diagnostics::diagnostics(&self.annotations)
+    }
+
+    // every annotated range we draw, with the style it's drawn in
+    fn styled_ranges(&self, diagnostics: &[Diagnostic]) -> Vec<(usize, [usize; 4])> {
+        let mut ranges = Vec::new();
+        for annotation in &self.annotations {
+            let style_id = match annotation.kind.as_str() {
+                "selection" => SELECTION_STYLE,
+                "find" => FIND_HIGHLIGHT_STYLE,
+                _ => continue,
+            };
+            ranges.extend(annotation.ranges.iter().map(|range| (style_id, *range)));
+        }
+        // drawn last, so a squiggle shows under a selection too
+        ranges.extend(diagnostics.iter().map(|diagnostic| (diagnostic.severity.style_id(), diagnostic.range)));
+        ranges
+    }
+
+    // the buffer line and column `row` starts at, when we have the lines to work it out
+    fn buffer_position(&self, row: usize) -> Option<(usize, usize)> {
+        let mut column = 0;
+        for line in self.lines[..row.min(self.lines.len())].iter().rev() {
+            let line = line.as_ref()?;
+            column += line.text.len();
+            if let Some(number) = line.line_number {
+                return Some((number.saturating_sub(1), column));
+            }
+        }
+        None
+    }
+}
+
+fn annotate(line: &mut Line, buffer_line: usize, column: usize, ranges: &[(usize, [usize; 4])]) {
+    let row_end = column + line.text.len();
+    for &(style_id, [start_line, start_col, end_line, end_col]) in ranges {
+        if buffer_line < start_line || buffer_line > end_line {
+            continue;
+        }
+        let start = if buffer_line == start_line { start_col } else { 0 };
+        // a selection carrying on to the next line covers the rest of this one
+        let end = if buffer_line == end_line { end_col } else { usize::max_value() };
+        let (start, end) = (start.max(column), end.min(row_end));
+        if start < end {
+            line.styles.push(StyleSpan { start: start - column, end: end - column, style_id });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn update(value: Value) -> ViewUpdate {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn texts(cache: &LineCache) -> Vec<Option<String>> {
+        (0..cache.height()).map(|row| cache.get(row).map(|line| line.text.clone())).collect()
+    }
+
+    #[test]
+    fn applies_ops_and_keeps_revisions_of_copied_lines() {
+        let mut cache = LineCache::new();
+        cache.apply_update(update(json!({ "ops": [
+            { "op": "ins", "n": 3, "lines": [
+                { "text": "one", "ln": 1 },
+                { "text": "two", "ln": 2, "styles": [0, 3, 2] },
+                { "text": "three", "ln": 3, "cursor": [5] },
+            ]},
+        ]})));
+        let two = cache.get(1).unwrap().revision;
+        assert_eq!(cache.get(1).unwrap().styles, vec![StyleSpan { start: 0, end: 3, style_id: 2 }]);
+
+        // a line inserted above, the cursor moves on the last line
+        cache.apply_update(update(json!({ "ops": [
+            { "op": "ins", "n": 1, "lines": [{ "text": "zero", "ln": 1 }] },
+            { "op": "copy", "n": 2, "ln": 2 },
+            { "op": "update", "n": 1, "lines": [{ "cursor": [0], "ln": 4 }] },
+            { "op": "invalidate", "n": 1 },
+        ]})));
+
+        assert_eq!(texts(&cache), vec![
+            Some("zero".to_string()), Some("one".to_string()), Some("two".to_string()), Some("three".to_string()), None,
+        ]);
+        assert_eq!(cache.get(2).unwrap().revision, two);
+        assert_eq!(cache.get(2).unwrap().line_number, Some(3));
+        let three = cache.get(3).unwrap();
+        assert_eq!((three.cursors.clone(), three.line_number), (vec![0], Some(4)));
+    }
+
+    #[test]
+    fn selection_annotations_become_spans_across_wrapped_rows() {
+        let mut cache = LineCache::new();
+        cache.apply_update(update(json!({
+            "ops": [{ "op": "ins", "n": 3, "lines": [
+                { "text": "first ", "ln": 1 },
+                { "text": "wrapped" },
+                { "text": "second", "ln": 2 },
+            ]}],
+            "annotations": [{ "type": "selection", "ranges": [[0, 3, 1, 2]], "n": 1 }],
+        })));
+
+        let lines = cache.lines(0, 3);
+        let spans: Vec<Vec<StyleSpan>> = lines.iter().map(|line| line.styles.clone()).collect();
+        assert_eq!(spans, vec![
+            vec![StyleSpan { start: 3, end: 6, style_id: SELECTION_STYLE }],
+            vec![StyleSpan { start: 0, end: 7, style_id: SELECTION_STYLE }],
+            vec![StyleSpan { start: 0, end: 2, style_id: SELECTION_STYLE }],
+        ]);
+
+        // only the first row of a wrapped line is numbered, but every row knows its line
+        let numbers: Vec<(Option<usize>, Option<usize>)> = lines.iter().map(|line| (line.line_number, line.buffer_line)).collect();
+        assert_eq!(numbers, vec![(Some(1), Some(1)), (None, Some(1)), (Some(2), Some(2))]);
+    }
+}
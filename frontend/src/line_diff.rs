@@ -0,0 +1,80 @@
+//! A line by line diff, for showing how a file on disk differs from the buffer open on it.
+
+/// One line of a diff, with its line number counting from 1 in the text it's from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Removed(usize, String),
+    Added(usize, String),
+}
+
+impl DiffLine {
+    /// What the diff shows for it, `-` for lines only in the old text and `+` for the new.
+    pub fn describe(&self) -> String {
+        match self {
+            DiffLine::Removed(number, text) => format!("-{} {}", number, text.trim_end_matches('\n')),
+            DiffLine::Added(number, text) => format!("+{} {}", number, text.trim_end_matches('\n')),
+        }
+    }
+}
+
+// past this many lines of difference in the middle, it's not worth working out what's common
+const MAX_CELLS: usize = 4_000_000;
+
+/// The lines removed from `old` and added in `new`, in order. Lines both have at the start and
+/// end are skipped first, and what's left is matched up by longest common subsequence.
+pub fn diff(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let removed = |index: usize| DiffLine::Removed(prefix + index + 1, old_middle[index].to_string());
+    let added = |index: usize| DiffLine::Added(prefix + index + 1, new_middle[index].to_string());
+
+    // too big to match up, everything in the middle has changed
+    if old_middle.len() * new_middle.len() > MAX_CELLS {
+        return (0..old_middle.len()).map(removed).chain((0..new_middle.len()).map(added)).collect();
+    }
+
+    // common[i][j] is how long the longest common subsequence of old_middle[i..] and new_middle[j..] is
+    let (rows, columns) = (old_middle.len(), new_middle.len());
+    let mut common = vec![vec![0_usize; columns + 1]; rows + 1];
+    for i in (0..rows).rev() {
+        for j in (0..columns).rev() {
+            common[i][j] = if old_middle[i] == new_middle[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < rows || j < columns {
+        if i < rows && j < columns && old_middle[i] == new_middle[j] {
+            i += 1;
+            j += 1;
+        } else if j == columns || (i < rows && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(removed(i));
+            i += 1;
+        } else {
+            lines.push(added(j));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_lines_are_listed() {
+        let old = ["fn main() {\n", "    old();\n", "    same();\n", "}\n"];
+        let new = ["fn main() {\n", "    same();\n", "    new();\n", "}\n"];
+        let described: Vec<String> = diff(&old, &new).iter().map(DiffLine::describe).collect();
+        assert_eq!(described, vec!["-2     old();", "+3     new();"]);
+    }
+}
@@ -0,0 +1,41 @@
+use xi_core_lib::width_cache::{ WidthReq, WidthResponse };
+use xi_path_renderer::display_list::FontSpec;
+use xi_path_renderer::fonts::{ FontCache, MeasureText };
+
+/// Answers xi-core's `measure_width`, which it needs to wrap lines to the window's width.
+/// The callback comes in on the core's thread and wants an answer there and then, so this is
+/// shared with the frontend rather than going through the main loop.
+#[derive(Default)]
+pub struct WidthMeasurer {
+    fonts: FontCache,
+    font: Option<FontSpec>,
+}
+
+impl WidthMeasurer {
+    pub fn new() -> Self {
+        WidthMeasurer::default()
+    }
+
+    /// The font text is laid out in, kept up to date by whoever does the layout.
+    pub fn set_font(&mut self, font: FontSpec) {
+        self.font = Some(font);
+    }
+
+    // requests come with a style id each, but bold and italic faces of a monospace font are
+    // the same width, so everything is measured in the plain text font
+    pub fn measure(&mut self, reqs: &[WidthReq]) -> WidthResponse {
+        let font = match &self.font {
+            Some(f) => f.clone(),
+            // nothing laid out yet, count columns so the core still has something to wrap with
+            None => {
+                return reqs.iter()
+                    .map(|req| req.strings.iter().map(|s| s.chars().count() as f64).collect())
+                    .collect()
+            }
+        };
+
+        reqs.iter()
+            .map(|req| req.strings.iter().map(|s| self.fonts.text_width(s, &font) as f64).collect())
+            .collect()
+    }
+}
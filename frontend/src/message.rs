@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use xi_core_lib::{ ViewId, ConfigTable, LanguageId };
+
+use crate::line_cache::ViewUpdate;
+use crate::styles::StyleDef;
+
+/// A frontend callback from xi-core, copied into owned data so it can be sent to the main thread.
+///
+/// xi-core calls the frontend from whatever thread it happens to be running on, while SDL and the
+/// GPU have to stay on the main thread. So callbacks only ever queue one of these, and the main
+/// loop applies them to its `EditorState`.
+#[derive(Clone, Debug)]
+pub enum FrontendMessage {
+    Update { view_id: ViewId, update: ViewUpdate },
+    ScrollTo { view_id: ViewId, line: usize, col: usize },
+    DefStyle { style_id: usize, style: StyleDef },
+    ConfigChanged { view_id: ViewId, changes: ConfigTable },
+    AvailableLanguages(Vec<LanguageId>),
+    LanguageChanged { view_id: ViewId, language: LanguageId },
+    AddStatusItem { view_id: ViewId, key: String, value: String, alignment: String },
+    UpdateStatusItem { view_id: ViewId, key: String, value: String },
+    RemoveStatusItem { view_id: ViewId, key: String },
+    ScheduleIdle(usize),
+    ScheduleTimer { deadline: Instant, token: usize },
+    /// A file open in a view changed on disk, from the file watcher rather than xi-core.
+    FileChanged(PathBuf),
+}
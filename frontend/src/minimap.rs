@@ -0,0 +1,294 @@
+//! The minimap: the whole buffer drawn small down the right edge of the window, with what's on
+//! screen highlighted and ticks for find matches and diagnostics. xi-core only sends the lines
+//! we scroll to, so the rest are asked for a chunk at a time, and again when it invalidates them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use xi_path_renderer::display_list::{ Color, Frame, Image, Rect };
+
+use crate::line::Line;
+use crate::line_cache::LineCache;
+use crate::settings::ViewSettings;
+use crate::styles::StyleMap;
+
+pub const MINIMAP_WIDTH: f32 = 80.0;
+// a row is at most this tall and a character this wide, smaller when the buffer doesn't fit
+const ROW_HEIGHT: f32 = 2.0;
+const CHAR_WIDTH: f32 = 1.0;
+// how many rows of the line cache are asked for at a time
+const REQUEST_CHUNK: usize = 500;
+
+const BACKGROUND: Color = Color::rgb(248, 248, 248);
+const TEXT_COLOR: Color = Color::rgb(60, 60, 60);
+const ON_SCREEN: Color = Color::rgba(0, 0, 0, 28);
+const FIND_TICK: Color = Color::rgb(230, 170, 0);
+const TICK_WIDTH: f32 = 4.0;
+
+/// Where the minimap goes, and how the line cache's rows map onto it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinimapLayout {
+    pub rect: Rect,
+    pub rows: usize,
+    pub row_height: f32,
+}
+
+impl MinimapLayout {
+    /// The minimap for a window `width` wide, down the `height` above the status bar.
+    pub fn new(width: f32, height: f32, rows: usize) -> Self {
+        let row_height = match rows {
+            0 => ROW_HEIGHT,
+            rows => (height / rows as f32).min(ROW_HEIGHT),
+        };
+        MinimapLayout { rect: Rect::new(width - MINIMAP_WIDTH, 0.0, MINIMAP_WIDTH, height), rows, row_height }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.rect.x && x < self.rect.max_x() && y >= self.rect.y && y < self.rect.max_y()
+    }
+
+    pub fn y_of(&self, row: usize) -> f32 {
+        self.rect.y + row as f32 * self.row_height
+    }
+
+    /// The row drawn at `y`, for scrolling to where the minimap was clicked or dragged to.
+    pub fn row_at(&self, y: f32) -> usize {
+        let row = ((y - self.rect.y).max(0.0) / self.row_height) as usize;
+        row.min(self.rows.saturating_sub(1))
+    }
+}
+
+// what the drawn image depends on, when any of it changes it's drawn again
+#[derive(Clone, Copy, PartialEq)]
+struct DrawnKey {
+    generation: u64,
+    styles_generation: u64,
+    rows: usize,
+    size: (u32, u32),
+    row_height: u32,
+}
+
+/// A view's minimap, kept between frames.
+#[derive(Default)]
+pub struct Minimap {
+    drawn: Option<(DrawnKey, Arc<Image>, Vec<(usize, Color)>)>,
+    // the rows on screen last frame, to tell when the highlight moved
+    on_screen: Option<(usize, usize)>,
+    // the rows last asked for, and the cache generation they were asked for at
+    requested: Option<(u64, (usize, usize))>,
+    /// Where it was last drawn, for working out what a click on it hit.
+    pub layout: Option<MinimapLayout>,
+    /// Whether the mouse went down on it and hasn't come up yet.
+    pub dragging: bool,
+}
+
+impl Minimap {
+    /// Draws the minimap into `frame`, with `on_screen` the rows of the line cache the window
+    /// shows, end exclusive. Returns whether it looks different to last frame.
+    pub fn draw(&mut self,
+                frame: &mut Frame,
+                layout: MinimapLayout,
+                lines: &LineCache,
+                styles: &StyleMap,
+                settings: &ViewSettings,
+                on_screen: (usize, usize),
+                scale: f32) -> bool {
+        let size = ((layout.rect.width * scale).round() as u32, (layout.rect.height * scale).round() as u32);
+        let key = DrawnKey {
+            generation: lines.generation,
+            styles_generation: styles.generation,
+            rows: layout.rows,
+            size,
+            row_height: layout.row_height.to_bits(),
+        };
+
+        let redrawn = self.drawn.as_ref().map(|(drawn, _, _)| *drawn != key).unwrap_or(true);
+        if redrawn {
+            let image = Arc::new(render(lines, styles, settings, &layout, size, scale));
+            self.drawn = Some((key, image, ticks(lines)));
+        }
+        let changed = redrawn || self.on_screen != Some(on_screen) || self.layout != Some(layout);
+        self.on_screen = Some(on_screen);
+        self.layout = Some(layout);
+
+        let (_, image, ticks) = match &self.drawn {
+            Some(drawn) => drawn,
+            None => return changed,
+        };
+        frame.fill_rect(layout.rect, BACKGROUND);
+        frame.draw_image(layout.rect, image.clone());
+
+        let top = layout.y_of(on_screen.0);
+        let bottom = layout.y_of(on_screen.1).max(top + 2.0).min(layout.rect.max_y());
+        frame.fill_rect(Rect::new(layout.rect.x, top, layout.rect.width, bottom - top), ON_SCREEN);
+
+        for &(row, color) in ticks {
+            let tick = Rect::new(layout.rect.max_x() - TICK_WIDTH, layout.y_of(row), TICK_WIDTH, layout.row_height.max(2.0));
+            frame.fill_rect(tick, color);
+        }
+        changed
+    }
+
+    /// The next rows to ask xi-core for, if there are rows we don't have and we're not already
+    /// waiting on some. Rows xi-core invalidates are missing again, and asked for again.
+    pub fn rows_to_request(&mut self, lines: &LineCache) -> Option<(usize, usize)> {
+        if let Some((generation, _)) = self.requested {
+            if generation == lines.generation {
+                return None;
+            }
+        }
+
+        let first = (0..lines.height()).find(|row| lines.get(*row).is_none())?;
+        let last = (first..lines.height().min(first + REQUEST_CHUNK))
+            .take_while(|row| lines.get(*row).is_none())
+            .last()
+            .unwrap_or(first);
+        let range = (first, last + 1);
+        self.requested = Some((lines.generation, range));
+        Some(range)
+    }
+}
+
+// a block for every character that isn't blank, in its style's colour. with more rows than
+// pixels each pixel row shows the first of the rows it covers
+fn render(lines: &LineCache,
+          styles: &StyleMap,
+          settings: &ViewSettings,
+          layout: &MinimapLayout,
+          size: (u32, u32),
+          scale: f32) -> Image {
+    let mut image = Image::new(size.0, size.1, Color::transparent());
+    let row_pixels = layout.row_height * scale;
+    let char_pixels = (CHAR_WIDTH * scale).max(1.0) as i32;
+    let mut last_top = -1;
+
+    for row in 0..layout.rows {
+        let top = (row as f32 * row_pixels) as i32;
+        if top == last_top {
+            continue;
+        }
+        last_top = top;
+        if top >= size.1 as i32 {
+            break;
+        }
+        let bottom = (((row + 1) as f32 * row_pixels) as i32).max(top + 1);
+        let line = match lines.get(row) {
+            Some(line) => line,
+            None => continue,
+        };
+
+        let mut column = 0;
+        for (offset, c) in line.text.char_indices() {
+            if c == '\t' {
+                column += settings.tab_size - column % settings.tab_size;
+                continue;
+            }
+            let left = column as i32 * char_pixels;
+            if left >= size.0 as i32 {
+                break;
+            }
+            if !c.is_whitespace() {
+                let color = color_at(line, offset, styles);
+                for y in top..bottom {
+                    for x in left..left + char_pixels {
+                        image.blend(x, y, color, 170);
+                    }
+                }
+            }
+            column += 1;
+        }
+    }
+    image
+}
+
+// the foreground of the last span over `offset` that sets one
+fn color_at(line: &Line, offset: usize, styles: &StyleMap) -> Color {
+    line.styles.iter()
+        .rev()
+        .filter(|span| span.start <= offset && offset < span.end)
+        .filter_map(|span| styles.get(span.style_id).and_then(|style| style.fg))
+        .next()
+        .unwrap_or(TEXT_COLOR)
+}
+
+// a tick for each find match and diagnostic, at the row its line starts on. lines we don't have
+// yet are put where they'd be if no line wrapped
+fn ticks(lines: &LineCache) -> Vec<(usize, Color)> {
+    let rows: HashMap<usize, usize> = (0..lines.height())
+        .filter_map(|row| lines.get(row).and_then(|line| line.line_number).map(|number| (number - 1, row)))
+        .collect();
+    let last_line = lines.last_line_number().max(1);
+    let row_of = |line: usize| rows.get(&line).cloned().unwrap_or(line * lines.height() / last_line);
+
+    let mut ticks: Vec<(usize, Color)> = lines.annotations().iter()
+        .filter(|annotation| annotation.kind == "find")
+        .flat_map(|annotation| annotation.ranges.iter().map(|range| (row_of(range[0]), FIND_TICK)))
+        .collect();
+    ticks.extend(lines.diagnostics().iter().map(|diagnostic| (row_of(diagnostic.range[0]), diagnostic.severity.color())));
+    ticks
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::diagnostics::Severity;
+
+    #[test]
+    fn missing_rows_are_asked_for_once_per_update() {
+        let mut lines = LineCache::new();
+        lines.apply_update(serde_json::from_value(json!({ "ops": [
+            { "op": "ins", "n": 1, "lines": [{ "text": "one", "ln": 1 }] },
+            { "op": "invalidate", "n": 3 },
+            { "op": "ins", "n": 1, "lines": [{ "text": "five", "ln": 5 }] },
+        ]})).unwrap());
+
+        let mut minimap = Minimap::default();
+        assert_eq!(minimap.rows_to_request(&lines), Some((1, 4)));
+        // nothing new from xi-core yet, so nothing more to ask
+        assert_eq!(minimap.rows_to_request(&lines), None);
+    }
+
+    #[test]
+    fn long_buffers_are_squeezed_to_fit() {
+        let layout = MinimapLayout::new(800.0, 100.0, 400);
+        assert_eq!(layout.rect, Rect::new(720.0, 0.0, MINIMAP_WIDTH, 100.0));
+        assert_eq!(layout.row_height, 0.25);
+        assert_eq!(layout.row_at(50.0), 200);
+        assert_eq!(layout.row_at(1000.0), 399);
+    }
+
+    #[test]
+    fn ticks_go_on_the_rows_their_lines_start() {
+        let mut lines = LineCache::new();
+        lines.apply_update(serde_json::from_value(json!({
+            "ops": [
+                // line 2 wraps onto a second row, and lines 4 and 5 haven't been sent
+                { "op": "ins", "n": 4, "lines": [
+                    { "text": "one", "ln": 1 },
+                    { "text": "two wraps", "ln": 2 },
+                    { "text": "onto here" },
+                    { "text": "three", "ln": 3 },
+                ]},
+                { "op": "invalidate", "n": 2 },
+                { "op": "ins", "n": 1, "lines": [{ "text": "six", "ln": 6 }] },
+            ],
+            "annotations": [
+                { "type": "find", "ranges": [[0, 0, 0, 3], [2, 1, 2, 4]], "n": 2 },
+                { "type": "diagnostic", "ranges": [[4, 0, 4, 1], [1, 0, 1, 3]], "n": 2, "payloads": [
+                    { "severity": "warning" },
+                    { "severity": "error" },
+                ]},
+            ],
+        })).unwrap());
+
+        // line 5 isn't in the cache, so it goes where it would without wrapping, 4 * 7 rows / 6 lines
+        assert_eq!(ticks(&lines), vec![
+            (0, FIND_TICK),
+            (3, FIND_TICK),
+            (1, Severity::Error.color()),
+            (4, Severity::Warning.color()),
+        ]);
+    }
+}
@@ -0,0 +1,46 @@
+/// A list of choices shown as an overlay, navigated with the arrow keys.
+pub struct Picker<T> {
+    pub title: String,
+    items: Vec<T>,
+    selected: usize,
+}
+
+impl<T> Picker<T> {
+    pub fn new(title: &str, items: Vec<T>) -> Self {
+        Picker {
+            title: title.to_string(),
+            items,
+            selected: 0,
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected(&self) -> Option<&T> {
+        self.items.get(self.selected)
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.items.len() {
+            self.selected = index;
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.items.is_empty() {
+            self.selected = (self.selected + 1) % self.items.len();
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.items.is_empty() {
+            self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+        }
+    }
+}
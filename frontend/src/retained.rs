@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use xi_path_renderer::display_list::{ DisplayItem, Frame, Point, Rect };
+use xi_path_renderer::fonts::MeasureText;
+
+use crate::bidi::VisualLine;
+use crate::layout::{ self, LayoutConfig, Viewport, ViewLayout, BACKGROUND, TEXT_PADDING };
+use crate::line::{ Line, StyleSpan };
+use crate::settings::ViewSettings;
+
+// a line's display items, and its runs kept for hit testing
+type LaidOutLine = (Arc<Vec<DisplayItem>>, Arc<VisualLine>);
+
+// what laying out a line depends on besides the line, when any of it changes every line is laid out again
+#[derive(Clone, PartialEq)]
+struct LayoutContext {
+    settings: ViewSettings,
+    styles_generation: u64,
+    line_height: f32,
+    descent: f32,
+    // lines are drawn right of the gutter, so when it grows they all move
+    gutter_width: f32,
+}
+
+// selections, cursors and folds can change without xi-core sending the line's text again
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct LineKey {
+    revision: u64,
+    styles: Vec<StyleSpan>,
+    cursors: Vec<usize>,
+    folded: bool,
+}
+
+impl LineKey {
+    // lines that didn't come from a line cache have no revision, and are laid out every time
+    fn of(line: &Line) -> Option<LineKey> {
+        if line.revision == 0 {
+            return None;
+        }
+        Some(LineKey {
+            revision: line.revision,
+            styles: line.styles.clone(),
+            cursors: line.cursors.clone(),
+            folded: line.folded,
+        })
+    }
+}
+
+/// Keeps the layout of the lines on screen between frames, so a frame only lays out the lines
+/// that changed, and works out which parts of the window need drawing again.
+#[derive(Default)]
+pub struct RetainedLayout {
+    lines: HashMap<LineKey, LaidOutLine>,
+    context: Option<LayoutContext>,
+    // what was on screen last frame, to compare the next one against
+    rows: Vec<Option<LineKey>>,
+    gutters: Vec<Vec<DisplayItem>>,
+    chrome: Vec<DisplayItem>,
+    size: (f32, f32),
+    /// How many lines the last frame had to lay out, rather than reusing.
+    pub relaid_lines: usize,
+}
+
+impl RetainedLayout {
+    pub fn new() -> Self {
+        RetainedLayout::default()
+    }
+
+    /// Lays out a whole frame for a view: gutter, text, and the status bar along the bottom.
+    pub fn build_frame(&mut self,
+                       width: f32, height: f32,
+                       view: &ViewLayout,
+                       config: &LayoutConfig,
+                       fonts: &mut dyn MeasureText) -> Frame {
+        let mut frame = Frame::new(width, height, BACKGROUND);
+        let viewport = layout::viewport(width, height,
+                                        view.first_line,
+                                        view.last_line_number,
+                                        view.settings,
+                                        view.status_bar.is_some(),
+                                        config,
+                                        fonts);
+
+        if let Some(status_bar) = view.status_bar {
+            layout::draw_status_bar(&mut frame, status_bar, config, fonts);
+        }
+
+        // lines partly hidden behind the status bar are cut off at it
+        frame.push_clip(Rect::new(0.0, 0.0, width, viewport.text_height));
+
+        // everything but the lines and their gutters, when any of it changes it's simplest to draw everything again
+        let chrome = frame.items.clone();
+        let context = LayoutContext {
+            settings: view.settings.clone(),
+            styles_generation: view.styles.generation,
+            line_height: viewport.line_height,
+            descent: viewport.descent,
+            gutter_width: viewport.gutter_width,
+        };
+        let redraw_all = self.size != (width, height) || self.chrome != chrome || self.context.as_ref() != Some(&context);
+        if self.context.as_ref() != Some(&context) {
+            self.lines.clear();
+            self.context = Some(context);
+        }
+
+        let line_margin = viewport.gutter_width + TEXT_PADDING;
+        let row_rect = |row: usize| Rect::new(0.0, viewport.line_height * row as f32, width, viewport.line_height);
+        let mut kept = HashMap::new();
+        let mut rows = Vec::with_capacity(view.lines.len());
+        let mut damage = Vec::new();
+        self.relaid_lines = 0;
+
+        // the gutter is cheap to lay out, and a row is drawn again when its part changes, like
+        // when the cursor moves to another line and the highlight goes with it
+        let gutters = layout::gutter_rows(view.lines, view.cursor_line, &viewport, width, view.settings, config, fonts);
+
+        for (row, (line, gutter)) in view.lines.iter().zip(&gutters).enumerate() {
+            let key = LineKey::of(line);
+            let (items, visual) = match key.as_ref().and_then(|key| self.lines.get(key)) {
+                Some(laid_out) => laid_out.clone(),
+                None => {
+                    self.relaid_lines += 1;
+                    let visual = VisualLine::new(line, view.settings, &config.text_font(view.settings), fonts);
+                    let items = layout::layout_line(line, &visual, &viewport, view.styles, view.settings, config, fonts);
+                    (Arc::new(items), Arc::new(visual))
+                }
+            };
+
+            let top = viewport.line_height * row as f32;
+            frame.items.extend(gutter.iter().cloned());
+            frame.items.extend(items.iter().map(|item| item.translate(line_margin, top)));
+
+            if key.is_none() || self.rows.get(row) != Some(&key) || self.gutters.get(row) != Some(gutter) {
+                damage.push(row_rect(row));
+            }
+            if let Some(key) = key.clone() {
+                kept.insert(key, (items, visual));
+            }
+            rows.push(key);
+        }
+
+        // rows that had a line last frame, and are empty now
+        for row in view.lines.len()..self.rows.len() {
+            damage.push(row_rect(row));
+        }
+        frame.pop_clip();
+
+        // only what's on screen is kept, so the cache doesn't grow with the file
+        self.lines = kept;
+        self.rows = rows;
+        self.gutters = gutters;
+        self.chrome = chrome;
+        self.size = (width, height);
+
+        frame.damage = if redraw_all { None } else { Some(damage) };
+        frame
+    }
+
+    /// The row of `lines` and the offset into it drawn under `point`, with `lines` starting at
+    /// the top of the viewport. `None` when the point isn't over a line. Lines as they were in
+    /// the last frame aren't measured again.
+    pub fn hit_test(&self,
+                    point: Point,
+                    lines: &[Line],
+                    viewport: &Viewport,
+                    settings: &ViewSettings,
+                    config: &LayoutConfig,
+                    fonts: &mut dyn MeasureText) -> Option<(usize, usize)> {
+        if point.y < 0.0 || point.y >= viewport.text_height {
+            return None;
+        }
+        let row = (point.y / viewport.line_height) as usize;
+        let line = lines.get(row)?;
+        let text_x = point.x - viewport.gutter_width - TEXT_PADDING;
+
+        let same_settings = self.context.as_ref().map(|context| &context.settings) == Some(settings);
+        let kept = LineKey::of(line).filter(|_| same_settings).and_then(|key| self.lines.get(&key));
+        let offset = match kept {
+            Some((_, visual)) => visual.offset_at(text_x),
+            None => VisualLine::new(line, settings, &config.text_font(settings), fonts).offset_at(text_x),
+        };
+        Some((row, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_path_renderer::display_list::{ FontFace, FontSpec };
+    use xi_path_renderer::fonts::LineMetrics;
+
+    use crate::styles::StyleMap;
+    use crate::test_measure::FixedWidth;
+
+    fn line(text: &str, revision: u64) -> Line {
+        let mut line = Line::new(text);
+        line.revision = revision;
+        line
+    }
+
+    #[test]
+    fn only_changed_lines_are_laid_out_and_damaged() {
+        let face = FontFace::Family("Monospace".to_string());
+        let config = LayoutConfig { text_face: face.clone(), gutter_face: face.clone(), status_face: face };
+        let settings = ViewSettings::default();
+        let styles = StyleMap::default();
+        let mut retained = RetainedLayout::new();
+
+        let mut lines = vec![line("one", 1), line("two", 2), line("three", 3)];
+        let frame = |retained: &mut RetainedLayout, lines: &[Line]| {
+            let view = ViewLayout { lines, styles: &styles, settings: &settings, status_bar: None, first_line: 0, last_line_number: 3, cursor_line: None };
+            retained.build_frame(200.0, 100.0, &view, &config, &mut FixedWidth)
+        };
+
+        let first = frame(&mut retained, &lines);
+        assert_eq!(first.damage, None);
+        assert_eq!(retained.relaid_lines, 3);
+
+        // nothing changed, nothing to draw
+        assert_eq!(frame(&mut retained, &lines).damage, Some(vec![]));
+        assert_eq!(retained.relaid_lines, 0);
+
+        // a new revision of the second line, and a cursor on the third
+        lines[1] = line("two!", 4);
+        lines[2].cursors = vec![1];
+        let changed = frame(&mut retained, &lines);
+        assert_eq!(retained.relaid_lines, 2);
+        assert_eq!(changed.damage, Some(vec![Rect::new(0.0, 20.0, 200.0, 20.0), Rect::new(0.0, 40.0, 200.0, 20.0)]));
+        assert_eq!(changed.items.len(), first.items.len() + 1);
+    }
+
+    #[test]
+    fn relative_numbers_redraw_every_row_when_the_cursor_changes_line() {
+        let face = FontFace::Family("Monospace".to_string());
+        let config = LayoutConfig { text_face: face.clone(), gutter_face: face.clone(), status_face: face };
+        let mut settings = ViewSettings::default();
+        settings.relative_line_numbers = true;
+        let styles = StyleMap::default();
+        let mut retained = RetainedLayout::new();
+
+        let numbered = |text: &str, number: usize| {
+            let mut line = line(text, number as u64);
+            line.line_number = Some(number);
+            line.buffer_line = Some(number);
+            line
+        };
+        let mut lines = vec![numbered("one", 1), numbered("two", 2), numbered("three", 3)];
+        lines[0].cursors = vec![0];
+        let frame = |retained: &mut RetainedLayout, lines: &[Line], cursor_line: usize| {
+            let view = ViewLayout {
+                lines,
+                styles: &styles,
+                settings: &settings,
+                status_bar: None,
+                first_line: 0,
+                last_line_number: 3,
+                cursor_line: Some(cursor_line),
+            };
+            retained.build_frame(200.0, 100.0, &view, &config, &mut FixedWidth)
+        };
+        frame(&mut retained, &lines, 1);
+
+        // only the two lines the cursor moved between changed, but every number did
+        lines[0].cursors.clear();
+        lines[1].cursors = vec![0];
+        let moved = frame(&mut retained, &lines, 2);
+        assert_eq!(retained.relaid_lines, 2);
+        assert_eq!(moved.damage.map(|damage| damage.len()), Some(3));
+
+        let viewport = layout::viewport(200.0, 100.0, 0, 3, &settings, false, &config, &mut FixedWidth);
+        let labels = |cursor_line: usize| -> Vec<String> {
+            layout::gutter_rows(&lines, Some(cursor_line), &viewport, 200.0, &settings, &config, &mut FixedWidth)
+                .into_iter()
+                .flatten()
+                .filter_map(|item| match item {
+                    DisplayItem::Text(run) => Some(run.text),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(labels(2), vec!["1", "2", "1"]);
+        // with the cursor scrolled off the bottom, the numbers still count from its line
+        assert_eq!(labels(10), vec!["9", "8", "7"]);
+    }
+
+    // measures nothing, so a hit test that measures the line again fails
+    struct Unmeasured;
+
+    impl MeasureText for Unmeasured {
+        fn text_width(&mut self, text: &str, _: &FontSpec) -> f32 {
+            panic!("measured {:?} again", text)
+        }
+
+        fn line_metrics(&mut self, _: &FontSpec) -> LineMetrics {
+            panic!("measured the line height again")
+        }
+    }
+
+    #[test]
+    fn clicks_on_lines_from_the_last_frame_reuse_their_layout() {
+        let face = FontFace::Family("Monospace".to_string());
+        let config = LayoutConfig { text_face: face.clone(), gutter_face: face.clone(), status_face: face };
+        let settings = ViewSettings::default();
+        let styles = StyleMap::default();
+        let mut retained = RetainedLayout::new();
+
+        let lines = vec![line("one", 1), line("two", 2)];
+        let view = ViewLayout { lines: &lines, styles: &styles, settings: &settings, status_bar: None, first_line: 0, last_line_number: 2, cursor_line: None };
+        retained.build_frame(200.0, 100.0, &view, &config, &mut FixedWidth);
+        let viewport = layout::viewport(200.0, 100.0, 0, 2, &settings, false, &config, &mut FixedWidth);
+
+        // two characters in, on the second line
+        let x = viewport.gutter_width + TEXT_PADDING + 21.0;
+        assert_eq!(retained.hit_test(Point::new(x, 25.0), &lines, &viewport, &settings, &config, &mut Unmeasured), Some((1, 2)));
+        assert_eq!(retained.hit_test(Point::new(x, 100.0), &lines, &viewport, &settings, &config, &mut Unmeasured), None);
+    }
+}
@@ -0,0 +1,162 @@
+use std::cmp::Reverse;
+use std::collections::{ BinaryHeap, VecDeque };
+use std::time::{ Duration, Instant };
+
+/// Where the scheduler gets the current time from, so tests can move time along by hand.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Holds the idle and timer tokens xi-core asks for until the main loop gets round to them.
+///
+/// Both kinds end up in `XiCore::handle_idle`: idle tokens whenever there's no input waiting,
+/// timer tokens once their deadline has passed. xi-core leans on these for incremental work
+/// (loading big files, find, wrapping), so nothing gets done if they are never drained.
+pub struct Scheduler<C: Clock = SystemClock> {
+    clock: C,
+    idle: VecDeque<usize>,
+    timers: BinaryHeap<Reverse<(Instant, usize)>>,
+}
+
+impl Default for Scheduler<SystemClock> {
+    fn default() -> Self {
+        Scheduler::with_clock(SystemClock)
+    }
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Scheduler {
+            clock,
+            idle: VecDeque::new(),
+            timers: BinaryHeap::new(),
+        }
+    }
+
+    pub fn schedule_idle(&mut self, token: usize) {
+        self.idle.push_back(token);
+    }
+
+    pub fn schedule_timer(&mut self, deadline: Instant, token: usize) {
+        self.timers.push(Reverse((deadline, token)));
+    }
+
+    pub fn has_idle(&self) -> bool {
+        !self.idle.is_empty()
+    }
+
+    /// How long until the earliest timer is due, zero if one already is, None if there are no timers.
+    pub fn time_until_next_timer(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.timers.peek().map(|Reverse((deadline, _))| deadline.saturating_duration_since(now))
+    }
+
+    /// How long the main loop can block on input before there's scheduled work to do.
+    /// None means there's nothing scheduled and it can wait for the next event.
+    pub fn wait_timeout(&self) -> Option<Duration> {
+        if self.has_idle() {
+            return Some(Duration::from_millis(0));
+        }
+        self.time_until_next_timer()
+    }
+
+    /// Removes the tokens that should be handled now: every expired timer, earliest first,
+    /// then a single idle token so one slice of idle work doesn't hold up input for long.
+    pub fn take_due(&mut self) -> Vec<usize> {
+        let now = self.clock.now();
+        let mut due = Vec::new();
+
+        while let Some(Reverse((deadline, token))) = self.timers.peek().cloned() {
+            if deadline > now {
+                break;
+            }
+            self.timers.pop();
+            due.push(token);
+        }
+
+        if let Some(token) = self.idle.pop_front() {
+            due.push(token);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct MockClock(Rc<Cell<Instant>>);
+
+    impl MockClock {
+        fn new() -> Self {
+            MockClock(Rc::new(Cell::new(Instant::now())))
+        }
+
+        fn advance(&self, by: Duration) {
+            self.0.set(self.0.get() + by);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn idle_tokens_run_one_at_a_time_in_order() {
+        let mut scheduler = Scheduler::with_clock(MockClock::new());
+        scheduler.schedule_idle(1);
+        scheduler.schedule_idle(2);
+
+        assert_eq!(scheduler.wait_timeout(), Some(Duration::from_millis(0)));
+        assert_eq!(scheduler.take_due(), vec![1]);
+        assert_eq!(scheduler.take_due(), vec![2]);
+        assert_eq!(scheduler.take_due(), Vec::<usize>::new());
+        assert_eq!(scheduler.wait_timeout(), None);
+    }
+
+    #[test]
+    fn timers_fire_in_deadline_order_once_due() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+        scheduler.schedule_timer(start + Duration::from_millis(30), 3);
+        scheduler.schedule_timer(start + Duration::from_millis(10), 1);
+        scheduler.schedule_timer(start + Duration::from_millis(20), 2);
+
+        assert_eq!(scheduler.wait_timeout(), Some(Duration::from_millis(10)));
+        assert!(scheduler.take_due().is_empty());
+
+        clock.advance(Duration::from_millis(25));
+        assert_eq!(scheduler.take_due(), vec![1, 2]);
+        assert_eq!(scheduler.wait_timeout(), Some(Duration::from_millis(5)));
+
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(scheduler.take_due(), vec![3]);
+        assert_eq!(scheduler.wait_timeout(), None);
+    }
+
+    #[test]
+    fn expired_timers_run_before_idle_work() {
+        let clock = MockClock::new();
+        let mut scheduler = Scheduler::with_clock(clock.clone());
+        scheduler.schedule_idle(7);
+        scheduler.schedule_timer(clock.now() + Duration::from_millis(5), 8);
+
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(scheduler.take_due(), vec![8, 7]);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+use serde::{ Deserialize, Serialize };
+
+/// What the editor keeps between runs, in `<config_dir>/session.json`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionState {
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, FileState>,
+    /// The views open when the editor last quit, in the order they were opened.
+    #[serde(default)]
+    pub views: Vec<ViewSnapshot>,
+    /// Which of `views` had focus.
+    #[serde(default)]
+    pub focused: Option<usize>,
+    #[serde(default)]
+    pub window: Option<WindowGeometry>,
+}
+
+/// What's kept for each file that's been open.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileState {
+    /// The first lines of the folded regions, counting from 0.
+    #[serde(default)]
+    pub folds: Vec<usize>,
+}
+
+/// A view open at quit, to open again on the next start.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewSnapshot {
+    pub path: PathBuf,
+    /// (start line, start column, end line, end column) like xi-core's selection annotations,
+    /// with the cursor at the end.
+    #[serde(default)]
+    pub selections: Vec<[usize; 4]>,
+    /// The first line that was on screen.
+    #[serde(default)]
+    pub first_line: usize,
+}
+
+/// Where the window was and how big, in points.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SessionState {
+    pub fn path(config_dir: &Path) -> PathBuf {
+        config_dir.join("session.json")
+    }
+
+    // there's no state on a first run, a broken file we just report and start afresh
+    pub fn load(config_dir: &Path) -> Self {
+        let path = SessionState::path(config_dir);
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => return SessionState::default(),
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(state) => state,
+            Err(e) => {
+                eprintln!("ignoring malformed session state {:?}: {}", path, e);
+                SessionState::default()
+            }
+        }
+    }
+
+    pub fn save(&self, config_dir: &Path) {
+        let path = SessionState::path(config_dir);
+        let saved = serde_json::to_string_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            eprintln!("couldn't save session state to {:?}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn state_from_before_views_were_kept_still_loads() {
+        let state: SessionState = serde_json::from_value(json!({
+            "files": { "/src/main.rs": { "folds": [3] } },
+        })).unwrap();
+        assert_eq!(state.files[&PathBuf::from("/src/main.rs")].folds, vec![3]);
+        assert_eq!((state.views.len(), state.focused, state.window), (0, None, None));
+
+        let state: SessionState = serde_json::from_value(json!({
+            "views": [{ "path": "/src/lib.rs", "selections": [[1, 0, 1, 4]] }],
+            "focused": 0,
+        })).unwrap();
+        assert_eq!(state.views, vec![ViewSnapshot {
+            path: PathBuf::from("/src/lib.rs"),
+            selections: vec![[1, 0, 1, 4]],
+            first_line: 0,
+        }]);
+    }
+}
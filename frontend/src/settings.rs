@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use unicode_segmentation::UnicodeSegmentation;
+use xi_core_lib::ConfigTable;
+use xi_path_renderer::fonts::grapheme_columns;
+
+/// The settings of a single view, built up from the `config_changed` notifications xi-core sends.
+///
+/// The first notification for a view carries every setting, later ones only carry what changed.
+/// Keys we don't use (or that come with a type we don't expect) are kept in `other` so they can
+/// still be shown, since plugins and newer versions of xi-core are free to add their own.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewSettings {
+    pub font_face: Option<String>,
+    pub font_size: f32,
+    pub wrap_width: usize,
+    pub tab_size: usize,
+    pub translate_tabs_to_spaces: bool,
+    pub line_ending: String,
+    /// Number lines in the gutter by how far they are from the cursor's, like vim's `relativenumber`.
+    pub relative_line_numbers: bool,
+    pub highlight_current_line: bool,
+    pub show_minimap: bool,
+    pub other: BTreeMap<String, Value>,
+}
+
+impl Default for ViewSettings {
+    // mirrors xi-core's defaults, so a view draws sensibly before its first config_changed
+    fn default() -> Self {
+        ViewSettings {
+            font_face: None,
+            font_size: 14.0,
+            wrap_width: 0,
+            tab_size: 4,
+            translate_tabs_to_spaces: true,
+            line_ending: "\n".to_string(),
+            relative_line_numbers: false,
+            highlight_current_line: true,
+            show_minimap: true,
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+impl ViewSettings {
+    pub fn apply(&mut self, changes: &ConfigTable) {
+        for (key, value) in changes {
+            let known = match key.as_str() {
+                "font_face" => value.as_str().map(|face| self.font_face = Some(face.to_string())),
+                "font_size" => value.as_f64().map(|size| self.font_size = size as f32),
+                "wrap_width" => value.as_u64().map(|width| self.wrap_width = width as usize),
+                "tab_size" => value.as_u64().map(|size| self.tab_size = size.max(1) as usize),
+                "translate_tabs_to_spaces" => value.as_bool().map(|t| self.translate_tabs_to_spaces = t),
+                "line_ending" => value.as_str().map(|ending| self.line_ending = ending.to_string()),
+                // not xi-core's, but it passes on whatever is in the user's config
+                "relative_line_numbers" => value.as_bool().map(|r| self.relative_line_numbers = r),
+                "highlight_current_line" => value.as_bool().map(|h| self.highlight_current_line = h),
+                "show_minimap" => value.as_bool().map(|m| self.show_minimap = m),
+                _ => None,
+            };
+
+            if known.is_none() {
+                self.other.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    /// Every setting as `key: value` text, in key order, for showing to the user.
+    pub fn describe(&self) -> Vec<String> {
+        let mut entries = BTreeMap::new();
+        entries.insert("font_face", self.font_face.clone().unwrap_or_else(|| "(system)".to_string()));
+        entries.insert("font_size", self.font_size.to_string());
+        entries.insert("wrap_width", self.wrap_width.to_string());
+        entries.insert("tab_size", self.tab_size.to_string());
+        entries.insert("translate_tabs_to_spaces", self.translate_tabs_to_spaces.to_string());
+        entries.insert("line_ending", format!("{:?}", self.line_ending));
+        entries.insert("relative_line_numbers", self.relative_line_numbers.to_string());
+        entries.insert("highlight_current_line", self.highlight_current_line.to_string());
+        entries.insert("show_minimap", self.show_minimap.to_string());
+
+        let mut lines: Vec<String> = entries.iter()
+            .map(|(key, value)| format!("{}: {}", key, value))
+            .collect();
+        lines.extend(self.other.iter().map(|(key, value)| format!("{}: {}", key, value)));
+        lines
+    }
+
+    /// Expands tabs to the next tab stop, for drawing a line with the view's tab width.
+    pub fn expand_tabs(&self, line: &str) -> String {
+        if !line.contains('\t') {
+            return line.to_string();
+        }
+
+        // a column is a grapheme, an accent combined with a letter doesn't move the next tab stop,
+        // but a wide one like an emoji takes two
+        let mut expanded = String::with_capacity(line.len());
+        let mut column = 0;
+        for grapheme in line.graphemes(true) {
+            if grapheme == "\t" {
+                let spaces = self.tab_size - (column % self.tab_size);
+                expanded.extend(std::iter::repeat(' ').take(spaces));
+                column += spaces;
+            } else {
+                expanded.push_str(grapheme);
+                column += grapheme_columns(grapheme);
+            }
+        }
+        expanded
+    }
+
+    /// Where each grapheme of `line` starts, as its byte offset in `line` and in the line with
+    /// its tabs expanded, ending with the ends of both.
+    pub fn expanded_offsets(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut offsets = Vec::with_capacity(line.len() + 1);
+        let (mut expanded, mut column) = (0, 0);
+        for (offset, grapheme) in line.grapheme_indices(true) {
+            offsets.push((offset, expanded));
+            if grapheme == "\t" {
+                let spaces = self.tab_size - (column % self.tab_size);
+                expanded += spaces;
+                column += spaces;
+            } else {
+                expanded += grapheme.len();
+                column += grapheme_columns(grapheme);
+            }
+        }
+        offsets.push((line.len(), expanded));
+        offsets
+    }
+}
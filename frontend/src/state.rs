@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use xi_core_lib::{ ViewId, LanguageId };
+
+use crate::file_watcher::DiskState;
+use crate::folds::{ Fold, Folds };
+use crate::keymap::Keymap;
+use crate::layout::Viewport;
+use crate::line::Line;
+use crate::line_cache::LineCache;
+use crate::message::FrontendMessage;
+use crate::scheduler::Scheduler;
+use crate::settings::ViewSettings;
+use crate::status_bar::StatusBar;
+use crate::styles::StyleMap;
+
+/// Status bar key used for the language of a view.
+pub const LANGUAGE_STATUS_KEY: &str = "xi-path.language";
+/// Status bar key shown while a view has unsaved edits.
+pub const MODIFIED_STATUS_KEY: &str = "xi-path.modified";
+
+/// Everything the frontend knows about a single view, as told to it by xi-core.
+#[derive(Default)]
+pub struct ViewState {
+    pub language: Option<LanguageId>,
+    pub keymap: Keymap,
+    pub status_bar: StatusBar,
+    pub settings: ViewSettings,
+    pub lines: LineCache,
+    /// The first line on screen.
+    pub first_line: usize,
+    /// What of the view was on screen when we last told xi-core.
+    pub viewport: Option<Viewport>,
+    /// The rows of the line cache we last asked xi-core for, which with folds is more than fit on screen.
+    pub requested_rows: Option<(usize, usize)>,
+    /// The file the view is of, `None` until it's saved.
+    pub path: Option<PathBuf>,
+    pub folds: Folds,
+    /// Whether there are edits that haven't been saved, going by the `pristine` of xi-core's updates.
+    pub dirty: bool,
+    /// The view's file as it was when last opened or saved, to tell someone else's changes from ours.
+    pub on_disk: Option<DiskState>,
+    /// Set when the file watcher says the file's changed, until it's been dealt with.
+    pub changed_on_disk: bool,
+}
+
+impl ViewState {
+    /// A view that's only just been opened, with the keymap every language starts from.
+    pub fn new(config_dir: Option<&PathBuf>) -> Self {
+        let keymap = match config_dir {
+            Some(dir) => Keymap::load(dir, None),
+            None => Keymap::built_in(),
+        };
+        ViewState { keymap, ..ViewState::default() }
+    }
+
+    // a language change means a different set of keybindings, so reload them from the config dir.
+    // language specific settings (tab size etc) are handled by xi-core and come back through config_changed
+    fn set_language(&mut self, language: &LanguageId, config_dir: Option<&PathBuf>) {
+        self.language = Some(language.clone());
+        self.keymap = match config_dir {
+            Some(dir) => Keymap::load(dir, Some(language)),
+            None => Keymap::built_in(),
+        };
+        self.status_bar.set_item(LANGUAGE_STATUS_KEY, language.as_ref(), "right");
+    }
+
+    fn set_pristine(&mut self, pristine: bool) {
+        self.dirty = !pristine;
+        if self.dirty {
+            self.status_bar.set_item(MODIFIED_STATUS_KEY, "modified", "left");
+        } else {
+            self.status_bar.remove_item(MODIFIED_STATUS_KEY);
+        }
+    }
+
+    /// The name the view goes by, its file's or "untitled", marked when there are unsaved edits.
+    pub fn title(&self) -> String {
+        let name = self.path.as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "untitled".to_string());
+        if self.dirty { format!("\u{25cf} {}", name) } else { name }
+    }
+
+    /// Works out the fold regions again after the lines change, and unfolds any a cursor has moved into.
+    pub fn update_folds(&mut self) {
+        self.folds.update_regions(self.lines.annotations(), self.lines.buffer_lines(), self.settings.tab_size);
+        for line in self.lines.cursor_lines() {
+            self.folds.reveal(line);
+        }
+    }
+
+    /// The lines drawn from `first_line` down, as many as fit, with the rows in folded regions
+    /// skipped. Each comes with its row in the line cache, which is what xi-core counts in.
+    pub fn displayed_lines(&self) -> Vec<(usize, Line)> {
+        let visible = self.viewport.map(|v| v.visible_lines).unwrap_or(0);
+        let height = self.lines.height();
+        let mut displayed: Vec<(usize, Line)> = Vec::with_capacity(visible);
+        let mut row = self.first_line;
+
+        // a screenful at a time, until enough of them weren't hidden
+        while displayed.len() < visible && row < height {
+            let end = (row + visible - displayed.len()).min(height);
+            for (offset, mut line) in self.lines.lines(row, end).into_iter().enumerate() {
+                let buffer_line = line.buffer_line.map(|number| number - 1);
+                if buffer_line.map(|l| self.folds.is_hidden(l)).unwrap_or(false) {
+                    continue;
+                }
+                if line.line_number.is_some() {
+                    line.fold = buffer_line.and_then(|l| self.folds.marker(l));
+                }
+                displayed.push((row + offset, line));
+            }
+            row = end;
+        }
+
+        // the placeholder goes after the last row of a folded line, so after any it wraps onto
+        let mut in_folded_line = false;
+        for index in 0..displayed.len() {
+            let line = &displayed[index].1;
+            if line.line_number.is_some() {
+                in_folded_line = line.fold == Some(Fold::Folded);
+            }
+            let continues = displayed.get(index + 1)
+                .map(|(_, next)| next.line_number.is_none() && next.buffer_line == line.buffer_line)
+                .unwrap_or(false);
+            displayed[index].1.folded = in_folded_line && !continues;
+        }
+        displayed
+    }
+
+    /// The rows of the line cache that `displayed_lines` draws from, end exclusive, the way
+    /// xi-core's `scroll` wants them.
+    pub fn row_range(&self) -> (usize, usize) {
+        let visible = self.viewport.map(|v| v.visible_lines).unwrap_or(0);
+        match self.displayed_lines().last() {
+            // rows past what xi-core has sent yet still count as on screen
+            Some((row, _)) => (self.first_line, (row + 1).max(self.first_line + visible)),
+            None => (self.first_line, self.first_line + visible),
+        }
+    }
+
+    /// Scrolls so that row `row` of the line cache is in the middle of the screen, as near as
+    /// the ends of the buffer allow, like when the minimap is clicked.
+    pub fn center_on(&mut self, row: usize) {
+        let visible = self.viewport.map(|v| v.visible_lines).unwrap_or(1);
+        let last_first = self.lines.height().saturating_sub(visible.saturating_sub(1).max(1));
+        self.first_line = row.saturating_sub(visible / 2).min(last_first);
+    }
+
+    /// Scrolls as little as we can to have `line` fully on screen.
+    pub fn scroll_to(&mut self, line: usize) {
+        // the last visible line is only partly shown
+        let visible = self.viewport.map(|v| v.visible_lines).unwrap_or(1);
+        let fully_visible = visible.saturating_sub(1).max(1);
+
+        if line < self.first_line {
+            self.first_line = line;
+        } else if line >= self.first_line + fully_visible {
+            self.first_line = line + 1 - fully_visible;
+        }
+    }
+}
+
+/// Frontend state, owned by the main loop and changed only by applying `FrontendMessage`s.
+#[derive(Default)]
+pub struct EditorState {
+    pub config_dir: Option<PathBuf>,
+    pub available_languages: Vec<LanguageId>,
+    pub views: HashMap<ViewId, ViewState>,
+    /// Styles are defined once for every view.
+    pub styles: StyleMap,
+    pub scheduler: Scheduler,
+}
+
+impl EditorState {
+    pub fn view(&self, view_id: &ViewId) -> Option<&ViewState> {
+        self.views.get(view_id)
+    }
+
+    // callbacks can arrive for a view before we've seen anything else about it, so create it on demand
+    pub fn view_mut(&mut self, view_id: ViewId) -> &mut ViewState {
+        let config_dir = &self.config_dir;
+        self.views.entry(view_id).or_insert_with(|| ViewState::new(config_dir.as_ref()))
+    }
+
+    pub fn apply(&mut self, message: FrontendMessage) {
+        match message {
+            FrontendMessage::Update { view_id, update } => {
+                let view = self.view_mut(view_id);
+                view.set_pristine(update.pristine);
+                view.lines.apply_update(update);
+                view.update_folds();
+            }
+            FrontendMessage::ScrollTo { view_id, line, .. } => self.view_mut(view_id).scroll_to(line),
+            FrontendMessage::DefStyle { style_id, style } => self.styles.define(style_id, style),
+            FrontendMessage::ConfigChanged { view_id, changes } => {
+                let view = self.view_mut(view_id);
+                view.settings.apply(&changes);
+                // the tab size is part of working out indentation
+                view.update_folds();
+            }
+            FrontendMessage::AvailableLanguages(languages) => self.set_available_languages(languages),
+            FrontendMessage::LanguageChanged { view_id, language } => self.language_changed(view_id, &language),
+            FrontendMessage::AddStatusItem { view_id, key, value, alignment } => {
+                self.view_mut(view_id).status_bar.set_item(&key, &value, &alignment)
+            }
+            FrontendMessage::UpdateStatusItem { view_id, key, value } => {
+                self.view_mut(view_id).status_bar.update_item(&key, &value)
+            }
+            FrontendMessage::RemoveStatusItem { view_id, key } => {
+                self.view_mut(view_id).status_bar.remove_item(&key)
+            }
+            FrontendMessage::ScheduleIdle(token) => self.scheduler.schedule_idle(token),
+            FrontendMessage::ScheduleTimer { deadline, token } => self.scheduler.schedule_timer(deadline, token),
+            FrontendMessage::FileChanged(path) => {
+                for view in self.views.values_mut().filter(|view| view.path.as_ref() == Some(&path)) {
+                    view.changed_on_disk = true;
+                }
+            }
+        }
+    }
+
+    pub fn set_available_languages(&mut self, mut languages: Vec<LanguageId>) {
+        languages.sort();
+        self.available_languages = languages;
+    }
+
+    pub fn language_changed(&mut self, view_id: ViewId, language: &LanguageId) {
+        let config_dir = self.config_dir.clone();
+        self.view_mut(view_id).set_language(language, config_dir.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ env, fs };
+
+    use crate::status_bar::StatusAlignment;
+
+    use super::*;
+
+    fn config_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("xi-path-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("keymaps")).unwrap();
+        fs::write(dir.join("keymaps/default.json"), r#"{ "ctrl+k": "delete_to_end_of_paragraph" }"#).unwrap();
+        fs::write(dir.join("keymaps/rust.json"), r#"{ "ctrl+k": "toggle_comment" }"#).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_new_view_has_the_default_keymap() {
+        let mut state = EditorState::default();
+        let view = state.view_mut(ViewId::from(1));
+        assert_eq!(view.keymap.command_for("return"), Some("insert_newline"));
+
+        let dir = config_dir("new-view");
+        state.config_dir = Some(dir.clone());
+        let view = state.view_mut(ViewId::from(2));
+        assert_eq!(view.keymap.command_for("ctrl+k"), Some("delete_to_end_of_paragraph"));
+        assert_eq!(view.keymap.command_for("return"), Some("insert_newline"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn languages_are_kept_sorted_and_shown_for_each_view() {
+        let dir = config_dir("languages");
+        let mut state = EditorState { config_dir: Some(dir.clone()), ..EditorState::default() };
+        state.apply(FrontendMessage::AvailableLanguages(vec!["Rust".into(), "Markdown".into(), "JSON".into()]));
+        assert_eq!(state.available_languages, vec!["JSON".into(), "Markdown".into(), LanguageId::from("Rust")]);
+
+        let view_id = ViewId::from(1);
+        state.apply(FrontendMessage::LanguageChanged { view_id, language: "rust".into() });
+        state.apply(FrontendMessage::LanguageChanged { view_id: ViewId::from(2), language: "Markdown".into() });
+        let view = state.view(&view_id).unwrap();
+        assert_eq!(view.language, Some("rust".into()));
+        // the language's own keymap goes over the default one
+        assert_eq!(view.keymap.command_for("ctrl+k"), Some("toggle_comment"));
+        let shown: Vec<_> = view.status_bar.items(StatusAlignment::Right)
+            .map(|item| (item.key.as_str(), item.value.as_str()))
+            .collect();
+        assert_eq!(shown, vec![(LANGUAGE_STATUS_KEY, "rust")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
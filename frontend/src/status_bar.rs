@@ -0,0 +1,61 @@
+/// A single piece of text shown in a view's status bar, either by xi-core/plugins or by us.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusItem {
+    pub key: String,
+    pub value: String,
+    pub alignment: StatusAlignment,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatusAlignment {
+    Left,
+    Right,
+}
+
+impl StatusAlignment {
+    // xi sends alignment as a string, anything it doesn't know about goes on the left
+    pub fn from_str(alignment: &str) -> Self {
+        match alignment {
+            "right" => StatusAlignment::Right,
+            _ => StatusAlignment::Left,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StatusBar {
+    items: Vec<StatusItem>,
+}
+
+impl StatusBar {
+    /// Adds the item, or replaces the value and alignment of an existing item with the same key.
+    pub fn set_item(&mut self, key: &str, value: &str, alignment: &str) {
+        let alignment = StatusAlignment::from_str(alignment);
+        match self.items.iter_mut().find(|item| item.key == key) {
+            Some(item) => {
+                item.value = value.to_string();
+                item.alignment = alignment;
+            }
+            None => self.items.push(StatusItem {
+                key: key.to_string(),
+                value: value.to_string(),
+                alignment,
+            }),
+        }
+    }
+
+    pub fn update_item(&mut self, key: &str, value: &str) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.key == key) {
+            item.value = value.to_string();
+        }
+    }
+
+    pub fn remove_item(&mut self, key: &str) {
+        self.items.retain(|item| item.key != key);
+    }
+
+    /// Items in the order they should be drawn for the given side.
+    pub fn items(&self, alignment: StatusAlignment) -> impl Iterator<Item = &StatusItem> {
+        self.items.iter().filter(move |item| item.alignment == alignment)
+    }
+}
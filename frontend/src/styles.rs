@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use xi_path_renderer::display_list::Color;
+
+use crate::diagnostics::Severity;
+
+/// Style ids xi-core reserves, and never sends a `def_style` for.
+pub const SELECTION_STYLE: usize = 0;
+pub const FIND_HIGHLIGHT_STYLE: usize = 1;
+
+/// Style ids of our own, for diagnostics. They count down from the top, so they never meet
+/// xi-core's, which count up.
+pub const ERROR_STYLE: usize = usize::max_value();
+pub const WARNING_STYLE: usize = usize::max_value() - 1;
+pub const INFO_STYLE: usize = usize::max_value() - 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StyleDef {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    /// A wavy underline in this colour, which is how diagnostics are drawn.
+    pub squiggle: Option<Color>,
+}
+
+impl StyleDef {
+    /// Reads a style from xi-core's `def_style`, along with the id spans refer to it by.
+    /// Colours are packed ARGB, and anything at or above a weight of 700 is bold.
+    pub fn from_json(style: &Value) -> Option<(usize, StyleDef)> {
+        let id = style.get("id")?.as_u64()? as usize;
+        let color = |key: &str| style.get(key).and_then(Value::as_u64).map(|argb| Color::from_argb(argb as u32));
+        let flag = |key: &str| style.get(key).and_then(Value::as_bool).unwrap_or(false);
+
+        let def = StyleDef {
+            fg: color("fg_color"),
+            bg: color("bg_color"),
+            bold: style.get("weight").and_then(Value::as_u64).map(|weight| weight >= 700).unwrap_or(false),
+            italic: flag("italic"),
+            underline: flag("underline"),
+            squiggle: None,
+        };
+        Some((id, def))
+    }
+
+    /// This style drawn on top of `under`, anything it doesn't set shows through.
+    pub fn over(&self, under: &StyleDef) -> StyleDef {
+        StyleDef {
+            fg: self.fg.or(under.fg),
+            bg: self.bg.or(under.bg),
+            bold: self.bold || under.bold,
+            italic: self.italic || under.italic,
+            underline: self.underline || under.underline,
+            squiggle: self.squiggle.or(under.squiggle),
+        }
+    }
+}
+
+/// The styles a view's spans refer to, by id.
+#[derive(Clone, Debug)]
+pub struct StyleMap {
+    styles: HashMap<usize, StyleDef>,
+    /// Changes whenever a style is defined, so anything drawn with the old styles can be thrown away.
+    pub generation: u64,
+}
+
+impl Default for StyleMap {
+    fn default() -> Self {
+        let mut styles = HashMap::new();
+        styles.insert(SELECTION_STYLE, StyleDef { bg: Some(Color::rgb(200, 220, 255)), ..StyleDef::default() });
+        styles.insert(FIND_HIGHLIGHT_STYLE, StyleDef { bg: Some(Color::rgb(255, 240, 160)), ..StyleDef::default() });
+        for &severity in &[Severity::Error, Severity::Warning, Severity::Info] {
+            styles.insert(severity.style_id(), StyleDef { squiggle: Some(severity.color()), ..StyleDef::default() });
+        }
+        StyleMap { styles, generation: 0 }
+    }
+}
+
+impl StyleMap {
+    pub fn define(&mut self, style_id: usize, style: StyleDef) {
+        self.styles.insert(style_id, style);
+        self.generation += 1;
+    }
+
+    pub fn get(&self, style_id: usize) -> Option<&StyleDef> {
+        self.styles.get(&style_id)
+    }
+}
@@ -0,0 +1,17 @@
+//! A stand-in for fonts in unit tests, so layout can be checked without loading one.
+
+use xi_path_renderer::display_list::FontSpec;
+use xi_path_renderer::fonts::{ LineMetrics, MeasureText };
+
+/// Every char 10 wide and lines 20 tall, so positions can be worked out by hand.
+pub struct FixedWidth;
+
+impl MeasureText for FixedWidth {
+    fn text_width(&mut self, text: &str, _: &FontSpec) -> f32 {
+        text.chars().count() as f32 * 10.0
+    }
+
+    fn line_metrics(&mut self, _: &FontSpec) -> LineMetrics {
+        LineMetrics { ascent: 16.0, descent: 4.0 }
+    }
+}
@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::{ self, BufRead, BufWriter, Write };
+use std::path::Path;
+use std::sync::{ Arc, Mutex };
+use std::time::Instant;
+
+use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+
+use xi_core_lib:: {
+    ViewId, ConfigTable,LanguageId,
+    styles:: { Style, ThemeSettings },
+    client:: { Frontend, Update },
+    find::FindStatus,
+    view::Replace,
+    plugin_manifest::Command,
+    plugin_rpc::ClientPluginInfo,
+    width_cache:: { WidthReq, WidthResponse }
+};
+
+use crate::frontend::{ HandleNotification, XiPathFrontend };
+use crate::state::EditorState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    FromCore,
+    ToCore,
+}
+
+/// One line of a trace. Messages are recorded with their xi json-rpc method and params, the
+/// same as they'd go over the wire to an out-of-process core, so a trace replays through
+/// `HandleNotification`. Requests also record what they got back.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub elapsed_ms: u64,
+    pub direction: Direction,
+    pub method: String,
+    pub params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+}
+
+/// A json-lines file everything going between the frontend and xi-core is written to.
+/// Cheap to clone, so the frontend and the transport can both write to the same trace.
+#[derive(Clone)]
+pub struct Trace {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    started: Instant,
+}
+
+impl Trace {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Trace::to_writer(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn to_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        Trace {
+            out: Arc::new(Mutex::new(Box::new(writer))),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn record(&self, direction: Direction, method: &str, params: Value, response: Option<Value>) {
+        let entry = TraceEntry {
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            direction,
+            method: method.to_string(),
+            params,
+            response,
+        };
+
+        // flushed every line, the trace is most useful when we've just crashed
+        let mut out = self.out.lock().unwrap();
+        let result = serde_json::to_writer(&mut *out, &entry)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(out))
+            .and_then(|_| out.flush());
+        if let Err(e) = result {
+            eprintln!("couldn't write trace: {}", e);
+        }
+    }
+}
+
+/// Wraps the frontend, recording every callback from xi-core to a trace before passing it on.
+pub struct RecordingFrontend {
+    inner: XiPathFrontend,
+    trace: Trace,
+}
+
+impl RecordingFrontend {
+    pub fn new(inner: XiPathFrontend, trace: Trace) -> Self {
+        RecordingFrontend { inner, trace }
+    }
+
+    fn record(&self, method: &str, params: Value) {
+        self.trace.record(Direction::FromCore, method, params, None);
+    }
+}
+
+impl HandleNotification for RecordingFrontend {
+    fn handle_notification(&self, method: &str, params: &Value) {
+        self.record(method, params.clone());
+        self.inner.handle_notification(method, params);
+    }
+}
+
+impl Frontend for RecordingFrontend {
+
+    fn update_view(&self, view_id: ViewId, update: &Update) {
+        self.record("update", json!({ "view_id": view_id, "update": update }));
+        self.inner.update_view(view_id, update)
+    }
+    fn scroll_to(&self, view_id: ViewId, line: usize, col: usize) {
+        self.record("scroll_to", json!({ "view_id": view_id, "line": line, "col": col }));
+        self.inner.scroll_to(view_id, line, col)
+    }
+    fn config_changed(&self, view_id: ViewId, changes: &ConfigTable) {
+        self.record("config_changed", json!({ "view_id": view_id, "changes": changes }));
+        self.inner.config_changed(view_id, changes)
+    }
+    fn available_themes(&self, theme_names: Vec<String>) {
+        self.record("available_themes", json!({ "themes": theme_names }));
+        self.inner.available_themes(theme_names)
+    }
+    fn available_languages(&self, languages: Vec<LanguageId>) {
+        self.record("available_languages", json!({ "languages": languages }));
+        self.inner.available_languages(languages)
+    }
+    fn theme_changed(&self, name: &str, theme: &ThemeSettings) {
+        self.record("theme_changed", json!({ "name": name, "theme": theme }));
+        self.inner.theme_changed(name, theme)
+    }
+    fn language_changed(&self, view_id: ViewId, new_lang: &LanguageId) {
+        self.record("language_changed", json!({ "view_id": view_id, "language_id": new_lang }));
+        self.inner.language_changed(view_id, new_lang)
+    }
+    fn plugin_started(&self, view_id: ViewId, plugin: &str) {
+        self.record("plugin_started", json!({ "view_id": view_id, "plugin": plugin }));
+        self.inner.plugin_started(view_id, plugin)
+    }
+    fn plugin_stopped(&self, view_id: ViewId, plugin: &str, code: i32) {
+        self.record("plugin_stopped", json!({ "view_id": view_id, "plugin": plugin, "code": code }));
+        self.inner.plugin_stopped(view_id, plugin, code)
+    }
+    fn available_plugins(&self, view_id: ViewId, plugins: &[ClientPluginInfo]) {
+        self.record("available_plugins", json!({ "view_id": view_id, "plugins": plugins }));
+        self.inner.available_plugins(view_id, plugins)
+    }
+    fn update_cmds(&self, view_id: ViewId, plugin: &str, cmds: &[Command]) {
+        self.record("update_cmds", json!({ "view_id": view_id, "plugin": plugin, "cmds": cmds }));
+        self.inner.update_cmds(view_id, plugin, cmds)
+    }
+    fn def_style(&self, style: &Style)  {
+        self.record("def_style", json!(style));
+        self.inner.def_style(style)
+    }
+    fn find_status(&self, view_id: ViewId, queries: &Vec<FindStatus>) {
+        self.record("find_status", json!({ "view_id": view_id, "queries": queries }));
+        self.inner.find_status(view_id, queries)
+    }
+    fn replace_status(&self, view_id: ViewId, replace: &Replace) {
+        self.record("replace_status", json!({ "view_id": view_id, "status": replace }));
+        self.inner.replace_status(view_id, replace)
+    }
+
+    fn measure_width(&self, reqs: &[WidthReq]) -> WidthResponse {
+        let response = self.inner.measure_width(reqs);
+        self.trace.record(Direction::FromCore, "measure_width", json!(reqs), Some(json!(response)));
+        response
+    }
+
+    fn add_status_item(
+        &self,
+        view_id: ViewId,
+        source: &str,
+        key: &str,
+        value: &str,
+        alignment: &str,
+    ) {
+        self.record("add_status_item", json!({
+            "view_id": view_id,
+            "source": source,
+            "key": key,
+            "value": value,
+            "alignment": alignment,
+        }));
+        self.inner.add_status_item(view_id, source, key, value, alignment)
+    }
+    fn update_status_item(&self, view_id: ViewId, key: &str, value: &str) {
+        self.record("update_status_item", json!({ "view_id": view_id, "key": key, "value": value }));
+        self.inner.update_status_item(view_id, key, value)
+    }
+    fn remove_status_item(&self, view_id: ViewId, key: &str) {
+        self.record("remove_status_item", json!({ "view_id": view_id, "key": key }));
+        self.inner.remove_status_item(view_id, key)
+    }
+    fn show_hover(&self, view_id: ViewId, request_id: usize, result: String) {
+        self.record("show_hover", json!({ "view_id": view_id, "request_id": request_id, "result": result }));
+        self.inner.show_hover(view_id, request_id, result)
+    }
+    // not part of the wire protocol, an out-of-process core schedules its own work. recorded so
+    // the trace shows when idle work happened, replay skips them
+    fn schedule_idle(&self, token: usize) {
+        self.record("schedule_idle", json!({ "token": token }));
+        self.inner.schedule_idle(token)
+    }
+    fn schedule_timer(&self, timeout: std::time::Instant, token: usize) {
+        let delay_ms = timeout.saturating_duration_since(Instant::now()).as_millis() as u64;
+        self.record("schedule_timer", json!({ "token": token, "delay_ms": delay_ms }));
+        self.inner.schedule_timer(timeout, token)
+    }
+}
+
+/// Feeds every message xi-core sent in a trace to `frontend`, in order. Messages we sent to the
+/// core are skipped, there's no core listening. Returns how many messages were replayed.
+pub fn replay<R: BufRead>(trace: R, frontend: &dyn HandleNotification) -> io::Result<usize> {
+    let mut replayed = 0;
+    for line in trace.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: TraceEntry = serde_json::from_str(&line)?;
+        if entry.direction == Direction::FromCore && entry.response.is_none() {
+            frontend.handle_notification(&entry.method, &entry.params);
+            replayed += 1;
+        }
+    }
+    Ok(replayed)
+}
+
+/// Replays a trace into a frontend with no window or core attached, and gives back the
+/// state the main loop would have ended up with.
+pub fn replay_headless<R: BufRead>(trace: R) -> io::Result<EditorState> {
+    let (frontend, messages) = XiPathFrontend::new();
+    replay(trace, &frontend)?;
+    drop(frontend);
+
+    let mut state = EditorState::default();
+    for message in messages {
+        state.apply(message);
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::status_bar::StatusAlignment;
+
+    #[derive(Clone, Default)]
+    struct Written(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Written {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn recorded_callbacks_replay_into_the_same_state() {
+        let written = Written::default();
+        let trace = Trace::to_writer(written.clone());
+        let (inner, _messages) = XiPathFrontend::new();
+        let recording = RecordingFrontend::new(inner, trace.clone());
+
+        let view_id: ViewId = serde_json::from_value(json!("view-id-1")).unwrap();
+        recording.available_languages(vec![LanguageId::from("Rust"), LanguageId::from("Markdown")]);
+        recording.language_changed(view_id, &LanguageId::from("Rust"));
+        recording.add_status_item(view_id, "lsp", "errors", "2 errors", "left");
+        trace.record(Direction::ToCore, "edit", json!({ "view_id": "view-id-1", "method": "undo" }), None);
+
+        let bytes = written.0.lock().unwrap().clone();
+        assert_eq!(bytes.iter().filter(|b| **b == b'\n').count(), 4);
+
+        let state = replay_headless(Cursor::new(bytes)).unwrap();
+        assert_eq!(state.available_languages, vec![LanguageId::from("Markdown"), LanguageId::from("Rust")]);
+
+        let view = state.view(&view_id).unwrap();
+        assert_eq!(view.language, Some(LanguageId::from("Rust")));
+        let left: Vec<_> = view.status_bar.items(StatusAlignment::Left).map(|item| item.value.clone()).collect();
+        assert_eq!(left, vec!["2 errors".to_string()]);
+    }
+}
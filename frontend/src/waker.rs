@@ -0,0 +1,47 @@
+use std::ptr;
+
+use sdl2::event::{ Event, EventSender };
+use sdl2::EventSubsystem;
+
+/// Wakes the SDL main loop out of `wait_event` when xi-core has given it something to do.
+///
+/// This works from any thread: it pushes an SDL user event of a type registered just for us.
+pub struct Waker {
+    sender: EventSender,
+    event_type: u32,
+}
+
+impl Waker {
+    pub fn new(events: &EventSubsystem) -> Self {
+        let event_type = match unsafe { events.register_event() } {
+            Ok(t) => t,
+            Err(e) => panic!("couldn't register the wake event: {}", e),
+        };
+
+        Waker {
+            sender: events.event_sender(),
+            event_type,
+        }
+    }
+
+    pub fn wake(&self) {
+        let event = Event::User {
+            timestamp: 0,
+            window_id: 0,
+            type_: self.event_type,
+            code: 0,
+            data1: ptr::null_mut(),
+            data2: ptr::null_mut(),
+        };
+
+        // the queue being full just means the loop is already awake
+        let _ = self.sender.push_event(event);
+    }
+
+    pub fn is_wake_event(&self, event: &Event) -> bool {
+        match event {
+            Event::User { type_, .. } => *type_ == self.event_type,
+            _ => false,
+        }
+    }
+}
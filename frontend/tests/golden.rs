@@ -0,0 +1,292 @@
+//! Renders views with the software renderer and compares them with the images in `tests/golden`.
+//!
+//! Goldens are only ever written with `XI_PATH_BLESS=1` set, run that way to add the golden for
+//! a new test or to rewrite every golden after an intended change to layout, and check them in.
+//! A missing golden fails the test like a mismatch does.
+//! On a mismatch, the actual image and a diff (differing pixels in red) are written next to the golden.
+
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{ Path, PathBuf };
+
+use frontend::diagnostics::Severity;
+use frontend::layout::{ self, LayoutConfig, ViewLayout };
+use frontend::line::{ Line, StyleSpan };
+use frontend::settings::ViewSettings;
+use frontend::status_bar::StatusBar;
+use frontend::styles::{ StyleDef, StyleMap, SELECTION_STYLE };
+use xi_path_renderer::display_list::{ Color, FontFace, RenderTarget };
+use xi_path_renderer::software_renderer::{ Image, SoftwareRenderer };
+use xi_path_renderer::Renders;
+
+const WIDTH: f32 = 320.0;
+const HEIGHT: f32 = 160.0;
+
+// how far apart a channel can be before a pixel counts as different, antialiasing varies a little
+const CHANNEL_TOLERANCE: u8 = 24;
+// how much of the image can differ before the test fails
+const MAX_DIFFERING: f32 = 0.002;
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn config() -> LayoutConfig {
+    let font = FontFace::Path(tests_dir().join("fonts/DejaVuSansMono.ttf"));
+    LayoutConfig {
+        text_face: font.clone(),
+        gutter_face: font.clone(),
+        status_face: font,
+    }
+}
+
+// lays out at the same logical size whatever the scale, like a window moved to a HiDPI monitor
+fn render(view: &ViewLayout, scale_factor: f32) -> Image {
+    let mut renderer = SoftwareRenderer::new();
+    let frame = layout::build_frame(WIDTH, HEIGHT, view, &config(), renderer.fonts());
+    let target = RenderTarget::new((WIDTH * scale_factor) as u32, (HEIGHT * scale_factor) as u32, scale_factor);
+    renderer.render(&frame, &target);
+    renderer.image().clone()
+}
+
+fn read_png(path: &Path) -> Image {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let (info, mut reader) = decoder.read_info().unwrap();
+    assert_eq!(info.color_type, png::ColorType::RGBA, "{:?} isn't rgba", path);
+
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    Image { width: info.width, height: info.height, pixels }
+}
+
+fn write_png(path: &Path, image: &Image) {
+    let file = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, image.width, image.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(&image.pixels).unwrap();
+}
+
+/// Every pixel of `actual` that's too far from `expected` in red, the rest faded out.
+/// Returns the diff and how many pixels differ.
+fn diff(expected: &Image, actual: &Image) -> (Image, usize) {
+    let mut diff = Image::new(actual.width, actual.height, Color::white());
+    let mut differing = 0;
+    for y in 0..actual.height {
+        for x in 0..actual.width {
+            let (e, a) = (expected.pixel(x, y), actual.pixel(x, y));
+            let close = |e: u8, a: u8| (e as i16 - a as i16).abs() <= CHANNEL_TOLERANCE as i16;
+            if close(e.r, a.r) && close(e.g, a.g) && close(e.b, a.b) && close(e.a, a.a) {
+                diff.blend(x as i32, y as i32, a, 40);
+            } else {
+                diff.blend(x as i32, y as i32, Color::rgb(255, 0, 0), 255);
+                differing += 1;
+            }
+        }
+    }
+    (diff, differing)
+}
+
+fn assert_golden(name: &str, view: &ViewLayout) {
+    assert_golden_scaled(name, view, 1.0)
+}
+
+fn assert_golden_scaled(name: &str, view: &ViewLayout, scale_factor: f32) {
+    let actual = render(view, scale_factor);
+    let golden_dir = tests_dir().join("golden");
+    let golden = golden_dir.join(format!("{}.png", name));
+    let actual_path = golden_dir.join(format!("{}.actual.png", name));
+    let diff_path = golden_dir.join(format!("{}.diff.png", name));
+
+    if env::var_os("XI_PATH_BLESS").is_some() {
+        std::fs::create_dir_all(&golden_dir).unwrap();
+        write_png(&golden, &actual);
+        eprintln!("wrote golden {:?}", golden);
+        return;
+    }
+    if !golden.exists() {
+        std::fs::create_dir_all(&golden_dir).unwrap();
+        write_png(&actual_path, &actual);
+        panic!("{} has no golden at {:?}, run with XI_PATH_BLESS=1 to write it from {:?} and check it in",
+               name, golden, actual_path);
+    }
+
+    let expected = read_png(&golden);
+    assert_eq!((expected.width, expected.height), (actual.width, actual.height),
+               "{} rendered at a different size to its golden", name);
+
+    let (diff_image, differing) = diff(&expected, &actual);
+    let fraction = differing as f32 / (actual.width * actual.height) as f32;
+    if fraction > MAX_DIFFERING {
+        write_png(&actual_path, &actual);
+        write_png(&diff_path, &diff_image);
+        panic!("{} differs from its golden in {} pixels, see {:?}", name, differing, diff_path);
+    }
+
+    // clear out what a previous failing run left behind
+    let _ = std::fs::remove_file(actual_path);
+    let _ = std::fs::remove_file(diff_path);
+}
+
+fn span(start: usize, end: usize, style_id: usize) -> StyleSpan {
+    StyleSpan { start, end, style_id }
+}
+
+// numbers lines from `first`, the way xi-core sends them
+fn numbered(mut lines: Vec<Line>, first: usize) -> Vec<Line> {
+    for (index, line) in lines.iter_mut().enumerate() {
+        line.line_number = Some(first + index);
+        line.buffer_line = line.line_number;
+    }
+    lines
+}
+
+#[test]
+fn gutter() {
+    let mut settings = ViewSettings::default();
+    settings.relative_line_numbers = true;
+    let styles = StyleMap::default();
+
+    // line 99 wraps onto a second row, which isn't numbered, and the cursor is on line 100
+    let mut lines = numbered(vec![Line::new("ninety eight"), Line::new("ninety nine wraps"), Line::new("one hundred")], 98);
+    let mut continued = Line::new("onto a second row");
+    continued.buffer_line = Some(99);
+    lines.insert(2, continued);
+    lines[3].cursors = vec![3];
+
+    assert_golden("gutter", &ViewLayout {
+        lines: &lines,
+        styles: &styles,
+        settings: &settings,
+        status_bar: None,
+        first_line: 0,
+        last_line_number: 100,
+        cursor_line: Some(100),
+    });
+}
+
+#[test]
+fn styled_text() {
+    let settings = ViewSettings::default();
+    let mut styles = StyleMap::default();
+    styles.define(2, StyleDef { fg: Some(Color::rgb(160, 40, 160)), bold: true, ..StyleDef::default() });
+    styles.define(3, StyleDef { fg: Some(Color::rgb(40, 120, 40)), ..StyleDef::default() });
+    styles.define(4, StyleDef { fg: Some(Color::rgb(200, 40, 40)), underline: true, ..StyleDef::default() });
+
+    let mut first = Line::new("fn main() {");
+    first.styles = vec![span(0, 2, 2), span(3, 7, 3)];
+    let mut second = Line::new("\tlet x = \"text\";");
+    second.styles = vec![span(1, 4, 2), span(9, 15, 4)];
+    let lines = numbered(vec![first, second, Line::new("}")], 1);
+
+    assert_golden("styled_text", &ViewLayout {
+        lines: &lines,
+        styles: &styles,
+        settings: &settings,
+        status_bar: None,
+        first_line: 0,
+        last_line_number: lines.len(),
+        cursor_line: None,
+    });
+}
+
+#[test]
+fn selections() {
+    let settings = ViewSettings::default();
+    let mut styles = StyleMap::default();
+    styles.define(2, StyleDef { fg: Some(Color::rgb(160, 40, 160)), ..StyleDef::default() });
+
+    // a selection over a styled span, one running to the end of a line, and bare cursors
+    let mut first = Line::new("let selected = 1;");
+    first.styles = vec![span(0, 3, 2), span(2, 12, SELECTION_STYLE)];
+    first.cursors = vec![12];
+    let mut second = Line::new("select to the end");
+    second.styles = vec![span(7, 17, SELECTION_STYLE)];
+    second.cursors = vec![17];
+    let mut third = Line::new("two cursors");
+    third.cursors = vec![0, 4];
+    let lines = numbered(vec![first, second, third], 1);
+
+    assert_golden("selections", &ViewLayout {
+        lines: &lines,
+        styles: &styles,
+        settings: &settings,
+        status_bar: None,
+        first_line: 0,
+        last_line_number: lines.len(),
+        cursor_line: Some(1),
+    });
+}
+
+#[test]
+fn diagnostics() {
+    let settings = ViewSettings::default();
+    let styles = StyleMap::default();
+
+    // an error under a call, a warning under a name that's also selected, and a line with only an info
+    let mut first = Line::new("let total = sum(items);");
+    first.styles = vec![span(12, 22, Severity::Error.style_id())];
+    first.diagnostic = Some(Severity::Error);
+    let mut second = Line::new("let unused = 1;");
+    second.styles = vec![span(4, 10, SELECTION_STYLE), span(4, 10, Severity::Warning.style_id())];
+    second.diagnostic = Some(Severity::Warning);
+    let mut third = Line::new("// todo");
+    third.diagnostic = Some(Severity::Info);
+    let lines = numbered(vec![first, second, third], 1);
+
+    assert_golden("diagnostics", &ViewLayout {
+        lines: &lines,
+        styles: &styles,
+        settings: &settings,
+        status_bar: None,
+        first_line: 0,
+        last_line_number: lines.len(),
+        cursor_line: None,
+    });
+}
+
+#[test]
+fn status_bar() {
+    let settings = ViewSettings::default();
+    let styles = StyleMap::default();
+    let mut status_bar = StatusBar::default();
+    status_bar.set_item("errors", "2 errors", "left");
+    status_bar.set_item("branch", "master", "left");
+    status_bar.set_item("language", "Rust", "right");
+    let lines = numbered(vec![Line::new("with a status bar")], 1);
+
+    assert_golden("status_bar", &ViewLayout {
+        lines: &lines,
+        styles: &styles,
+        settings: &settings,
+        status_bar: Some(&status_bar),
+        first_line: 0,
+        last_line_number: lines.len(),
+        cursor_line: None,
+    });
+}
+
+#[test]
+fn hidpi() {
+    let settings = ViewSettings::default();
+    let styles = StyleMap::default();
+    let mut status_bar = StatusBar::default();
+    status_bar.set_item("language", "Rust", "right");
+    let mut line = Line::new("sharp at 2x");
+    line.styles = vec![span(0, 5, SELECTION_STYLE)];
+    line.cursors = vec![5];
+    let lines = numbered(vec![line], 1);
+    let view = ViewLayout {
+        lines: &lines,
+        styles: &styles,
+        settings: &settings,
+        status_bar: Some(&status_bar),
+        first_line: 0,
+        last_line_number: lines.len(),
+        cursor_line: Some(1),
+    };
+
+    assert_golden_scaled("hidpi_2x", &view, 2.0);
+    assert_golden_scaled("hidpi_1_5x", &view, 1.5);
+}
@@ -1,27 +1,30 @@
-use std::path::Path;
-use foreign_types::ForeignTypeRef;
-use metal::{CAMetalLayer, CoreAnimationLayerRef};
-use pathfinder_canvas::{Canvas, CanvasFontContext, CanvasRenderingContext2D, Path2D, TextAlign, Vector2I, FillStyle, ColorU, Vector2F};
-use pathfinder_color::ColorF;
-use pathfinder_geometry::vector::{vec2f, vec2i};
-use pathfinder_geometry::rect::RectF;
-use pathfinder_metal::MetalDevice;
-use pathfinder_renderer::concurrent::rayon::RayonExecutor;
-use pathfinder_renderer::concurrent::scene_proxy::SceneProxy;
-use pathfinder_renderer::gpu::options::{DestFramebuffer, RendererMode, RendererOptions};
-use pathfinder_renderer::gpu::renderer::Renderer;
-use pathfinder_renderer::options::BuildOptions;
-use pathfinder_resources::embedded::EmbeddedResourceLoader;
-use font_kit::font::Font;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use pathfinder_geometry::vector::vec2i;
 use sdl2::event::Event;
-use sdl2::hint;
-use sdl2::keyboard::Keycode;
-use sdl2_sys::SDL_RenderGetMetalLayer;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
 
+mod overlay;
+mod render_loop;
 mod session;
+mod transport;
+use render_loop::RenderLoop;
 use session::Session;
-use xi_core_lib::ViewId;
-use frontend::frontend::XiPathFrontend;
+use transport::{DirectTransport, RecordingTransport, StdioTransport};
+use xi_path_backend::Transport;
+use frontend::frontend::{HandleNotification, XiPathFrontend};
+use frontend::trace::{self, RecordingFrontend, Trace};
+use xi_core_lib::client::Frontend;
+use frontend::waker::Waker;
+use frontend::file_watcher::FileWatcher;
+use frontend::measure::WidthMeasurer;
+use frontend::status_bar::StatusAlignment;
+use xi_path_renderer::pathfinder_renderer::PathfinderRenderer;
 
 // - main
 //   - arranges keyboard/input receiving
@@ -34,163 +37,273 @@ use frontend::frontend::XiPathFrontend;
 //   - needs reference to frontend's messaging interface (done through client modifications)
 
 fn main() {
+    // --replay <trace> runs a recorded trace through a headless frontend, no window or xi-core
+    // --record <trace> runs the editor as normal, writing everything to and from xi-core to the trace
+    // --frame-stats prints how long frames take to stderr, every couple of seconds of drawing
+    // --config-dir <dir> reads settings and keymaps from, and keeps the session in, another directory
+    let mut record_path = None;
+    let mut frame_stats = false;
+    let mut config_dir_arg = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record_path = args.next().map(PathBuf::from),
+            "--frame-stats" => frame_stats = true,
+            "--config-dir" => config_dir_arg = args.next().map(PathBuf::from),
+            "--replay" => {
+                let path = args.next().map(PathBuf::from).expect("--replay needs a trace file");
+                replay_trace(&path);
+                return;
+            }
+            _ => eprintln!("ignoring unknown argument {}", arg),
+        }
+    }
+
     // Set up SDL2.
-    assert!(hint::set("SDL_RENDER_DRIVER", "metal"));
     let sdl_context = sdl2::init().unwrap();
     let video = sdl_context.video().unwrap();
 
-    // Open a window.
-    let window_size = vec2i(640, 480);
-    let window = video.window("Minimal example", window_size.x() as u32, window_size.y() as u32)
-                      .opengl()
-                      .build()
-                      .unwrap();
-
-    // Create a Metal context.
-    let canvas = window.into_canvas().present_vsync().build().unwrap();
-    let metal_layer = unsafe {
-        CoreAnimationLayerRef::from_ptr(SDL_RenderGetMetalLayer(canvas.raw()) as *mut CAMetalLayer)
-    };
-    let metal_device = metal_layer.device();
-    let drawable = metal_layer.next_drawable().unwrap();
+    // Open a window, and everything we need to draw to it.
+    let renderer = PathfinderRenderer::new(&video, "Minimal example", vec2i(640, 480));
 
-    // Create a Pathfinder renderer.
-    let device = unsafe {
-        MetalDevice::new(metal_device, drawable.clone())
-    };
-    let mode = RendererMode::default_for_device(&device);
-    let options = RendererOptions {
-        dest: DestFramebuffer::full_window(window_size),
-        background_color: Some(ColorF::white()),
-        ..RendererOptions::default()
+    let (mut front_end, frontend_messages) = XiPathFrontend::new();
+    let events = sdl_context.event().unwrap();
+    let waker = Arc::new(Waker::new(&events));
+    front_end.set_waker(waker.clone());
+    // changes to open files made outside the editor come in with xi-core's messages
+    let file_watcher = match FileWatcher::new(front_end.sender(), Some(waker.clone())) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            eprintln!("couldn't watch files for changes: {}", e);
+            None
+        }
     };
-    let mut renderer = Renderer::new(device, &EmbeddedResourceLoader, mode, options);
-
-    // Make a canvas. We're going to draw a text editor.
-    let canvas = Canvas::new(window_size.to_f32());
-    let mut canvas = canvas.get_context_2d(CanvasFontContext::from_system_source());
-
-    let front_end = XiPathFrontend::new_with_pathfinder_renderer();
+    let measurer = Arc::new(Mutex::new(WidthMeasurer::new()));
+    front_end.set_measurer(measurer.clone());
 
-    // arrange xi-editor backend
-    let mut backend_session = Session::new(Box::new(front_end));
-
-    // create a new view
-    let view_id = create_view(&mut backend_session);
+    // arrange xi-editor backend, in-process unless we've been pointed at an xi-core binary to run
+    let config_dir = config_dir(config_dir_arg);
+    //todo bundle plugins and themes with the app
+    let extras_dir = None;
+    // with --record, everything between us and xi-core also goes to a trace file
+    let transport: Box<dyn Transport> = match record_path {
+        Some(path) => {
+            let trace = match Trace::create(&path) {
+                Ok(t) => t,
+                Err(e) => panic!("couldn't create trace {:?}: {}", path, e),
+            };
+            let front_end = RecordingFrontend::new(front_end, trace.clone());
+            let transport = start_transport(front_end, config_dir.clone(), extras_dir);
+            Box::new(RecordingTransport::new(transport, trace))
+        }
+        None => start_transport(front_end, config_dir.clone(), extras_dir),
+    };
+    // the file finder looks under wherever the editor was started from
+    let working_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut backend_session = Session::new(transport, frontend_messages, config_dir);
 
-    // draw our editor on the canvas
-    draw(&mut canvas, &window_size);
+    if let Some(watcher) = file_watcher {
+        backend_session.set_file_watcher(watcher);
+    }
+    let mut render_loop = RenderLoop::new(renderer, measurer, frame_stats);
 
-    // Render the canvas to screen.
-    let mut scene = SceneProxy::from_scene(canvas.into_canvas().into_scene(),
-                                           renderer.mode().level,
-                                           RayonExecutor);
-    scene.build_and_render(&mut renderer, BuildOptions::default());
-    renderer.device().present_drawable(drawable);
+    // pick up where the last run left off, or start with an empty view
+    if let Some(window) = backend_session.saved_window() {
+        render_loop.restore_window(window);
+    }
+    if !backend_session.restore_views() {
+        if let Err(e) = backend_session.add_new_view(None) {
+            panic!("couldn't open an empty view: {}", e);
+        }
+    }
 
     // Wait for a keypress.
     let mut event_pump = sdl_context.event_pump().unwrap();
     loop {
-        match event_pump.wait_event() {
-            Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return,
-            //todo insert
-            Event::KeyDown { keycode: Some(key), .. } => backend_session.insert(&view_id, key),
-            _ => {}
+        // apply whatever xi-core has told the frontend since we last looked
+        if backend_session.apply_frontend_messages() {
+            render_loop.core_updated();
         }
-    }
-}
 
-fn draw(canvas : &mut CanvasRenderingContext2D, window_size: &Vector2I) {
-    let gutter_size = draw_line_gutter(canvas, &window_size);
-    draw_sample_lines(canvas, &gutter_size);
-}
+        // work that's already due runs now, steady input would otherwise keep it waiting
+        // forever, since it only runs when waiting for input times out
+        if backend_session.wait_timeout() == Some(Duration::from_millis(0)) {
+            backend_session.run_scheduled();
+        }
 
-fn draw_sample_lines(canvas: &mut CanvasRenderingContext2D, gutter_size: &Vector2F) {
-    set_line_text_style(canvas);
-    let padding = 4_f32;
-    let line_margin = gutter_size.x() + padding;
+        // handle everything that's already waiting before drawing, then block for input,
+        // but only until xi-core has idle or timer work waiting
+        let event = match event_pump.poll_event() {
+            Some(e) => Some(e),
+            None => {
+                render_loop.redraw_if_needed(&mut backend_session);
+                match backend_session.wait_timeout() {
+                    None => Some(event_pump.wait_event()),
+                    Some(timeout) => event_pump.wait_event_timeout(ceil_millis(timeout)),
+                }
+            }
+        };
 
-    for line in 1..11 {
-        let line_bottom = gutter_size.y() * (line as f32); 
-        canvas.fill_text("System.Console.WriteLine(\"Hello World!\");", vec2f(line_margin, line_bottom));
-    }
+        let event = match event {
+            Some(e) => e,
+            None => {
+                // anything the work changes comes back as frontend messages
+                backend_session.run_scheduled();
+                continue;
+            }
+        };
 
-    for line in 11..21 {
-        let line_bottom = gutter_size.y() * (line as f32); 
-        canvas.fill_text("System.Console.WriteLine(\"Hello World!\");", vec2f(line_margin, line_bottom));
+        match event {
+            // unsaved edits are asked about before anything is thrown away
+            Event::Quit {..} => {
+                backend_session.request_quit();
+                render_loop.input_received(false)
+            }
+            // resizes, and moves to a monitor with a different scale factor
+            Event::Window { .. } => render_loop.resize(),
+            // a callback queued a message, loop round to apply it
+            ref e if waker.is_wake_event(e) => {}
+            // mouse positions are in points, the same as layout, whatever the scale factor
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                if render_loop.click(&mut backend_session, x as f32, y as f32) {
+                    render_loop.input_received(true)
+                }
+            }
+            Event::MouseMotion { mousestate, x, y, .. } if mousestate.left() => {
+                if render_loop.drag(&mut backend_session, x as f32, y as f32) {
+                    render_loop.input_received(false)
+                }
+            }
+            Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => render_loop.release(),
+            // text typed into an overlay, like the file finder's query
+            Event::TextInput { ref text, .. } if backend_session.handle_overlay_text(text) => {
+                render_loop.input_received(false)
+            }
+            // anything else typed goes into the focused view
+            Event::TextInput { ref text, .. } => {
+                if let Some(view_id) = backend_session.focused_view() {
+                    backend_session.insert(view_id, text);
+                    render_loop.input_received(true)
+                }
+            }
+            // an open picker gets every key first, including escape to close it
+            Event::KeyDown { keycode: Some(key), .. } if backend_session.handle_overlay_key(key) => {
+                render_loop.input_received(false)
+            }
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                backend_session.request_quit();
+                render_loop.input_received(false)
+            }
+            Event::KeyDown { keycode: Some(Keycode::L), keymod, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                backend_session.open_language_picker();
+                render_loop.input_received(false)
+            }
+            Event::KeyDown { keycode: Some(Keycode::P), keymod, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                backend_session.open_file_finder(&working_dir);
+                render_loop.input_received(false)
+            }
+            Event::KeyDown { keycode: Some(Keycode::S), keymod, .. }
+                if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                backend_session.open_save_as();
+                render_loop.input_received(false)
+            }
+            Event::KeyDown { keycode: Some(Keycode::S), keymod, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                backend_session.save();
+                render_loop.input_received(false)
+            }
+            Event::KeyDown { keycode: Some(Keycode::Comma), keymod, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                backend_session.toggle_settings_panel();
+                render_loop.input_received(false)
+            }
+            Event::KeyDown { keycode: Some(Keycode::LeftBracket), keymod, .. }
+                if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                if backend_session.toggle_fold_at_cursor() {
+                    render_loop.input_received(false)
+                }
+            }
+            Event::KeyDown { keycode: Some(Keycode::M), keymod, .. }
+                if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                backend_session.toggle_diagnostics_panel();
+                render_loop.input_received(false)
+            }
+            // keys that type text come back as TextInput, so only bound keys are handled here
+            Event::KeyDown { keycode: Some(key), keymod, .. } => {
+                if backend_session.handle_bound_key(key, keymod) {
+                    render_loop.input_received(true)
+                }
+            }
+            _ => {}
+        }
+
+        if backend_session.should_quit() {
+            break;
+        }
     }
+
+    backend_session.save_views(render_loop.window_geometry());
+    backend_session.shutdown();
+    render_loop.print_stats();
 }
 
-fn draw_line_gutter(canvas: &mut CanvasRenderingContext2D, canvas_size: &Vector2I) -> Vector2F {
-    set_gutter_text_style(canvas);
-    
-    // we need metrics for the height of the chosen gutter text style - so we insert a 0 (numbers dont descened, and they take up full vertical space, so any is fine)
-    let single_digit_metrics = canvas.measure_text("0");
-    let line_height = single_digit_metrics.em_height_ascent();
-
-    //get total lines the view can fit in entirety, drop the remainder by forcing to i32, add 1 line to be partially rendered at bottom
-    let lines = (canvas_size.y() as f32 / line_height) as i32 + 1;
-    
-    let left_margin = 0_f32;
-    
-    // we use the iterator for line number text, so start at 1 and add 1 the inclusive end
-    for number in 1..(lines +1) as i32 {        
-        let line_bottom = line_height * number as f32;
-        canvas.fill_text(&number.to_string(), vec2f(left_margin, line_bottom))
+// --config-dir, then $XI_PATH_CONFIG_DIR, then xi-path in the platform's config directory, like
+// ~/.config/xi-path. it's made on a first run, so there's somewhere to keep the session
+fn config_dir(arg: Option<PathBuf>) -> Option<PathBuf> {
+    let dir = arg
+        .or_else(|| env::var_os("XI_PATH_CONFIG_DIR").map(PathBuf::from))
+        .or_else(|| dirs::config_dir().map(|dir| dir.join("xi-path")));
+    match dir {
+        Some(dir) => {
+            if let Err(e) = fs::create_dir_all(&dir) {
+                eprintln!("couldn't make config dir {:?}: {}", dir, e);
+            }
+            Some(dir)
+        }
+        None => {
+            eprintln!("no config dir to read settings from, pass one with --config-dir");
+            None
+        }
     }
-    
-    //measure the text of the last line number (lines in 10s, 100s, 1000s range have diff width)
-    let max_digit_metrics = canvas.measure_text(&lines.to_string());
-    return vec2f(max_digit_metrics.width(), line_height);
 }
 
-fn set_line_text_style(canvas: &mut CanvasRenderingContext2D)
+fn start_transport<F>(front_end: F, config_dir: Option<PathBuf>, extras_dir: Option<PathBuf>) -> Box<dyn Transport>
+    where F: Frontend + HandleNotification + Send + Sync + 'static
 {
-    let path = Path::new("/Users/nickspagnola/Library/Fonts/Roboto Mono for Powerline.ttf");
-    
-    let font = match Font::from_path(path, 0) {
-        Err(e) => panic!("{}", e),
-        Ok(f) => f,
-    };
-
-    canvas.set_font(font);
-    canvas.set_font_size(14.0);
-    canvas.set_fill_style(FillStyle::Color(ColorU::black()));
+    match env::var_os("XI_PATH_CORE") {
+        Some(core_path) => {
+            match StdioTransport::spawn(&PathBuf::from(core_path), front_end, config_dir, extras_dir) {
+                Ok(t) => Box::new(t),
+                Err(e) => panic!("couldn't start xi-core: {}", e),
+            }
+        }
+        None => Box::new(DirectTransport::new(front_end, config_dir, extras_dir)),
+    }
 }
 
-fn set_gutter_text_style(canvas: &mut CanvasRenderingContext2D)
-{
-    let path = Path::new("/Users/nickspagnola/Library/Fonts/Roboto Mono Light for Powerline.ttf");
-    let font = match Font::from_path(path, 0) {
-        Err(e) => panic!("{}", e),
+fn replay_trace(path: &Path) {
+    let file = match File::open(path) {
         Ok(f) => f,
+        Err(e) => panic!("couldn't open trace {:?}: {}", path, e),
+    };
+    let state = match trace::replay_headless(BufReader::new(file)) {
+        Ok(s) => s,
+        Err(e) => panic!("couldn't replay trace {:?}: {}", path, e),
     };
 
-    canvas.set_font(font);
-    canvas.set_font_size(14.0);
-    canvas.set_fill_style(FillStyle::Color(ColorU::new(170, 170, 170, 255)));
+    let languages: Vec<&str> = state.available_languages.iter().map(|l| l.as_ref()).collect();
+    println!("available languages: {}", languages.join(", "));
+    for (view_id, view) in &state.views {
+        println!("{:?}", view_id);
+        println!("  language: {}", view.language.as_ref().map(|l| l.as_ref()).unwrap_or("(none)"));
+        for setting in view.settings.describe() {
+            println!("  {}", setting);
+        }
+        for item in view.status_bar.items(StatusAlignment::Left).chain(view.status_bar.items(StatusAlignment::Right)) {
+            println!("  status {}: {}", item.key, item.value);
+        }
+    }
 }
 
-fn print_text_metrics(canvas: &mut CanvasRenderingContext2D, text: &str) {
-    let metrics = canvas.measure_text(text);
-    println!("** Text: {}", text);
-
-    println!(" actual_bounding_box_ascent: {}", metrics.actual_bounding_box_ascent());
-    println!(" actual_bounding_box_descent: {}", metrics.actual_bounding_box_descent());
-    println!(" actual_bounding_box_left: {}", metrics.actual_bounding_box_left());
-    println!(" actual_bounding_box_right: {}", metrics.actual_bounding_box_right());
-    println!(" alphabetic_baseline: {}", metrics.alphabetic_baseline());
-    println!(" em_height_ascent: {}", metrics.em_height_ascent());
-    println!(" em_height_descent: {}", metrics.em_height_descent());
-    println!(" font_bounding_box_ascent: {}", metrics.font_bounding_box_ascent());
-    println!(" font_bounding_box_descent: {}", metrics.font_bounding_box_descent());
-    println!(" hanging_baseline: {}", metrics.hanging_baseline());
-    println!(" ideographic_baseline: {}", metrics.ideographic_baseline());
-    println!(" text_x_offset: {}", metrics.text_x_offset());
-    println!(" text_y_offset: {}", metrics.text_y_offset());
-    println!(" width: {}", metrics.width());
+// rounds up, so we never wake just before a timer is due and spin
+fn ceil_millis(duration: Duration) -> u32 {
+    ((duration.as_micros() + 999) / 1000) as u32
 }
-
-fn insert(session: &mut Session, view_id: &ViewId) {
-
-}
\ No newline at end of file
@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use frontend::diagnostics::Diagnostic;
+use frontend::file_finder::FileFinder;
+use frontend::picker::Picker;
+use xi_core_lib::{ LanguageId, ViewId };
+
+/// The choices when saving over a file that's already there.
+pub const OVERWRITE: &str = "Overwrite";
+pub const CANCEL: &str = "Cancel";
+/// The choices when quitting with unsaved edits, besides cancelling.
+pub const SAVE: &str = "Save";
+pub const DISCARD: &str = "Discard";
+/// The choices when a file with unsaved edits changes on disk.
+pub const RELOAD: &str = "Reload";
+pub const KEEP: &str = "Keep";
+pub const DIFF: &str = "Diff";
+
+/// Whatever is currently drawn over the editor and taking keyboard input.
+pub enum Overlay {
+    LanguagePicker(Picker<LanguageId>),
+    Settings(Picker<String>),
+    Diagnostics(Picker<Diagnostic>),
+    /// Something to tell the user, with a line for each thing it's about.
+    Notice(Picker<String>),
+    FileFinder(FileFinder),
+    /// The path a view is to be saved to, as typed so far.
+    SaveAs { view_id: ViewId, path: String },
+    /// Saving a view over a file that's already there, until the user says it's alright.
+    ConfirmOverwrite { view_id: ViewId, path: PathBuf, choices: Picker<String> },
+    /// What to do with a view's unsaved edits before quitting.
+    ConfirmQuit { view_id: ViewId, choices: Picker<String> },
+    /// A view's file changed on disk while it had unsaved edits, and what to do about it.
+    FileConflict { view_id: ViewId, choices: Picker<String> },
+}
+
+impl Overlay {
+    pub fn select_next(&mut self) {
+        match self {
+            Overlay::LanguagePicker(picker) => picker.select_next(),
+            Overlay::Settings(picker) => picker.select_next(),
+            Overlay::Diagnostics(picker) => picker.select_next(),
+            Overlay::Notice(picker) => picker.select_next(),
+            Overlay::FileFinder(finder) => finder.matches.select_next(),
+            Overlay::SaveAs { .. } => {}
+            Overlay::ConfirmOverwrite { choices, .. } => choices.select_next(),
+            Overlay::ConfirmQuit { choices, .. } => choices.select_next(),
+            Overlay::FileConflict { choices, .. } => choices.select_next(),
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        match self {
+            Overlay::LanguagePicker(picker) => picker.select_previous(),
+            Overlay::Settings(picker) => picker.select_previous(),
+            Overlay::Diagnostics(picker) => picker.select_previous(),
+            Overlay::Notice(picker) => picker.select_previous(),
+            Overlay::FileFinder(finder) => finder.matches.select_previous(),
+            Overlay::SaveAs { .. } => {}
+            Overlay::ConfirmOverwrite { choices, .. } => choices.select_previous(),
+            Overlay::ConfirmQuit { choices, .. } => choices.select_previous(),
+            Overlay::FileConflict { choices, .. } => choices.select_previous(),
+        }
+    }
+
+    /// Types `text` into the overlay, if it's one that takes text. Returns whether it was.
+    pub fn type_text(&mut self, text: &str) -> bool {
+        match self {
+            Overlay::FileFinder(finder) => finder.push_str(text),
+            Overlay::SaveAs { path, .. } => path.push_str(text),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn backspace(&mut self) {
+        match self {
+            Overlay::FileFinder(finder) => finder.pop(),
+            Overlay::SaveAs { path, .. } => {
+                path.pop();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use frontend::frame_stats::{FrameStats, FrameTiming};
+use frontend::layout::{self, LayoutConfig, ViewLayout};
+use frontend::line::Line;
+use frontend::measure::WidthMeasurer;
+use frontend::minimap::{Minimap, MinimapLayout};
+use frontend::retained::RetainedLayout;
+use frontend::session_state::WindowGeometry;
+use frontend::settings::ViewSettings;
+use sdl2::video::WindowPos;
+use xi_path_renderer::display_list::{Frame, Point, RenderTarget};
+use xi_path_renderer::fonts::FontCache;
+use xi_path_renderer::pathfinder_renderer::PathfinderRenderer;
+use xi_path_renderer::Renders;
+use xi_core_lib::ViewId;
+
+use crate::overlay::Overlay;
+use crate::session::Session;
+
+// how many frames go into each summary printed with --frame-stats
+const STATS_FRAMES: usize = 120;
+
+/// Decides when to draw, and draws. Everything that wants the window redrawn asks for it, and
+/// one frame is drawn once the events waiting have all been handled, so a burst of keys or of
+/// updates from xi-core is drawn once. Presenting waits for vsync, so that's a frame per vsync at most.
+pub struct RenderLoop {
+    renderer: PathfinderRenderer,
+    measurer: Arc<Mutex<WidthMeasurer>>,
+    // line layouts kept from the last frame, so only lines that changed are laid out again
+    retained: RetainedLayout,
+    // the focused view's minimap, started afresh when another view gets focus
+    minimap: Minimap,
+    minimap_view: Option<ViewId>,
+    // whether the mouse went down on the text and hasn't come up yet
+    selecting: bool,
+    needs_redraw: bool,
+    // overlays aren't tracked for damage, so a frame with one, or just after one, is drawn whole
+    had_overlay: bool,
+    // the oldest input not on screen yet, and whether it's waiting on xi-core to show
+    pending_input: Option<(Instant, bool)>,
+    core_updated: bool,
+    // what the window's title was last set to
+    title: String,
+    stats: Option<FrameStats>,
+}
+
+impl RenderLoop {
+    pub fn new(renderer: PathfinderRenderer, measurer: Arc<Mutex<WidthMeasurer>>, frame_stats: bool) -> Self {
+        RenderLoop {
+            renderer,
+            measurer,
+            retained: RetainedLayout::new(),
+            minimap: Minimap::default(),
+            minimap_view: None,
+            selecting: false,
+            needs_redraw: true,
+            had_overlay: false,
+            pending_input: None,
+            core_updated: false,
+            title: String::new(),
+            stats: if frame_stats { Some(FrameStats::new(STATS_FRAMES)) } else { None },
+        }
+    }
+
+    pub fn request_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
+
+    /// xi-core has sent something that changes what's on screen.
+    pub fn core_updated(&mut self) {
+        self.core_updated = true;
+        self.needs_redraw = true;
+    }
+
+    /// A key came in. Typing only shows up once xi-core has sent the edit back, so its latency
+    /// runs to the first frame after that; keys we handle ourselves show up on the next frame.
+    pub fn input_received(&mut self, waits_for_core: bool) {
+        if self.pending_input.is_none() {
+            self.pending_input = Some((Instant::now(), waits_for_core));
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Picks up a new window size or scale factor, see `PathfinderRenderer::resize`.
+    pub fn resize(&mut self) {
+        if self.renderer.resize() {
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Where the window is and how big, to keep for the next start.
+    pub fn window_geometry(&self) -> WindowGeometry {
+        let window = self.renderer.window();
+        let (x, y) = window.position();
+        let (width, height) = window.size();
+        WindowGeometry { x, y, width, height }
+    }
+
+    /// Puts the window back where `window_geometry` said it was.
+    pub fn restore_window(&mut self, geometry: WindowGeometry) {
+        let window = self.renderer.window_mut();
+        if let Err(e) = window.set_size(geometry.width, geometry.height) {
+            eprintln!("couldn't resize the window to {}x{}: {}", geometry.width, geometry.height, e);
+        }
+        window.set_position(WindowPos::Positioned(geometry.x), WindowPos::Positioned(geometry.y));
+        self.resize();
+    }
+
+    pub fn redraw_if_needed(&mut self, session: &mut Session) {
+        if !self.needs_redraw {
+            return;
+        }
+        self.needs_redraw = false;
+
+        // lay out our editor, then draw it to screen
+        let started = Instant::now();
+        let target = self.renderer.target();
+        // the text and gutter go in the view's font_face setting, when it has one
+        let config = LayoutConfig::default();
+        update_viewport(&target, session, &config, self.renderer.fonts(), &self.measurer);
+        let frame = self.build_frame(&target, session, &config);
+        self.request_minimap_lines(session);
+        self.update_title(session);
+        let laid_out = Instant::now();
+
+        self.renderer.render(&frame, &target);
+        self.renderer.present();
+        let presented = Instant::now();
+
+        let latency = match self.pending_input {
+            Some((input, waits_for_core)) if self.core_updated || !waits_for_core => {
+                self.pending_input = None;
+                Some(presented - input)
+            }
+            _ => None,
+        };
+        self.core_updated = false;
+
+        if let Some(stats) = &mut self.stats {
+            stats.record(FrameTiming {
+                layout: laid_out - started,
+                render: presented - laid_out,
+                latency,
+                relaid_lines: self.retained.relaid_lines,
+            });
+            if stats.len() == STATS_FRAMES {
+                eprintln!("{}", stats.summary());
+                *stats = FrameStats::new(STATS_FRAMES);
+            }
+        }
+    }
+
+    /// Sends a click at (`x`, `y`) in the window to xi-core as a cursor placement, if it's over
+    /// a line, or folds or unfolds a region when it's on a fold marker. Returns whether it was either.
+    pub fn click(&mut self, session: &mut Session, x: f32, y: f32) -> bool {
+        let view_id = match session.focused_view() {
+            Some(v) => v,
+            None => return false,
+        };
+        if self.minimap.layout.map(|minimap| minimap.contains(x, y)).unwrap_or(false) {
+            self.minimap.dragging = true;
+            return self.drag(session, x, y);
+        }
+
+        let (viewport, displayed) = match session.state().view(&view_id) {
+            Some(view) => match view.viewport {
+                Some(viewport) => (viewport, view.displayed_lines()),
+                None => return false,
+            },
+            None => return false,
+        };
+
+        if layout::over_fold_markers(x, &viewport) && y >= 0.0 && y < viewport.text_height {
+            let fold_line = displayed.get((y / viewport.line_height) as usize)
+                .filter(|(_, line)| line.fold.is_some())
+                .and_then(|(_, line)| line.line_number);
+            if let Some(number) = fold_line {
+                return session.toggle_fold(view_id, number - 1);
+            }
+        }
+
+        match self.text_position(session, view_id, x, y) {
+            Some((row, col)) => {
+                session.click(view_id, row, col);
+                self.selecting = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The mouse moved to (`x`, `y`) with the button held. While dragging on the minimap the
+    /// view follows it, and after a click on the text the selection does. Returns whether either did.
+    pub fn drag(&mut self, session: &mut Session, x: f32, y: f32) -> bool {
+        let view_id = match session.focused_view() {
+            Some(v) => v,
+            None => return false,
+        };
+        if let (true, Some(minimap)) = (self.minimap.dragging, self.minimap.layout) {
+            session.center_on(view_id, minimap.row_at(y));
+            return true;
+        }
+        if !self.selecting {
+            return false;
+        }
+        match self.text_position(session, view_id, x, y) {
+            Some((row, col)) => {
+                session.drag(view_id, row, col);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.minimap.dragging = false;
+        self.selecting = false;
+    }
+
+    // the row of the line cache and byte offset in it at (`x`, `y`), if that's over a line
+    fn text_position(&mut self, session: &Session, view_id: ViewId, x: f32, y: f32) -> Option<(usize, usize)> {
+        let view = session.state().view(&view_id)?;
+        let viewport = view.viewport?;
+        let (rows, lines): (Vec<usize>, Vec<Line>) = view.displayed_lines().into_iter().unzip();
+        let (row, col) = self.retained.hit_test(Point::new(x, y), &lines, &viewport, &view.settings, &LayoutConfig::default(), self.renderer.fonts())?;
+        // rows of the window skip folded lines, xi-core's don't
+        Some((rows[row], col))
+    }
+
+    /// Prints the frames since the last summary, if we're keeping stats.
+    pub fn print_stats(&self) {
+        if let Some(stats) = &self.stats {
+            if !stats.is_empty() {
+                eprintln!("{}", stats.summary());
+            }
+        }
+    }
+
+    fn build_frame(&mut self, target: &RenderTarget, session: &Session, config: &LayoutConfig) -> Frame {
+        let state = session.state();
+        let view = session.focused_view().and_then(|view_id| state.view(&view_id));
+
+        // settings are re-read every frame, so a config_changed shows up on the next redraw
+        let default_settings = ViewSettings::default();
+        let settings = view.map(|v| &v.settings).unwrap_or(&default_settings);
+
+        let first_line = view.map(|v| v.first_line).unwrap_or(0);
+        let lines: Vec<Line> = match view {
+            Some(v) => v.displayed_lines().into_iter().map(|(_, line)| line).collect(),
+            None => Vec::new(),
+        };
+
+        let view_layout = ViewLayout {
+            lines: &lines,
+            styles: &state.styles,
+            settings,
+            status_bar: view.map(|v| &v.status_bar),
+            first_line,
+            last_line_number: view.map(|v| v.lines.last_line_number()).unwrap_or(0),
+            // the cursor's line is looked for in the whole cache, it may be scrolled out of sight
+            cursor_line: view.and_then(|v| v.lines.cursor_lines().first().map(|line| line + 1)),
+        };
+        let (width, height) = target.logical_size();
+        let fonts = self.renderer.fonts();
+        let mut frame = self.retained.build_frame(width, height, &view_layout, config, fonts);
+
+        // the minimap goes over the right of the text, and isn't part of the retained layout,
+        // so its area is damaged whenever it looks different
+        let focused = session.focused_view();
+        if self.minimap_view != focused {
+            self.minimap = Minimap::default();
+            self.minimap_view = focused;
+        }
+        match (view, view.and_then(|v| v.viewport)) {
+            (Some(view), Some(viewport)) if settings.show_minimap => {
+                let minimap = MinimapLayout::new(width, viewport.text_height, view.lines.height());
+                let on_screen = view.row_range();
+                let changed = self.minimap.draw(&mut frame, minimap, &view.lines, &state.styles, settings, on_screen, target.scale_factor);
+                if let (true, Some(damage)) = (changed, frame.damage.as_mut()) {
+                    damage.push(minimap.rect);
+                }
+            }
+            _ => self.minimap.layout = None,
+        }
+
+        let overlay = session.overlay();
+        if overlay.is_some() || self.had_overlay {
+            frame.damage = None;
+        }
+        self.had_overlay = overlay.is_some();
+
+        match overlay {
+            Some(Overlay::LanguagePicker(picker)) => {
+                let labels: Vec<String> = picker.items().iter().map(|language| language.as_ref().to_string()).collect();
+                layout::draw_picker(&mut frame, &picker.title, &labels, picker.selected_index(), settings, config, fonts)
+            }
+            Some(Overlay::Settings(picker)) | Some(Overlay::Notice(picker)) => {
+                layout::draw_picker(&mut frame, &picker.title, picker.items(), picker.selected_index(), settings, config, fonts)
+            }
+            Some(Overlay::Diagnostics(picker)) => {
+                let labels: Vec<String> = picker.items().iter().map(|diagnostic| diagnostic.describe()).collect();
+                layout::draw_picker(&mut frame, &picker.title, &labels, picker.selected_index(), settings, config, fonts)
+            }
+            Some(Overlay::FileFinder(finder)) => {
+                let labels: Vec<String> = finder.matches.items().iter().map(|path| path.display().to_string()).collect();
+                layout::draw_picker(&mut frame, &finder.matches.title, &labels, finder.matches.selected_index(), settings, config, fonts)
+            }
+            Some(Overlay::SaveAs { path, .. }) => {
+                layout::draw_picker(&mut frame, &format!("Save as: {}", path), &[], 0, settings, config, fonts)
+            }
+            Some(Overlay::ConfirmOverwrite { choices, .. })
+            | Some(Overlay::ConfirmQuit { choices, .. })
+            | Some(Overlay::FileConflict { choices, .. }) => {
+                layout::draw_picker(&mut frame, &choices.title, choices.items(), choices.selected_index(), settings, config, fonts)
+            }
+            None => {}
+        }
+        frame
+    }
+}
+
+impl RenderLoop {
+    // the title bar names the focused view, and shows when it has unsaved edits
+    fn update_title(&mut self, session: &Session) {
+        let view = session.focused_view().and_then(|view_id| session.state().view(&view_id));
+        let title = match view {
+            Some(view) => format!("{} - xi-path", view.title()),
+            None => "xi-path".to_string(),
+        };
+        if title == self.title {
+            return;
+        }
+        if let Err(e) = self.renderer.window_mut().set_title(&title) {
+            eprintln!("couldn't set the window title to {:?}: {}", title, e);
+        }
+        self.title = title;
+    }
+
+    // the minimap shows the whole buffer, so the lines xi-core hasn't sent are asked for a chunk
+    // at a time, the next once the last has come in
+    fn request_minimap_lines(&mut self, session: &mut Session) {
+        let view_id = match session.focused_view() {
+            Some(v) => v,
+            None => return,
+        };
+        let rows = match session.state().view(&view_id) {
+            Some(view) if view.settings.show_minimap => self.minimap.rows_to_request(&view.lines),
+            _ => None,
+        };
+        if let Some((first, last)) = rows {
+            session.request_lines(view_id, first, last);
+        }
+    }
+}
+
+// tells xi-core what's on screen, which is also how it finds out the window's been resized
+fn update_viewport(target: &RenderTarget,
+                   session: &mut Session,
+                   config: &LayoutConfig,
+                   fonts: &mut FontCache,
+                   measurer: &Mutex<WidthMeasurer>) {
+    let view_id = match session.focused_view() {
+        Some(v) => v,
+        None => return,
+    };
+    let (settings, first_line, last_line_number) = match session.state().view(&view_id) {
+        Some(view) => (view.settings.clone(), view.first_line, view.lines.last_line_number()),
+        None => (ViewSettings::default(), 0, 0),
+    };
+
+    // xi-core measures for wrapping in whatever font we're drawing with now
+    measurer.lock().unwrap().set_font(config.text_font(&settings));
+
+    let (width, height) = target.logical_size();
+    let viewport = layout::viewport(width, height, first_line, last_line_number, &settings, true, config, fonts);
+    session.set_viewport(view_id, viewport);
+}
@@ -1,279 +1,878 @@
-use std::{path::PathBuf, sync::Mutex, sync::Arc, cell::RefCell, sync::Weak};
-use sdl2::{keyboard::Keycode, VideoSubsystem};
+use std::{fs, path::{Path, PathBuf}, sync::mpsc::Receiver, time::Duration};
+use sdl2::keyboard::{Keycode, Mod};
 
-use xi_core_lib::{ ViewId, XiCore , client::Frontend};
+use xi_core_lib::{ ViewId, LanguageId };
+use xi_path_backend::{ Client, RpcError, Transport };
+use xi_path_backend::edit::{ EditCommand, GestureType, MouseDrag, Size };
+use xi_path_backend::rpc::{ CloseView, GetConfig, NewView, Save, SetLanguage };
 
-use frontend::frontend::{XiPathFrontend};
+use frontend::file_finder::FileFinder;
+use frontend::file_watcher::{ DiskState, FileWatcher };
+use frontend::keymap;
+use frontend::line_diff;
+use frontend::layout::Viewport;
+use frontend::message::FrontendMessage;
+use frontend::picker::Picker;
+use frontend::session_state::{ SessionState, ViewSnapshot, WindowGeometry };
+use frontend::state::EditorState;
+
+use crate::overlay::{ Overlay, CANCEL, DIFF, DISCARD, KEEP, OVERWRITE, RELOAD, SAVE };
 
 pub struct Session {
-    frontend: XiPathFrontend,
-    backend: XiCore,
+    state: EditorState,
+    messages: Receiver<FrontendMessage>,
+    backend: Client,
+    focused_view: Option<ViewId>,
+    // every view we've opened, in order
+    views: Vec<ViewId>,
+    overlay: Option<Overlay>,
+    // what's kept between runs, saved whenever it changes
+    saved: SessionState,
+    // while quitting, the views with unsaved edits still to be asked about
+    quitting: Option<Vec<ViewId>>,
+    quit_confirmed: bool,
+    watcher: Option<FileWatcher>,
 }
 
 impl Session {
-    pub fn new() -> Self {
-        let config_dir = PathBuf::from("/Users/nickspagnola/Development/Projects/xi-path/dev/config");
-        let extra_dir = PathBuf::from(todo!());
-
-        let frontend = XiPathFrontend::new_with_pathfinder_renderer(); 
+    /// `messages` is the receiving end of the frontend the transport's xi-core was given.
+    pub fn new(transport: Box<dyn Transport>, messages: Receiver<FrontendMessage>, config_dir: Option<PathBuf>) -> Self {
+        let saved = config_dir.as_ref().map(|dir| SessionState::load(dir)).unwrap_or_default();
+        let mut state = EditorState::default();
+        state.config_dir = config_dir;
         Session {
-            frontend,
-            backend: XiCore::new_direct(frontend, Some(config_dir), Some(extras_dir))
+            state,
+            messages,
+            backend: Client::new(transport),
+            focused_view: None,
+            views: Vec::new(),
+            overlay: None,
+            saved,
+            quitting: None,
+            quit_confirmed: false,
+            watcher: None,
+        }
+    }
+
+    /// Watches the files of views for changes made outside the editor.
+    pub fn set_file_watcher(&mut self, mut watcher: FileWatcher) {
+        for path in self.views.iter().filter_map(|view_id| self.state.view(view_id).and_then(|view| view.path.as_ref())) {
+            watcher.watch(path);
+        }
+        self.watcher = Some(watcher);
+    }
+
+    pub fn state(&self) -> &EditorState {
+        &self.state
+    }
+
+    /// Applies everything the frontend has queued up since we last looked.
+    /// Returns whether there was anything, so the caller knows to redraw.
+    pub fn apply_frontend_messages(&mut self) -> bool {
+        let applied = self.drain_frontend_messages();
+        if applied {
+            self.handle_changed_files();
+        }
+        applied
+    }
+
+    // applies the queued messages without acting on files changed on disk, for when there's a
+    // prompt in progress that shouldn't be interrupted
+    fn drain_frontend_messages(&mut self) -> bool {
+        let mut applied = false;
+        while let Ok(message) = self.messages.try_recv() {
+            self.state.apply(message);
+            applied = true;
+        }
+        applied
+    }
+}
+
+// scheduling
+impl Session {
+
+    /// How long the main loop can wait for input before xi-core has scheduled work due.
+    pub fn wait_timeout(&self) -> Option<Duration> {
+        self.state.scheduler.wait_timeout()
+    }
+
+    /// Hands xi-core any idle or timer tokens that are due. Returns whether it had anything to do.
+    pub fn run_scheduled(&mut self) -> bool {
+        let due = self.state.scheduler.take_due();
+        for token in &due {
+            self.backend.handle_idle(*token);
         }
+        !due.is_empty()
     }
 }
 
 // view-related
 impl Session {
 
-    pub (crate) fn add_new_view(&mut self, path: Option<PathBuf>) -> ViewId {
-        match self.backend.new_view(path) {
+    /// Opens a view of `path`, or of a new buffer, and focuses it. xi-core refuses files it
+    /// can't read as text, like binaries or ones we aren't allowed to read.
+    pub (crate) fn add_new_view(&mut self, path: Option<PathBuf>) -> Result<ViewId, RpcError> {
+        let view_id = self.backend.request(&NewView { file_path: path.clone() })?;
+        self.focused_view = Some(view_id);
+        self.views.push(view_id);
+
+        // folds from last time apply once xi-core sends lines they're regions of
+        let folds = path.as_ref().and_then(|path| self.saved.files.get(path)).map(|file| file.folds.clone());
+        let view = self.state.view_mut(view_id);
+        view.on_disk = path.as_ref().and_then(|path| DiskState::of(path));
+        if let (Some(path), Some(watcher)) = (&path, self.watcher.as_mut()) {
+            watcher.watch(path);
+        }
+        view.path = path;
+        if let Some(folds) = folds {
+            view.folds.set_folded(&folds);
+        }
+        Ok(view_id)
+    }
+
+    // xi-core handles what it's sent in order, so once a request has been answered, everything
+    // sent before it has been done, saves written included
+    fn wait_for_core(&mut self, view_id: ViewId) {
+        if let Err(e) = self.backend.request(&GetConfig { view_id }) {
+            eprintln!("xi-core didn't answer: {}", e);
+        }
+    }
+
+    pub fn focused_view(&self) -> Option<ViewId> {
+        self.focused_view
+    }
+
+    /// Tells xi-core which lines are on screen, so it sends us those, when they've changed
+    /// since last time. Called every layout, so resizes and font changes are picked up too.
+    pub fn set_viewport(&mut self, view_id: ViewId, viewport: Viewport) {
+        let view = self.state.view_mut(view_id);
+        let previous = view.viewport.replace(viewport);
+        // folded lines aren't drawn, so the rows on screen can reach further than the viewport
+        let rows = view.row_range();
+        if previous == Some(viewport) && view.requested_rows == Some(rows) {
+            return;
+        }
+        view.requested_rows = Some(rows);
+        let wrap_to_width = view.settings.wrap_width == 0;
+
+        let (first, last) = rows;
+        self.backend.edit(view_id, EditCommand::Scroll((first as i64, last as i64)));
+
+        // with no wrap_width set, xi-core wraps to the window (when word_wrap is on), measuring
+        // text with measure_width, so it needs to know the width we have whenever that changes
+        let width_changed = previous.map(|p| p.text_width != viewport.text_width).unwrap_or(true);
+        if wrap_to_width && width_changed {
+            let size = Size { width: viewport.text_width as usize, height: viewport.text_height as usize };
+            self.backend.edit(view_id, EditCommand::Resize(size));
+        }
+    }
+
+    /// Scrolls so row `row` of the line cache is in the middle of the screen. xi-core is told
+    /// with the next layout, same as any other scroll of ours.
+    pub fn center_on(&mut self, view_id: ViewId, row: usize) {
+        self.state.view_mut(view_id).center_on(row);
+    }
+
+    /// Asks xi-core for rows `first` up to `last` of the view, whether they're on screen or not.
+    pub fn request_lines(&mut self, view_id: ViewId, first: usize, last: usize) {
+        self.backend.edit(view_id, EditCommand::RequestLines((first as i64, last as i64)));
+    }
+
+    // the first selection replaces the cursor xi-core opened the file with, the rest are added.
+    // gestures are in screen lines, which are buffer lines until xi-core has wrapped anything
+    fn restore_selections(&mut self, view_id: ViewId, selections: &[[usize; 4]]) {
+        for (index, range) in selections.iter().enumerate() {
+            let ty = if index == 0 { GestureType::PointSelect } else { GestureType::ToggleSel };
+            self.backend.edit(view_id, EditCommand::Gesture { line: range[0] as u64, col: range[1] as u64, ty });
+            if range[..2] != range[2..] {
+                let gesture = EditCommand::Gesture { line: range[2] as u64, col: range[3] as u64, ty: GestureType::RangeSelect };
+                self.backend.edit(view_id, gesture);
+            }
+        }
+    }
+
+    /// Puts the cursor at `col` (a byte offset) on screen line `line` of the view, where a click landed.
+    pub fn click(&mut self, view_id: ViewId, line: usize, col: usize) {
+        let gesture = EditCommand::Gesture { line: line as u64, col: col as u64, ty: GestureType::PointSelect };
+        self.backend.edit(view_id, gesture);
+    }
+
+    /// Extends the selection a click started to `col` on screen line `line`, where the mouse was dragged.
+    pub fn drag(&mut self, view_id: ViewId, line: usize, col: usize) {
+        let drag = MouseDrag { line: line as u64, col: col as u64, modifiers: 0 };
+        self.backend.edit(view_id, EditCommand::Drag(drag));
+    }
+
+    /// Runs the keymap binding for this chord in the focused view, if there is one.
+    /// Returns false when the key isn't bound, so the caller can treat it as text instead.
+    pub fn handle_bound_key(&mut self, keycode: Keycode, keymod: Mod) -> bool {
+        let view_id = match self.focused_view {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let chord = keymap::chord(keycode, keymod);
+        let method = match self.state.view(&view_id).and_then(|view| view.keymap.command_for(&chord)) {
+            Some(m) => m,
+            None => return false,
+        };
+
+        // moving onto a folded region goes straight past it
+        let down = match method {
+            "move_down" => Some(true),
+            "move_up" => Some(false),
+            _ => None,
+        };
+        if let (Some(down), Some(view)) = (down, self.state.view(&view_id)) {
+            let jump = view.lines.cursor_lines().first().and_then(|line| view.folds.jump_over(*line, down));
+            if let Some(line) = jump {
+                self.goto_line(view_id, line);
+                return true;
+            }
+        }
+
+        match EditCommand::from_method(method) {
+            Some(command) => self.backend.edit(view_id, command),
+            None => eprintln!("{} is bound to {}, which isn't an edit command", chord, method),
+        }
+        true
+    }
+}
+
+// keeping the session between runs
+impl Session {
+
+    /// Where the window was when the editor last quit.
+    pub fn saved_window(&self) -> Option<WindowGeometry> {
+        self.saved.window
+    }
+
+    /// Opens the views that were open when the editor last quit, with their selections and
+    /// where they were scrolled to. Files that have gone since, or that xi-core won't open any
+    /// more, are listed in a notice and forgotten. Returns whether any view opened.
+    pub fn restore_views(&mut self) -> bool {
+        let snapshots = std::mem::replace(&mut self.saved.views, Vec::new());
+        let saved_focus = self.saved.focused.take();
+        let mut focus = None;
+        let mut failed = Vec::new();
+
+        for (index, snapshot) in snapshots.into_iter().enumerate() {
+            if !snapshot.path.is_file() {
+                failed.push(format!("{}: it's gone", snapshot.path.display()));
+                continue;
+            }
+            let view_id = match self.add_new_view(Some(snapshot.path.clone())) {
+                Ok(v) => v,
+                Err(e) => {
+                    failed.push(format!("{}: {}", snapshot.path.display(), e));
+                    continue;
+                }
+            };
+            self.restore_selections(view_id, &snapshot.selections);
+            // xi-core scrolls to the cursor once it's placed, which is a no-op if it was on screen
+            self.state.view_mut(view_id).first_line = snapshot.first_line;
+            if saved_focus == Some(index) {
+                focus = Some(view_id);
+                self.saved.focused = Some(self.saved.views.len());
+            }
+            self.saved.views.push(snapshot);
+        }
+
+        if let Some(view_id) = focus {
+            self.focused_view = Some(view_id);
+        }
+        if !failed.is_empty() {
+            eprintln!("couldn't reopen: {}", failed.join(", "));
+            if let Some(dir) = &self.state.config_dir {
+                self.saved.save(dir);
+            }
+            self.overlay = Some(Overlay::Notice(Picker::new("Couldn't reopen these files", failed)));
+        }
+        !self.views.is_empty()
+    }
+
+    /// Keeps the open views and the window's geometry for the next start. Called on quit.
+    pub fn save_views(&mut self, window: WindowGeometry) {
+        let config_dir = match self.state.config_dir.clone() {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        let mut snapshots = Vec::new();
+        let mut focused = None;
+        for view_id in &self.views {
+            // a view that's never been saved has nothing to reopen
+            let view = match self.state.view(view_id) {
+                Some(view) => view,
+                None => continue,
+            };
+            let path = match &view.path {
+                Some(path) => path.clone(),
+                None => continue,
+            };
+            if self.focused_view == Some(*view_id) {
+                focused = Some(snapshots.len());
+            }
+            snapshots.push(ViewSnapshot { path, selections: view.lines.selections(), first_line: view.first_line });
+        }
+
+        self.saved.views = snapshots;
+        self.saved.focused = focused;
+        self.saved.window = Some(window);
+        self.saved.save(&config_dir);
+    }
+}
+
+// opening and saving files
+impl Session {
+
+    /// Opens the file finder over the files under `root`.
+    pub fn open_file_finder(&mut self, root: &Path) {
+        self.overlay = Some(Overlay::FileFinder(FileFinder::new(root)));
+    }
+
+    /// Focuses the view of `path`, opening one if there isn't one yet.
+    pub fn open_file(&mut self, path: PathBuf) {
+        let state = &self.state;
+        let open = self.views.iter().find(|view_id| state.view(view_id).and_then(|view| view.path.as_ref()) == Some(&path)).cloned();
+        match open {
+            Some(view_id) => self.focused_view = Some(view_id),
+            None => {
+                if let Err(e) = self.add_new_view(Some(path.clone())) {
+                    let title = format!("Couldn't open {}: {}", path.display(), e);
+                    self.overlay = Some(Overlay::Notice(Picker::new(&title, Vec::new())));
+                }
+            }
+        }
+    }
+
+    /// Saves the focused view to its file, or asks where to when it hasn't got one.
+    pub fn save(&mut self) {
+        let view_id = match self.focused_view {
+            Some(v) => v,
+            None => return,
+        };
+        match self.state.view(&view_id).and_then(|view| view.path.clone()) {
+            Some(path) => self.save_as(view_id, path),
+            None => self.open_save_as(),
+        }
+    }
+
+    /// Asks where to save the focused view, starting from where it's saved now.
+    pub fn open_save_as(&mut self) {
+        let view_id = match self.focused_view {
+            Some(v) => v,
+            None => return,
+        };
+        let path = self.state.view(&view_id)
+            .and_then(|view| view.path.as_ref())
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        self.overlay = Some(Overlay::SaveAs { view_id, path });
+    }
+
+    /// Has xi-core save the view to `path`, which is then the file the view is of.
+    pub fn save_as(&mut self, view_id: ViewId, path: PathBuf) {
+        let before = DiskState::of(&path);
+        self.backend.notify(&Save { view_id, file_path: path.clone() });
+
+        // what we've just written isn't a change to reload. xi-core doesn't say when a save
+        // fails, so it's the file itself that tells us, it's rewritten by every save that works
+        self.wait_for_core(view_id);
+        self.drain_frontend_messages();
+        let written = DiskState::of(&path);
+        if written.is_none() || written == before {
+            let title = format!("Couldn't save {}", path.display());
+            eprintln!("{}", title);
+            if self.quitting.is_some() {
+                // asked about again, rather than quitting with the edits lost
+                self.quit_prompt(view_id, &format!("{}, it still has unsaved changes", title));
+            } else {
+                self.overlay = Some(Overlay::Notice(Picker::new(&title, Vec::new())));
+            }
+            return;
+        }
+
+        let view = self.state.view_mut(view_id);
+        let previous = view.path.replace(path.clone());
+        view.on_disk = written;
+        if let Some(watcher) = self.watcher.as_mut() {
+            if let Some(previous) = previous.filter(|previous| *previous != path) {
+                watcher.unwatch(&previous);
+            }
+            watcher.watch(&path);
+        }
+
+        // a save asked for while quitting moves on to the next view
+        if self.quitting.is_some() {
+            self.next_quit_prompt();
+        }
+    }
+
+    // saving over another file needs saying yes to first, saving over the view's own doesn't
+    fn confirm_save_as(&mut self, view_id: ViewId, path: PathBuf) {
+        let own_file = self.state.view(&view_id).and_then(|view| view.path.as_ref()) == Some(&path);
+        if own_file || !path.exists() {
+            self.save_as(view_id, path);
+            return;
+        }
+        let title = format!("{} already exists", path.display());
+        let choices = Picker::new(&title, vec![OVERWRITE.to_string(), CANCEL.to_string()]);
+        self.overlay = Some(Overlay::ConfirmOverwrite { view_id, path, choices });
+    }
+}
+
+// quitting
+impl Session {
+
+    /// Starts quitting. Each view with unsaved edits is asked about in turn, to save them,
+    /// discard them, or call the quit off, and `should_quit` says when they all have been.
+    pub fn request_quit(&mut self) {
+        let state = &self.state;
+        let dirty = self.views.iter()
+            .filter(|view_id| state.view(view_id).map(|view| view.dirty).unwrap_or(false))
+            .cloned()
+            .collect();
+        self.quitting = Some(dirty);
+        self.next_quit_prompt();
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit_confirmed
+    }
+
+    /// Closes every view, ahead of xi-core being torn down, once the saves sent it are written.
+    pub fn shutdown(&mut self) {
+        if let Some(view_id) = self.views.first().cloned() {
+            self.wait_for_core(view_id);
+        }
+        for view_id in self.views.drain(..) {
+            self.backend.notify(&CloseView { view_id });
+        }
+        self.focused_view = None;
+    }
+
+    // asks about the next view with unsaved edits, or lets the quit go ahead once there are none
+    fn next_quit_prompt(&mut self) {
+        let view_id = match self.quitting.as_mut() {
+            Some(views) if !views.is_empty() => views.remove(0),
+            Some(_) => {
+                self.quitting = None;
+                self.quit_confirmed = true;
+                return;
+            }
+            None => return,
+        };
+
+        let title = self.state.view(&view_id).map(|view| view.title()).unwrap_or_default();
+        self.quit_prompt(view_id, &format!("{} has unsaved changes", title));
+    }
+
+    fn quit_prompt(&mut self, view_id: ViewId, title: &str) {
+        // the view in question is shown behind the prompt
+        self.focused_view = Some(view_id);
+        let choices = vec![SAVE.to_string(), DISCARD.to_string(), CANCEL.to_string()];
+        let choices = Picker::new(title, choices);
+        self.overlay = Some(Overlay::ConfirmQuit { view_id, choices });
+    }
+}
+
+// files changed outside the editor
+impl Session {
+
+    // a view without unsaved edits is reloaded, one with is asked about, a prompt at a time
+    fn handle_changed_files(&mut self) {
+        let state = &self.state;
+        let changed: Vec<ViewId> = self.views.iter()
+            .filter(|view_id| state.view(view_id).map(|view| view.changed_on_disk).unwrap_or(false))
+            .cloned()
+            .collect();
+
+        for view_id in changed {
+            let (path, on_disk, dirty) = match self.state.view(&view_id) {
+                Some(view) => match &view.path {
+                    Some(path) => (path.clone(), view.on_disk, view.dirty),
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            // our own saves, and files deleted (or not yet renamed into place) aren't reloaded
+            let now = DiskState::of(&path);
+            if now.is_none() || now == on_disk {
+                self.state.view_mut(view_id).changed_on_disk = false;
+                continue;
+            }
+            if !dirty {
+                self.state.view_mut(view_id).changed_on_disk = false;
+                self.reload(view_id);
+                continue;
+            }
+            // left marked until there's no other prompt in the way
+            if self.overlay.is_some() {
+                continue;
+            }
+
+            let view = self.state.view_mut(view_id);
+            view.changed_on_disk = false;
+            let title = format!("{} changed on disk, but has unsaved edits here", view.title());
+            let height = view.lines.height();
+            // a diff needs every line, so ask for any we don't have yet
+            if view.lines.text().is_none() {
+                self.request_lines(view_id, 0, height);
+            }
+            self.focused_view = Some(view_id);
+            let choices = Picker::new(&title, vec![RELOAD.to_string(), KEEP.to_string(), DIFF.to_string()]);
+            self.overlay = Some(Overlay::FileConflict { view_id, choices });
+        }
+    }
+
+    /// Opens the view's file again, from what's on disk now, keeping its selections, scroll
+    /// position and folds. xi-core can't reload a buffer, so a new view is opened in its place,
+    /// and the old one only closed once that's worked. If it doesn't, the old view stays.
+    pub fn reload(&mut self, view_id: ViewId) {
+        let (path, selections, first_line, folded) = match self.state.view(&view_id) {
+            Some(view) => match &view.path {
+                Some(path) => (path.clone(), view.lines.selections(), view.first_line, view.folds.folded()),
+                None => return,
+            },
+            None => return,
+        };
+        let index = match self.views.iter().position(|v| *v == view_id) {
+            Some(index) => index,
+            None => return,
+        };
+        let focused = self.focused_view;
+
+        // the file stays watched, the new view is of the same path
+        let reloaded = match self.add_new_view(Some(path.clone())) {
             Ok(v) => v,
-            Err(e) => panic!("new_view errored out"),
-        }
-    }
-
-    pub fn insert(&mut self, view_id: &ViewId, keycode: Keycode) {
-        let insertable_key = match keycode {
-            Keycode::A => keycode,
-            //Keycode::Backspace => {}
-            //Keycode::Tab => {}
-            // Keycode::Return => {}
-            // Keycode::Escape => {}
-            Keycode::Space => keycode,
-            Keycode::Exclaim => keycode,
-            Keycode::Quotedbl => keycode,
-            Keycode::Hash => keycode,
-            Keycode::Dollar => keycode,
-            Keycode::Percent => keycode,
-            Keycode::Ampersand => keycode,
-            Keycode::Quote => keycode,
-            Keycode::LeftParen => keycode,
-            Keycode::RightParen => keycode,
-            Keycode::Asterisk => keycode,
-            Keycode::Plus => keycode,
-            Keycode::Comma => keycode,
-            Keycode::Minus => keycode,
-            Keycode::Period => keycode,
-            Keycode::Slash => keycode,
-            Keycode::Num0 => keycode,
-            Keycode::Num1 => keycode,
-            Keycode::Num2 => keycode,
-            Keycode::Num3 => keycode,
-            Keycode::Num4 => keycode,
-            Keycode::Num5 => keycode,
-            Keycode::Num6 => keycode,
-            Keycode::Num7 => keycode,
-            Keycode::Num8 => keycode,
-            Keycode::Num9 => keycode,
-            Keycode::Colon => keycode,
-            Keycode::Semicolon => keycode,
-            Keycode::Less => keycode,
-            Keycode::Equals => keycode,
-            Keycode::Greater => keycode,
-            Keycode::Question => keycode,
-            Keycode::At => keycode,
-            Keycode::LeftBracket => keycode,
-            Keycode::Backslash => keycode,
-            Keycode::RightBracket => keycode,
-            Keycode::Caret => keycode,
-            Keycode::Underscore => keycode,
-            Keycode::Backquote => keycode,
-            Keycode::B => keycode,
-            Keycode::C => keycode,
-            Keycode::D => keycode,
-            Keycode::E => keycode,
-            Keycode::F => keycode,
-            Keycode::G => keycode,
-            Keycode::H => keycode,
-            Keycode::I => keycode,
-            Keycode::J => keycode,
-            Keycode::K => keycode,
-            Keycode::L => keycode,
-            Keycode::M => keycode,
-            Keycode::N => keycode,
-            Keycode::O => keycode,
-            Keycode::P => keycode,
-            Keycode::Q => keycode,
-            Keycode::R => keycode,
-            Keycode::S => keycode,
-            Keycode::T => keycode,
-            Keycode::U => keycode,
-            Keycode::V => keycode,
-            Keycode::W => keycode,
-            Keycode::X => keycode,
-            Keycode::Y => keycode,
-            Keycode::Z => keycode,
-            // Keycode::Delete => {}
-            // Keycode::CapsLock => {}
-            // Keycode::F1 => {}
-            // Keycode::F2 => {}
-            // Keycode::F3 => {}
-            // Keycode::F4 => {}
-            // Keycode::F5 => {}
-            // Keycode::F6 => {}
-            // Keycode::F7 => {}
-            // Keycode::F8 => {}
-            // Keycode::F9 => {}
-            // Keycode::F10 => {}
-            // Keycode::F11 => {}
-            // Keycode::F12 => {}
-            // Keycode::PrintScreen => {}
-            // Keycode::ScrollLock => {}
-            // Keycode::Pause => {}
-            // Keycode::Insert => {}
-            // Keycode::Home => {}
-            // Keycode::PageUp => {}
-            // Keycode::End => {}
-            // Keycode::PageDown => {}
-            // Keycode::Right => {}
-            // Keycode::Left => {}
-            // Keycode::Down => {}
-            // Keycode::Up => {}
-            // Keycode::NumLockClear => {}
-            // Keycode::KpDivide => {}
-            // Keycode::KpMultiply => {}
-            // Keycode::KpMinus => {}
-            // Keycode::KpPlus => {}
-            // Keycode::KpEnter => {}
-            // Keycode::Kp1 => {}
-            // Keycode::Kp2 => {}
-            // Keycode::Kp3 => {}
-            // Keycode::Kp4 => {}
-            // Keycode::Kp5 => {}
-            // Keycode::Kp6 => {}
-            // Keycode::Kp7 => {}
-            // Keycode::Kp8 => {}
-            // Keycode::Kp9 => {}
-            // Keycode::Kp0 => {}
-            // Keycode::KpPeriod => {}
-            // Keycode::Application => {}
-            // Keycode::Power => {}
-            // Keycode::KpEquals => {}
-            // Keycode::F13 => {}
-            // Keycode::F14 => {}
-            // Keycode::F15 => {}
-            // Keycode::F16 => {}
-            // Keycode::F17 => {}
-            // Keycode::F18 => {}
-            // Keycode::F19 => {}
-            // Keycode::F20 => {}
-            // Keycode::F21 => {}
-            // Keycode::F22 => {}
-            // Keycode::F23 => {}
-            // Keycode::F24 => {}
-            // Keycode::Execute => {}
-            // Keycode::Help => {}
-            // Keycode::Menu => {}
-            // Keycode::Select => {}
-            // Keycode::Stop => {}
-            // Keycode::Again => {}
-            // Keycode::Undo => {}
-            // Keycode::Cut => {}
-            // Keycode::Copy => {}
-            // Keycode::Paste => {}
-            // Keycode::Find => {}
-            // Keycode::Mute => {}
-            // Keycode::VolumeUp => {}
-            // Keycode::VolumeDown => {}
-            // Keycode::KpComma => {}
-            // Keycode::KpEqualsAS400 => {}
-            // Keycode::AltErase => {}
-            // Keycode::Sysreq => {}
-            // Keycode::Cancel => {}
-            // Keycode::Clear => {}
-            // Keycode::Prior => {}
-            // Keycode::Return2 => {}
-            // Keycode::Separator => {}
-            // Keycode::Out => {}
-            // Keycode::Oper => {}
-            // Keycode::ClearAgain => {}
-            // Keycode::CrSel => {}
-            // Keycode::ExSel => {}
-            // Keycode::Kp00 => {}
-            // Keycode::Kp000 => {}
-            // Keycode::ThousandsSeparator => {}
-            // Keycode::DecimalSeparator => {}
-            // Keycode::CurrencyUnit => {}
-            // Keycode::CurrencySubUnit => {}
-            // Keycode::KpLeftParen => {}
-            // Keycode::KpRightParen => {}
-            // Keycode::KpLeftBrace => {}
-            // Keycode::KpRightBrace => {}
-            // Keycode::KpTab => {}
-            // Keycode::KpBackspace => {}
-            // Keycode::KpA => {}
-            // Keycode::KpB => {}
-            // Keycode::KpC => {}
-            // Keycode::KpD => {}
-            // Keycode::KpE => {}
-            // Keycode::KpF => {}
-            // Keycode::KpXor => {}
-            // Keycode::KpPower => {}
-            // Keycode::KpPercent => {}
-            // Keycode::KpLess => {}
-            // Keycode::KpGreater => {}
-            // Keycode::KpAmpersand => {}
-            // Keycode::KpDblAmpersand => {}
-            // Keycode::KpVerticalBar => {}
-            // Keycode::KpDblVerticalBar => {}
-            // Keycode::KpColon => {}
-            // Keycode::KpHash => {}
-            // Keycode::KpSpace => {}
-            // Keycode::KpAt => {}
-            // Keycode::KpExclam => {}
-            // Keycode::KpMemStore => {}
-            // Keycode::KpMemRecall => {}
-            // Keycode::KpMemClear => {}
-            // Keycode::KpMemAdd => {}
-            // Keycode::KpMemSubtract => {}
-            // Keycode::KpMemMultiply => {}
-            // Keycode::KpMemDivide => {}
-            // Keycode::KpPlusMinus => {}
-            // Keycode::KpClear => {}
-            // Keycode::KpClearEntry => {}
-            // Keycode::KpBinary => {}
-            // Keycode::KpOctal => {}
-            // Keycode::KpDecimal => {}
-            // Keycode::KpHexadecimal => {}
-            // Keycode::LCtrl => {}
-            // Keycode::LShift => {}
-            // Keycode::LAlt => {}
-            // Keycode::LGui => {}
-            // Keycode::RCtrl => {}
-            // Keycode::RShift => {}
-            // Keycode::RAlt => {}
-            // Keycode::RGui => {}
-            // Keycode::Mode => {}
-            // Keycode::AudioNext => {}
-            // Keycode::AudioPrev => {}
-            // Keycode::AudioStop => {}
-            // Keycode::AudioPlay => {}
-            // Keycode::AudioMute => {}
-            // Keycode::MediaSelect => {}
-            // Keycode::Www => {}
-            // Keycode::Mail => {}
-            // Keycode::Calculator => {}
-            // Keycode::Computer => {}
-            // Keycode::AcSearch => {}
-            // Keycode::AcHome => {}
-            // Keycode::AcBack => {}
-            // Keycode::AcForward => {}
-            // Keycode::AcStop => {}
-            // Keycode::AcRefresh => {}
-            // Keycode::AcBookmarks => {}
-            // Keycode::BrightnessDown => {}
-            // Keycode::BrightnessUp => {}
-            // Keycode::DisplaySwitch => {}
-            // Keycode::KbdIllumToggle => {}
-            // Keycode::KbdIllumDown => {}
-            // Keycode::KbdIllumUp => {}
-            // Keycode::Eject => {}
-            // Keycode::Sleep => {}
-            _ => return,
+            Err(e) => {
+                let title = format!("Couldn't reload {}, keeping what's open: {}", path.display(), e);
+                eprintln!("{}", title);
+                // a prompt that's already up, like one about quitting, isn't pushed aside for it
+                if self.overlay.is_none() {
+                    self.overlay = Some(Overlay::Notice(Picker::new(&title, Vec::new())));
+                }
+                return;
+            }
+        };
+        self.views.pop();
+        self.views[index] = reloaded;
+        self.backend.notify(&CloseView { view_id });
+        self.state.views.remove(&view_id);
+        self.focused_view = if focused == Some(view_id) { Some(reloaded) } else { focused };
+
+        self.restore_selections(reloaded, &selections);
+        let view = self.state.view_mut(reloaded);
+        view.first_line = first_line;
+        view.folds.set_folded(&folded);
+    }
+
+    // how the file on disk differs from the view, in a list
+    fn show_diff(&mut self, view_id: ViewId) {
+        let view = match self.state.view(&view_id) {
+            Some(view) => view,
+            None => return,
+        };
+        let title = view.title();
+        let on_disk = view.path.as_ref().map(fs::read_to_string);
+
+        let (title, lines) = match (view.lines.text(), on_disk) {
+            (Some(buffer), Some(Ok(on_disk))) => {
+                let buffer: Vec<&str> = buffer.lines().collect();
+                let on_disk: Vec<&str> = on_disk.lines().collect();
+                let lines = line_diff::diff(&buffer, &on_disk).iter().map(|line| line.describe()).collect();
+                (format!("Changes on disk to {}", title), lines)
+            }
+            (None, _) => (format!("{} hasn't all loaded yet, try again in a moment", title), Vec::new()),
+            (_, Some(Err(e))) => (format!("Couldn't read {}: {}", title, e), Vec::new()),
+            (_, None) => return,
+        };
+        self.overlay = Some(Overlay::Notice(Picker::new(&title, lines)));
+        // asked about again once the diff's been looked at
+        self.state.view_mut(view_id).changed_on_disk = true;
+    }
+}
+
+// language-related
+impl Session {
+
+    pub fn set_language(&mut self, view_id: &ViewId, language: &LanguageId) {
+        self.backend.notify(&SetLanguage { view_id: *view_id, language_id: language.clone() });
+    }
+
+    pub fn open_language_picker(&mut self) {
+        let state = &self.state;
+        let mut picker = Picker::new("Language", state.available_languages.clone());
+
+        // start on the language the focused view already has
+        let current = self.focused_view
+            .and_then(|view_id| state.view(&view_id))
+            .and_then(|view| view.language.clone());
+        if let Some(current) = current {
+            if let Some(index) = picker.items().iter().position(|lang| *lang == current) {
+                picker.select(index);
+            }
+        }
+        self.overlay = Some(Overlay::LanguagePicker(picker));
+    }
+}
+
+// settings
+impl Session {
+
+    /// Shows every setting of the focused view, including ones we don't understand, or hides them again.
+    pub fn toggle_settings_panel(&mut self) {
+        if let Some(Overlay::Settings(_)) = self.overlay {
+            self.overlay = None;
+            return;
+        }
+
+        let state = &self.state;
+        let lines = self.focused_view
+            .and_then(|view_id| state.view(&view_id))
+            .map(|view| view.settings.describe())
+            .unwrap_or_default();
+        self.overlay = Some(Overlay::Settings(Picker::new("Settings", lines)));
+    }
+}
+
+// folding
+impl Session {
+
+    /// Folds or unfolds the region starting at buffer line `line`, counting from 0, and keeps
+    /// what's folded for the next time the file's opened.
+    pub fn toggle_fold(&mut self, view_id: ViewId, line: usize) -> bool {
+        let view = self.state.view_mut(view_id);
+        if !view.folds.toggle(line) {
+            return false;
+        }
+
+        if let (Some(path), Some(config_dir)) = (view.path.clone(), self.state.config_dir.clone()) {
+            let folds = self.state.view_mut(view_id).folds.folded();
+            self.saved.files.entry(path).or_default().folds = folds;
+            self.saved.save(&config_dir);
+        }
+        true
+    }
+
+    /// Folds or unfolds the innermost region around the cursor.
+    pub fn toggle_fold_at_cursor(&mut self) -> bool {
+        let view_id = match self.focused_view {
+            Some(v) => v,
+            None => return false,
         };
+        let region = self.state.view(&view_id)
+            .and_then(|view| view.lines.cursor_lines().first().and_then(|line| view.folds.innermost(*line)));
+        match region {
+            Some(region) => self.toggle_fold(view_id, region.start),
+            None => false,
+        }
+    }
+}
+
+// diagnostics
+impl Session {
+
+    /// Lists the focused view's diagnostics, or hides the list again. Picking one goes to its line.
+    pub fn toggle_diagnostics_panel(&mut self) {
+        if let Some(Overlay::Diagnostics(_)) = self.overlay {
+            self.overlay = None;
+            return;
+        }
+
+        let state = &self.state;
+        let diagnostics = self.focused_view
+            .and_then(|view_id| state.view(&view_id))
+            .map(|view| view.lines.diagnostics())
+            .unwrap_or_default();
+        self.overlay = Some(Overlay::Diagnostics(Picker::new("Diagnostics", diagnostics)));
+    }
+
+    /// Moves the cursor to the start of buffer line `line`, counting from 0. xi-core scrolls it into view.
+    pub fn goto_line(&mut self, view_id: ViewId, line: usize) {
+        self.backend.edit(view_id, EditCommand::GotoLine { line: line as u64 });
+    }
+}
+
+// overlays
+impl Session {
+
+    pub fn overlay(&self) -> Option<&Overlay> {
+        self.overlay.as_ref()
+    }
+
+    /// Types text into the open overlay, when it takes text. Returns false if it doesn't.
+    pub fn handle_overlay_text(&mut self, text: &str) -> bool {
+        self.overlay.as_mut().map(|overlay| overlay.type_text(text)).unwrap_or(false)
+    }
+
+    /// Handles a key while an overlay is open. Returns false if there is no overlay.
+    pub fn handle_overlay_key(&mut self, keycode: Keycode) -> bool {
+        let overlay = match self.overlay.as_mut() {
+            Some(o) => o,
+            None => return false,
+        };
+
+        match keycode {
+            Keycode::Up => overlay.select_previous(),
+            Keycode::Down => overlay.select_next(),
+            Keycode::Backspace => overlay.backspace(),
+            Keycode::Escape => self.overlay = None,
+            Keycode::Return => {
+                let overlay = self.overlay.take();
+                match (overlay, self.focused_view) {
+                    (Some(Overlay::LanguagePicker(picker)), Some(view_id)) => {
+                        if let Some(language) = picker.selected() {
+                            self.set_language(&view_id, language);
+                        }
+                    }
+                    (Some(Overlay::Diagnostics(picker)), Some(view_id)) => {
+                        if let Some(diagnostic) = picker.selected() {
+                            self.goto_line(view_id, diagnostic.range[0]);
+                        }
+                    }
+                    (Some(Overlay::FileFinder(finder)), _) => {
+                        if let Some(path) = finder.selected() {
+                            self.open_file(path);
+                        }
+                    }
+                    (Some(Overlay::SaveAs { view_id, path }), _) => {
+                        if !path.trim().is_empty() {
+                            self.confirm_save_as(view_id, PathBuf::from(path.trim()));
+                        }
+                    }
+                    (Some(Overlay::ConfirmOverwrite { view_id, path, choices }), _) => {
+                        if choices.selected().map(String::as_str) == Some(OVERWRITE) {
+                            self.save_as(view_id, path);
+                        }
+                    }
+                    (Some(Overlay::ConfirmQuit { view_id, choices }), _) => {
+                        match choices.selected().map(String::as_str) {
+                            // one that's never been saved has to be asked where to first
+                            Some(SAVE) => match self.state.view(&view_id).and_then(|view| view.path.clone()) {
+                                Some(path) => self.save_as(view_id, path),
+                                None => self.open_save_as(),
+                            },
+                            Some(DISCARD) => self.next_quit_prompt(),
+                            _ => {}
+                        }
+                    }
+                    (Some(Overlay::FileConflict { view_id, choices }), _) => {
+                        match choices.selected().map(String::as_str) {
+                            Some(RELOAD) => self.reload(view_id),
+                            // what's on disk now is what a save will overwrite
+                            Some(KEEP) => {
+                                let view = self.state.view_mut(view_id);
+                                view.on_disk = view.path.as_ref().and_then(|path| DiskState::of(path));
+                            }
+                            Some(DIFF) => self.show_diff(view_id),
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        // quitting goes from one prompt to the next, so with none open it's been called off,
+        // and changed files waiting on a prompt can have theirs
+        if self.overlay.is_none() {
+            self.quitting = None;
+            self.handle_changed_files();
+        }
+        true
+    }
+}
 
-        //todo tell backend with viewId there was an insert
-        println!("{}", insertable_key);
+// editing
+impl Session {
+
+    /// Types `text` into `view_id` at every selection, as the keyboard gave it to us.
+    pub fn insert(&mut self, view_id: ViewId, text: &str) {
+        self.backend.edit(view_id, EditCommand::Insert { chars: text.to_string() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::mpsc::{ channel, Sender };
+
+    use serde_json::{ json, Value };
+
+    use super::*;
+
+    // stands in for xi-core, answering just enough for a view to be edited and saved
+    struct FakeCore {
+        frontend: Sender<FrontendMessage>,
+    }
+
+    impl FakeCore {
+        fn update(&self, params: &Value, pristine: bool) {
+            let view_id = serde_json::from_value(params["view_id"].clone()).unwrap();
+            let update = serde_json::from_value(json!({ "ops": [], "pristine": pristine })).unwrap();
+            self.frontend.send(FrontendMessage::Update { view_id, update }).unwrap();
+        }
     }
-}
\ No newline at end of file
+
+    impl Transport for FakeCore {
+        fn send_notification(&mut self, method: &str, params: Value) {
+            match method {
+                "edit" => self.update(&params, false),
+                // xi-core logs a save that fails, without telling the frontend
+                "save" if fs::write(params["file_path"].as_str().unwrap(), "").is_ok() => self.update(&params, true),
+                _ => {}
+            }
+        }
+
+        fn send_request(&mut self, method: &str, _params: Value) -> Result<Value, RpcError> {
+            match method {
+                "new_view" => Ok(json!("view-id-1")),
+                _ => Ok(json!({})),
+            }
+        }
+
+        fn handle_idle(&mut self, _token: usize) {}
+    }
+
+    fn session() -> Session {
+        let (frontend, messages) = channel();
+        Session::new(Box::new(FakeCore { frontend }), messages, None)
+    }
+
+    #[test]
+    fn typing_leaves_a_view_to_be_asked_about_when_quitting() {
+        let mut session = session();
+        let view_id = session.add_new_view(None).unwrap();
+        session.insert(view_id, "a");
+        session.apply_frontend_messages();
+        assert!(session.state().view(&view_id).unwrap().dirty);
+
+        session.request_quit();
+        match session.overlay() {
+            Some(Overlay::ConfirmQuit { view_id: asked, .. }) => assert_eq!(*asked, view_id),
+            _ => panic!("quitting didn't ask about the edited view"),
+        }
+        assert!(!session.should_quit());
+    }
+
+    #[test]
+    fn a_save_that_writes_nothing_is_reported_even_for_a_clean_view() {
+        let mut session = session();
+        let view_id = session.add_new_view(None).unwrap();
+        let path = env::temp_dir().join("xi-path-no-such-dir").join("unsaved.txt");
+        session.save_as(view_id, path);
+        assert!(matches!(session.overlay(), Some(Overlay::Notice(_))));
+        assert_eq!(session.state().view(&view_id).unwrap().path, None);
+
+        let path = env::temp_dir().join(format!("xi-path-saved-{}.txt", std::process::id()));
+        session.overlay = None;
+        session.save_as(view_id, path.clone());
+        assert!(session.overlay().is_none());
+        assert_eq!(session.state().view(&view_id).unwrap().path.as_ref(), Some(&path));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use std::io::{ self, BufRead, BufReader, Write };
+use std::path::PathBuf;
+use std::process::{ Child, Command, Stdio };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+
+use serde_json::{ json, Value };
+use xi_core_lib::XiCore;
+use xi_core_lib::client::Frontend;
+use xi_core_lib::rpc::{ CoreNotification, CoreRequest };
+use xi_core_lib::width_cache::WidthReq;
+use xi_path_backend::{ RpcError, Transport };
+use xi_path_backend::pending::PendingRequests;
+use xi_path_backend::rpc::{ ClientStarted, Notification };
+
+use frontend::frontend::HandleNotification;
+use frontend::trace::{ Direction, Trace };
+
+/// xi-core running in-process, on our own thread.
+pub struct DirectTransport {
+    core: XiCore,
+}
+
+impl DirectTransport {
+    pub fn new<F>(frontend: F, config_dir: Option<PathBuf>, extras_dir: Option<PathBuf>) -> Self
+        where F: Frontend + Send + Sync + 'static
+    {
+        DirectTransport {
+            core: XiCore::new_direct(frontend, config_dir, extras_dir),
+        }
+    }
+}
+
+impl Transport for DirectTransport {
+    // xi-core takes its notifications as json-rpc, so build the message the same way a remote client would
+    fn send_notification(&mut self, method: &str, params: Value) {
+        let message = json!({ "method": method, "params": params });
+        match serde_json::from_value::<CoreNotification>(message) {
+            Ok(notification) => self.core.handle_notification(notification),
+            Err(e) => panic!("malformed {} notification: {}", method, e),
+        }
+    }
+
+    fn send_request(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let message = json!({ "method": method, "params": params });
+        match serde_json::from_value::<CoreRequest>(message) {
+            Ok(request) => self.core.handle_request(request).map_err(|e| RpcError::from_json(json!(e))),
+            Err(e) => panic!("malformed {} request: {}", method, e),
+        }
+    }
+
+    fn handle_idle(&mut self, token: usize) {
+        self.core.handle_idle(token);
+    }
+}
+
+type Pending = Arc<Mutex<PendingRequests>>;
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// xi-core in another process, speaking the standard xi json-rpc protocol: one json object per
+/// line, us writing to its stdin and reading from its stdout.
+///
+/// Everything xi-core sends is read on a background thread. Responses are handed back to the
+/// request waiting on them, notifications go to the frontend just like the in-process callbacks.
+pub struct StdioTransport {
+    writer: SharedWriter,
+    pending: Pending,
+    child: Option<Child>,
+}
+
+impl StdioTransport {
+    /// Starts the xi-core binary at `core_path` and tells it where our config lives.
+    pub fn spawn<F>(core_path: &PathBuf,
+                    frontend: F,
+                    config_dir: Option<PathBuf>,
+                    extras_dir: Option<PathBuf>) -> io::Result<Self>
+        where F: Frontend + HandleNotification + Send + 'static
+    {
+        let mut child = Command::new(core_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut transport = StdioTransport::new(stdin, BufReader::new(stdout), frontend);
+        transport.child = Some(child);
+
+        let started = ClientStarted { config_dir, client_extras_dir: extras_dir };
+        transport.send_notification(ClientStarted::METHOD, serde_json::to_value(started).unwrap());
+        Ok(transport)
+    }
+
+    /// Talks to whatever is on the other end of `writer`/`reader`, a real xi-core or a scripted fake.
+    pub fn new<W, R, F>(writer: W, reader: R, frontend: F) -> Self
+        where W: Write + Send + 'static,
+              R: BufRead + Send + 'static,
+              F: Frontend + HandleNotification + Send + 'static
+    {
+        let writer: SharedWriter = Arc::new(Mutex::new(Box::new(writer)));
+        let pending: Pending = Arc::new(Mutex::new(PendingRequests::new()));
+
+        let reader_writer = writer.clone();
+        let reader_pending = pending.clone();
+        thread::spawn(move || read_messages(reader, reader_writer, reader_pending, frontend));
+
+        StdioTransport {
+            writer,
+            pending,
+            child: None,
+        }
+    }
+}
+
+impl Transport for StdioTransport {
+    fn send_notification(&mut self, method: &str, params: Value) {
+        write_message(&self.writer, &json!({ "method": method, "params": params }));
+    }
+
+    fn send_request(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let (id, receiver) = self.pending.lock().unwrap().register();
+        write_message(&self.writer, &json!({ "id": id, "method": method, "params": params }));
+
+        match receiver.recv() {
+            Ok(result) => result,
+            Err(_) => Err(RpcError::disconnected()),
+        }
+    }
+
+    // an out-of-process core schedules and runs its own idle work
+    fn handle_idle(&mut self, _token: usize) {}
+}
+
+impl Drop for StdioTransport {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn write_message(writer: &SharedWriter, message: &Value) {
+    let mut writer = writer.lock().unwrap();
+    let result = writeln!(writer, "{}", message).and_then(|_| writer.flush());
+    if let Err(e) = result {
+        eprintln!("couldn't write to xi-core: {}", e);
+    }
+}
+
+fn read_messages<R: BufRead, F: Frontend + HandleNotification>(reader: R, writer: SharedWriter, pending: Pending, frontend: F) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("couldn't read from xi-core: {}", e);
+                break;
+            }
+        };
+
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("ignoring malformed message from xi-core: {}", e);
+                continue;
+            }
+        };
+
+        let id = message.get("id").and_then(Value::as_u64);
+        let method = message.get("method").and_then(Value::as_str);
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        match (id, method) {
+            // a response to one of our requests
+            (Some(id), None) => {
+                let result = match message.get("error") {
+                    Some(error) => Err(RpcError::from_json(error.clone())),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                if !pending.lock().unwrap().resolve(id, result) {
+                    eprintln!("ignoring response to unknown request {}", id);
+                }
+            }
+            // a request from xi-core, of which only measuring text for wrapping is answered yet
+            (Some(id), Some("measure_width")) => {
+                let reply = match serde_json::from_value::<Vec<WidthReq>>(params) {
+                    Ok(reqs) => json!({ "id": id, "result": frontend.measure_width(&reqs) }),
+                    Err(e) => json!({ "id": id, "error": { "code": -32602, "message": format!("invalid params: {}", e) } }),
+                };
+                write_message(&writer, &reply);
+            }
+            (Some(id), Some(method)) => {
+                write_message(&writer, &json!({
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("unsupported method {}", method) },
+                }));
+            }
+            (None, Some(method)) => frontend.handle_notification(method, &params),
+            (None, None) => eprintln!("ignoring message from xi-core with no id or method"),
+        }
+    }
+
+    // the core has gone, wake anything still waiting on a response so it can fail
+    pending.lock().unwrap().disconnect();
+}
+
+/// Wraps another transport, recording everything we send xi-core to a trace.
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    trace: Trace,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Box<dyn Transport>, trace: Trace) -> Self {
+        RecordingTransport { inner, trace }
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send_notification(&mut self, method: &str, params: Value) {
+        self.trace.record(Direction::ToCore, method, params.clone(), None);
+        self.inner.send_notification(method, params);
+    }
+
+    fn send_request(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let result = self.inner.send_request(method, params.clone());
+        let response = match &result {
+            Ok(value) => json!({ "result": value }),
+            Err(error) => json!({ "error": error }),
+        };
+        self.trace.record(Direction::ToCore, method, params, Some(response));
+        result
+    }
+
+    fn handle_idle(&mut self, token: usize) {
+        self.inner.handle_idle(token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frontend::frontend::XiPathFrontend;
+    use std::io::Read;
+    use std::sync::mpsc::{ channel, Receiver, Sender };
+    use frontend::message::FrontendMessage;
+
+    // the fake core's stdout: blocks until the script has something to say, like a real pipe
+    struct ScriptOutput {
+        lines: Receiver<String>,
+        buffer: io::Cursor<Vec<u8>>,
+    }
+
+    impl Read for ScriptOutput {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.buffer.position() as usize == self.buffer.get_ref().len() {
+                match self.lines.recv() {
+                    Ok(line) => self.buffer = io::Cursor::new(format!("{}\n", line).into_bytes()),
+                    Err(_) => return Ok(0),
+                }
+            }
+            self.buffer.read(buf)
+        }
+    }
+
+    // the fake core's stdin
+    struct ScriptInput(Sender<Vec<u8>>);
+
+    impl Write for ScriptInput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.0.send(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A stand-in for xi-core that answers each message it's sent with whatever `script` returns.
+    /// Every message the fake receives is also passed on to the returned receiver.
+    fn fake_core<F>(script: F) -> (ScriptInput, BufReader<ScriptOutput>, Receiver<Value>)
+        where F: Fn(&Value) -> Vec<Value> + Send + 'static
+    {
+        let (input_sender, input) = channel::<Vec<u8>>();
+        let (output_sender, output) = channel();
+        let (seen_sender, seen) = channel();
+
+        thread::spawn(move || {
+            let mut pending = Vec::new();
+            for bytes in input {
+                pending.extend(bytes);
+                while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    let message: Value = serde_json::from_slice(&line).unwrap();
+                    for reply in script(&message) {
+                        let _ = output_sender.send(reply.to_string());
+                    }
+                    let _ = seen_sender.send(message);
+                }
+            }
+        });
+
+        let reader = BufReader::new(ScriptOutput { lines: output, buffer: io::Cursor::new(Vec::new()) });
+        (ScriptInput(input_sender), reader, seen)
+    }
+
+    #[test]
+    fn request_gets_the_scripted_response() {
+        let (input, output, seen) = fake_core(|message| match message["method"].as_str() {
+            Some("new_view") => vec![json!({ "id": message["id"], "result": "view-id-1" })],
+            _ => vec![],
+        });
+        let (frontend, _messages) = XiPathFrontend::new();
+        let mut transport = StdioTransport::new(input, output, frontend);
+
+        let result = transport.send_request("new_view", json!({}));
+        assert_eq!(result, Ok(json!("view-id-1")));
+        assert_eq!(seen.recv().unwrap(), json!({ "id": 0, "method": "new_view", "params": {} }));
+    }
+
+    #[test]
+    fn error_responses_are_returned_as_errors() {
+        let (input, output, _seen) = fake_core(|message| {
+            vec![json!({ "id": message["id"], "error": { "code": 1, "message": "no such file" } })]
+        });
+        let (frontend, _messages) = XiPathFrontend::new();
+        let mut transport = StdioTransport::new(input, output, frontend);
+
+        let result = transport.send_request("new_view", json!({ "file_path": "/missing" }));
+        assert_eq!(result, Err(RpcError::new(1, "no such file")));
+    }
+
+    #[test]
+    fn measure_width_requests_are_answered() {
+        let (input, output, seen) = fake_core(|message| match message["method"].as_str() {
+            Some("client_started") => vec![json!({
+                "id": 7,
+                "method": "measure_width",
+                "params": [{ "id": 0, "strings": ["abc", "de"] }],
+            })],
+            _ => vec![],
+        });
+        let (frontend, _messages) = XiPathFrontend::new();
+        let mut transport = StdioTransport::new(input, output, frontend);
+        transport.send_notification("client_started", json!({}));
+
+        // with nothing laid out yet, widths are counted in columns
+        assert_eq!(seen.recv().unwrap()["method"], json!("client_started"));
+        assert_eq!(seen.recv().unwrap(), json!({ "id": 7, "result": [[3.0, 2.0]] }));
+    }
+
+    #[test]
+    fn notifications_reach_the_frontend() {
+        let (input, output, _seen) = fake_core(|_| {
+            vec![json!({ "method": "available_languages", "params": { "languages": ["Rust"] } })]
+        });
+        let (frontend, messages) = XiPathFrontend::new();
+        let mut transport = StdioTransport::new(input, output, frontend);
+        transport.send_notification("client_started", json!({}));
+
+        match messages.recv().unwrap() {
+            FrontendMessage::AvailableLanguages(languages) => assert_eq!(languages.len(), 1),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+}